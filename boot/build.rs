@@ -0,0 +1,25 @@
+//! Stops the build without a firmware signing key, see `image::PUBLIC_KEY`.
+//! There is no default key, make one with `firmware keygen`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+fn main() {
+    let path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("keys/firmware.pub");
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let key = fs::read(&path).unwrap_or_else(|e| {
+        eprintln!(
+            "{}: {}, make a signing key with `cargo run --bin firmware -- keygen <dir>` and copy firmware.pub here",
+            path.display(),
+            e
+        );
+        process::exit(1);
+    });
+    if key.len() != 32 {
+        eprintln!("{}: an Ed25519 public key is 32 bytes, not {}", path.display(), key.len());
+        process::exit(1);
+    }
+}
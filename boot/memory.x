@@ -0,0 +1,11 @@
+MEMORY
+{
+  /* NOTE K = KiBi = 1024 bytes */
+  /* The first 32K of flash, the firmware slots follow, see src/layout.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 32K
+  RAM : ORIGIN = 0x20000000, LENGTH = 20K - 256
+  /* Left alone so the crash record of the firmware survives */
+  CRASH : ORIGIN = 0x20004F00, LENGTH = 256
+}
+
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
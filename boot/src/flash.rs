@@ -0,0 +1,79 @@
+//! Erasing and programming the flash and the EEPROM a word at a time.
+//! Erased flash reads as zero.
+
+use stm32l0xx_hal::pac::FLASH;
+
+use crate::layout::PAGE_SIZE;
+
+const PEKEY1: u32 = 0x89AB_CDEF;
+const PEKEY2: u32 = 0x0203_0405;
+const PRGKEY1: u32 = 0x8C9D_AEBF;
+const PRGKEY2: u32 = 0x1314_1516;
+
+pub fn read(addr: u32) -> u32 {
+    unsafe { core::ptr::read_volatile(addr as *const u32) }
+}
+
+fn unlock(flash: &FLASH) {
+    if flash.pecr.read().pelock().bit_is_set() {
+        flash.pekeyr.write(|w| unsafe { w.bits(PEKEY1) });
+        flash.pekeyr.write(|w| unsafe { w.bits(PEKEY2) });
+    }
+    if flash.pecr.read().prglock().bit_is_set() {
+        flash.prgkeyr.write(|w| unsafe { w.bits(PRGKEY1) });
+        flash.prgkeyr.write(|w| unsafe { w.bits(PRGKEY2) });
+    }
+}
+
+// Locking the EEPROM locks the program memory as well
+fn lock(flash: &FLASH) {
+    flash.pecr.modify(|_, w| w.pelock().set_bit());
+}
+
+// Waits for the current operation and checks the error flags
+fn wait(flash: &FLASH) -> Result<(), ()> {
+    while flash.sr.read().bsy().bit_is_set() {}
+
+    let sr = flash.sr.read();
+    if sr.wrperr().bit_is_set() || sr.sizerr().bit_is_set() || sr.pgaerr().bit_is_set() {
+        // Error flags are cleared by writing 1
+        flash.sr.write(|w| unsafe { w.bits(sr.bits()) });
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Erases the page `addr` is in
+pub fn erase_page(flash: &FLASH, addr: u32) -> Result<(), ()> {
+    unlock(flash);
+    flash.pecr.modify(|_, w| w.erase().set_bit().prog().set_bit());
+
+    let page = addr & !(PAGE_SIZE - 1);
+    unsafe { core::ptr::write_volatile(page as *mut u32, 0) };
+    let result = wait(flash);
+
+    flash.pecr.modify(|_, w| w.erase().clear_bit().prog().clear_bit());
+    lock(flash);
+    result
+}
+
+/// Writes words to flash or EEPROM at `addr`, which is word aligned.
+/// Flash has to be erased first, unchanged words are skipped.
+pub fn write(flash: &FLASH, addr: u32, words: &[u32]) -> Result<(), ()> {
+    unlock(flash);
+    let mut result = Ok(());
+
+    for (i, word) in words.iter().enumerate() {
+        let addr = addr + 4 * i as u32;
+        if read(addr) != *word {
+            unsafe { core::ptr::write_volatile(addr as *mut u32, *word) };
+            if wait(flash).is_err() {
+                result = Err(());
+                break;
+            }
+        }
+    }
+
+    lock(flash);
+    result
+}
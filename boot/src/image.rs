@@ -0,0 +1,69 @@
+//! Checks of firmware images in the slots
+
+use core::convert::TryFrom;
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use protocol::update::signed_message;
+use sha2::{Digest, Sha256};
+
+use crate::flash;
+use crate::layout::SLOT_SIZE;
+
+/// Key the firmware is signed with, from `firmware keygen`. build.rs stops
+/// the build if it is missing.
+pub const PUBLIC_KEY: &[u8; 32] = include_bytes!("../keys/firmware.pub");
+
+const RAM_START: u32 = 0x2000_0000;
+const RAM_END: u32 = 0x2000_5000;
+
+/// What is known about an image before it has been received
+#[derive(Clone, Copy)]
+pub struct Manifest {
+    pub version: u32,
+    pub size: u32,
+    pub signature: [u8; 64],
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest {
+            version: 0,
+            size: 0,
+            signature: [0; 64],
+        }
+    }
+}
+
+/// The first `size` bytes of the slot at `addr`
+pub fn slot(addr: u32, size: u32) -> &'static [u8] {
+    let size = size.min(SLOT_SIZE) as usize;
+    unsafe { core::slice::from_raw_parts(addr as *const u8, size) }
+}
+
+/// True if `image` is the one in the manifest, signed with `PUBLIC_KEY`
+pub fn verify(manifest: &Manifest, image: &[u8]) -> bool {
+    if manifest.size == 0 || manifest.size > SLOT_SIZE || image.len() != manifest.size as usize {
+        return false;
+    }
+
+    let mut digest = [0; 32];
+    digest.copy_from_slice(&Sha256::digest(image));
+    let message = signed_message(manifest.version, manifest.size, &digest);
+
+    let key = match PublicKey::from_bytes(PUBLIC_KEY) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    match Signature::try_from(&manifest.signature[..]) {
+        Ok(signature) => key.verify(&message, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// True if the slot at `addr` starts with a vector table of this firmware:
+/// the stack in RAM and the reset handler in the slot
+pub fn bootable(addr: u32) -> bool {
+    let stack = flash::read(addr);
+    let reset = flash::read(addr + 4);
+    stack > RAM_START && stack <= RAM_END && reset > addr && reset < addr + SLOT_SIZE
+}
@@ -0,0 +1,23 @@
+//! Where everything is in the 192K flash, and the EEPROM used by updates
+
+pub const FLASH_START: u32 = 0x0800_0000;
+/// Erase unit of the flash
+pub const PAGE_SIZE: u32 = 128;
+
+/// The bootloader itself, see boot/memory.x
+pub const BOOTLOADER_SIZE: u32 = 32 * 1024;
+pub const SLOT_SIZE: u32 = 80 * 1024;
+/// The running firmware, which is linked here, see memory.x
+pub const SLOT_A: u32 = FLASH_START + BOOTLOADER_SIZE;
+/// Updates are received here
+pub const SLOT_B: u32 = SLOT_A + SLOT_SIZE;
+
+pub const EEPROM_START: u32 = 0x0808_0000;
+
+// EEPROM offsets, after the ones of the firmware in src/nvm.rs
+pub const OTA_STATE: u32 = 0xC40;
+/// Copy of the page being swapped, so a swap survives a power loss
+pub const OTA_SCRATCH: u32 = 0xD00;
+/// Bit per received fragment
+pub const OTA_BITMAP: u32 = 0xD80;
+pub const OTA_BITMAP_SIZE: u32 = 0x180;
@@ -0,0 +1,14 @@
+//! What the bootloader and the firmware share: the flash layout, the flash
+//! driver, the update state in EEPROM and the image check.
+//!
+//! Slot A holds the running firmware and slot B receives updates. The
+//! bootloader installs a verified update by swapping the slots, so the old
+//! firmware stays in slot B and can be swapped back if the new one doesn't
+//! confirm itself.
+
+#![no_std]
+
+pub mod flash;
+pub mod image;
+pub mod layout;
+pub mod state;
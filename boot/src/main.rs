@@ -0,0 +1,312 @@
+//! Bootloader, installs verified updates from slot B that are newer than the
+//! installed firmware and rolls them back if the new firmware doesn't
+//! confirm itself, then starts the firmware in slot A. See the update module of the protocol crate for the transfer.
+//!
+//! Updates can also be pushed over the serial port with `firmware flash`,
+//! see the boot module of the protocol crate.
+
+#![no_main]
+#![no_std]
+
+mod serial;
+
+use core::panic::PanicInfo;
+
+use cortex_m_rt::entry;
+use stm32l0xx_hal::pac::{self, FLASH};
+
+use boot::flash;
+use boot::image::{self, Manifest};
+use boot::layout::{EEPROM_START, OTA_SCRATCH, PAGE_SIZE, SLOT_A, SLOT_B, SLOT_SIZE};
+use boot::state::{self, State};
+use protocol::boot::{BootError, BootRequest, BootResponse};
+use protocol::update::{self, Status, FRAGMENT_SIZE};
+
+use crate::serial::Serial;
+
+/// Resets new firmware gets to confirm itself before it is rolled back
+const MAX_ATTEMPTS: u8 = 3;
+
+const PAGE_WORDS: usize = (PAGE_SIZE / 4) as usize;
+
+// Steps of swapping a page, in `State::progress` below the page number
+const STEP_COPY: u32 = 0;
+const STEP_A: u32 = 1;
+const STEP_B: u32 = 2;
+
+/// How long `Hello` is waited for after reset
+const HELLO_WINDOW_MS: u32 = 1000;
+// Polls of the serial port per ms, about 10 µs apart at 2 MHz
+const POLLS_PER_MS: u32 = 100;
+const POLL_CYCLES: u32 = 20;
+
+const MAX_FRAME: usize = 128;
+
+#[entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let flash = &dp.FLASH;
+    let mut state = State::load();
+
+    match state.status {
+        Status::Pending => {
+            let image = image::slot(SLOT_B, state.manifest.size);
+            if state.is_newer(state.manifest.version) && image::verify(&state.manifest, image) {
+                state.status = Status::Installing;
+                state.progress = 0;
+                save(flash, &state);
+                install(flash, &mut state);
+            } else {
+                state.status = Status::Rejected;
+                save(flash, &state);
+            }
+        }
+        // Power was lost while swapping
+        Status::Installing => install(flash, &mut state),
+        Status::Reverting => revert(flash, &mut state),
+        Status::Testing => {
+            state.attempts += 1;
+            if state.attempts > MAX_ATTEMPTS {
+                state.status = Status::Reverting;
+                state.progress = 0;
+                save(flash, &state);
+                revert(flash, &mut state);
+            } else {
+                save(flash, &state);
+            }
+        }
+        _ => {}
+    }
+
+    // Waits for `firmware flash`, for good if there is nothing to start
+    let mut serial = Serial::new(dp.USART1, &dp.RCC, &dp.GPIOA);
+    let bootable = image::bootable(SLOT_A);
+    if wait_for_hello(&mut serial, bootable) {
+        state.status = Status::Installing;
+        state.progress = 0;
+        state.manifest = receive(&mut serial, flash, &state);
+        save(flash, &state);
+        install(flash, &mut state);
+    }
+    serial.release(&dp.RCC, &dp.GPIOA);
+
+    unsafe { jump(SLOT_A) }
+}
+
+// Collects a zero delimited frame, None until it is complete
+fn read_frame(
+    serial: &mut Serial,
+    buf: &mut [u8; MAX_FRAME],
+    len: &mut usize,
+) -> Option<Result<BootRequest, protocol::Error>> {
+    match serial.read()? {
+        0 if *len == 0 => None,
+        0 => {
+            let request = protocol::decode(&mut buf[..*len]);
+            *len = 0;
+            Some(request)
+        }
+        byte => {
+            if *len < MAX_FRAME {
+                buf[*len] = byte;
+                *len += 1;
+            }
+            None
+        }
+    }
+}
+
+fn respond(serial: &mut Serial, response: &BootResponse) {
+    let mut buf = [0; MAX_FRAME];
+    if let Ok(encoded) = protocol::encode(response, &mut buf) {
+        serial.write(&[0]);
+        serial.write(encoded);
+    }
+}
+
+// True once `Hello` is received, only waits `HELLO_WINDOW_MS` if `timeout`
+fn wait_for_hello(serial: &mut Serial, timeout: bool) -> bool {
+    let mut buf = [0; MAX_FRAME];
+    let mut len = 0;
+    let mut polls = 0;
+
+    while !timeout || polls < HELLO_WINDOW_MS * POLLS_PER_MS {
+        if let Some(Ok(BootRequest::Hello)) = read_frame(serial, &mut buf, &mut len) {
+            respond(serial, &BootResponse::Ready { slot_size: SLOT_SIZE });
+            return true;
+        }
+        cortex_m::asm::delay(POLL_CYCLES);
+        polls += 1;
+    }
+    false
+}
+
+// Receives an image into slot B, in order, and returns its manifest once the
+// signature has been checked. There is no timeout, the host can always
+// reset the device to start the old firmware.
+fn receive(serial: &mut Serial, flash: &FLASH, state: &State) -> Manifest {
+    let mut buf = [0; MAX_FRAME];
+    let mut len = 0;
+    let mut manifest = None;
+    let mut next: u16 = 0;
+
+    loop {
+        let request = match read_frame(serial, &mut buf, &mut len) {
+            Some(Ok(request)) => request,
+            Some(Err(_)) => {
+                respond(serial, &BootResponse::Error(BootError::BadRequest));
+                continue;
+            }
+            None => continue,
+        };
+
+        let response = match request {
+            BootRequest::Hello => BootResponse::Ready { slot_size: SLOT_SIZE },
+            BootRequest::Start { size, .. } if size == 0 || size > SLOT_SIZE => {
+                BootResponse::Error(BootError::TooLarge)
+            }
+            BootRequest::Start { version, .. } if !state.is_newer(version) => {
+                BootResponse::Error(BootError::Outdated)
+            }
+            BootRequest::Start { version, size, signature } => {
+                let mut manifest_signature = [0; 64];
+                manifest_signature[..32].copy_from_slice(&signature.0);
+                manifest_signature[32..].copy_from_slice(&signature.1);
+                manifest = Some(Manifest {
+                    version: version,
+                    size: size,
+                    signature: manifest_signature,
+                });
+                next = 0;
+                BootResponse::Done
+            }
+            BootRequest::Fragment(frame) => match (manifest, update::decode_fragment(&frame)) {
+                (Some(_), Ok((index, _))) if index < next => BootResponse::Done,
+                (Some(manifest), Ok((index, data)))
+                    if index == next && index < update::fragment_count(manifest.size) =>
+                {
+                    if write_fragment(flash, index, data).is_ok() {
+                        next += 1;
+                        BootResponse::Done
+                    } else {
+                        BootResponse::Error(BootError::Flash)
+                    }
+                }
+                _ => BootResponse::Error(BootError::BadFragment),
+            },
+            BootRequest::Install => match manifest {
+                Some(manifest) if next < update::fragment_count(manifest.size) => {
+                    BootResponse::Error(BootError::Incomplete)
+                }
+                Some(manifest) if image::verify(&manifest, image::slot(SLOT_B, manifest.size)) => {
+                    respond(serial, &BootResponse::Done);
+                    return manifest;
+                }
+                Some(_) => BootResponse::Error(BootError::BadSignature),
+                None => BootResponse::Error(BootError::Incomplete),
+            },
+        };
+        respond(serial, &response);
+    }
+}
+
+fn write_fragment(flash: &FLASH, index: u16, data: &[u8]) -> Result<(), ()> {
+    let addr = SLOT_B + index as u32 * FRAGMENT_SIZE as u32;
+    if addr % PAGE_SIZE == 0 {
+        flash::erase_page(flash, addr)?;
+    }
+
+    let mut words = [0; FRAGMENT_SIZE / 4];
+    for (word, bytes) in words.iter_mut().zip(data.chunks(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    flash::write(flash, addr, &words)
+}
+
+fn install(flash: &FLASH, state: &mut State) {
+    swap(flash, state);
+    state.status = Status::Testing;
+    state.attempts = 0;
+    save(flash, state);
+}
+
+fn revert(flash: &FLASH, state: &mut State) {
+    swap(flash, state);
+    state.status = Status::RolledBack;
+    save(flash, state);
+}
+
+fn save(flash: &FLASH, state: &State) {
+    flash::write(flash, state::ADDRESS, &state.to_words()).ok();
+}
+
+fn read_page(addr: u32) -> [u32; PAGE_WORDS] {
+    let mut page = [0; PAGE_WORDS];
+    for (i, word) in page.iter_mut().enumerate() {
+        *word = flash::read(addr + 4 * i as u32);
+    }
+    page
+}
+
+fn write_page(flash: &FLASH, addr: u32, page: &[u32; PAGE_WORDS]) {
+    flash::erase_page(flash, addr).ok();
+    flash::write(flash, addr, page).ok();
+}
+
+// Swaps slot A and B a page at a time through the EEPROM scratch page. The
+// progress is saved after every step, so the swap can be picked up again
+// after a power loss without losing either image.
+fn swap(flash: &FLASH, state: &mut State) {
+    let scratch = EEPROM_START + OTA_SCRATCH;
+    let pages = SLOT_SIZE / PAGE_SIZE;
+
+    let mut page = state.progress >> 8;
+    let mut step = state.progress & 0xFF;
+
+    while page < pages {
+        let a = SLOT_A + page * PAGE_SIZE;
+        let b = SLOT_B + page * PAGE_SIZE;
+
+        match step {
+            STEP_COPY => {
+                let page_a = read_page(a);
+                // Most of both slots is usually erased
+                if page_a == read_page(b) {
+                    page += 1;
+                    continue;
+                }
+                flash::write(flash, scratch, &page_a).ok();
+                step = STEP_A;
+            }
+            STEP_A => {
+                write_page(flash, a, &read_page(b));
+                step = STEP_B;
+            }
+            _ => {
+                write_page(flash, b, &read_page(scratch));
+                page += 1;
+                step = STEP_COPY;
+            }
+        }
+
+        state.progress = page << 8 | step;
+        save(flash, state);
+    }
+
+    state.progress = 0;
+}
+
+// Starts the firmware with its own vector table and stack
+unsafe fn jump(addr: u32) -> ! {
+    (*cortex_m::peripheral::SCB::ptr()).vtor.write(addr);
+
+    let stack = flash::read(addr);
+    let reset: extern "C" fn() -> ! = core::mem::transmute(flash::read(addr + 4));
+    cortex_m::register::msp::write(stack);
+    reset()
+}
+
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
@@ -0,0 +1,76 @@
+//! USART1 on PA9 and PA10, set up on the registers so it can be put back
+//! to its reset state before the firmware starts
+
+use stm32l0xx_hal::pac::{GPIOA, RCC, USART1};
+
+/// MSI, the clock after reset
+const CLOCK_HZ: u32 = 2_097_000;
+const BAUD_RATE: u32 = 115_200;
+
+const IOPENR_GPIOAEN: u32 = 1 << 0;
+const APB2ENR_USART1EN: u32 = 1 << 14;
+
+const CR1_UE: u32 = 1 << 0;
+const CR1_RE: u32 = 1 << 2;
+const CR1_TE: u32 = 1 << 3;
+const ISR_ORE: u32 = 1 << 3;
+const ISR_RXNE: u32 = 1 << 5;
+const ISR_TXE: u32 = 1 << 7;
+const ICR_ORECF: u32 = 1 << 3;
+
+// Pins 9 and 10 in alternate function 4
+const MODER_MASK: u32 = 0b1111 << 18;
+const MODER_AF: u32 = 0b1010 << 18;
+const AFRH_MASK: u32 = 0xFF << 4;
+const AFRH_AF4: u32 = 0x44 << 4;
+
+pub struct Serial {
+    usart: USART1,
+}
+
+impl Serial {
+    pub fn new(usart: USART1, rcc: &RCC, gpioa: &GPIOA) -> Serial {
+        rcc.iopenr.modify(|r, w| unsafe { w.bits(r.bits() | IOPENR_GPIOAEN) });
+        rcc.apb2enr.modify(|r, w| unsafe { w.bits(r.bits() | APB2ENR_USART1EN) });
+
+        gpioa.afrh.modify(|r, w| unsafe { w.bits(r.bits() & !AFRH_MASK | AFRH_AF4) });
+        gpioa.moder.modify(|r, w| unsafe { w.bits(r.bits() & !MODER_MASK | MODER_AF) });
+
+        usart.brr.write(|w| unsafe { w.bits((CLOCK_HZ + BAUD_RATE / 2) / BAUD_RATE) });
+        usart.cr1.write(|w| unsafe { w.bits(CR1_UE | CR1_RE | CR1_TE) });
+
+        Serial { usart: usart }
+    }
+
+    /// A received byte if there is one, bytes lost to an overrun are dropped
+    pub fn read(&mut self) -> Option<u8> {
+        let isr = self.usart.isr.read().bits();
+        if isr & ISR_ORE != 0 {
+            self.usart.icr.write(|w| unsafe { w.bits(ICR_ORECF) });
+        }
+        if isr & ISR_RXNE != 0 {
+            Some(self.usart.rdr.read().bits() as u8)
+        } else {
+            None
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for byte in data.iter() {
+            while self.usart.isr.read().bits() & ISR_TXE == 0 {}
+            self.usart.tdr.write(|w| unsafe { w.bits(*byte as u32) });
+        }
+    }
+
+    /// Puts the USART and the pins back like they were after reset
+    pub fn release(self, rcc: &RCC, gpioa: &GPIOA) {
+        // Let the last byte go out
+        while self.usart.isr.read().bits() & ISR_TXE == 0 {}
+        cortex_m::asm::delay(CLOCK_HZ / 1000);
+
+        self.usart.cr1.write(|w| unsafe { w.bits(0) });
+        gpioa.moder.modify(|r, w| unsafe { w.bits(r.bits() | MODER_MASK) });
+        gpioa.afrh.modify(|r, w| unsafe { w.bits(r.bits() & !AFRH_MASK) });
+        rcc.apb2enr.modify(|r, w| unsafe { w.bits(r.bits() & !APB2ENR_USART1EN) });
+    }
+}
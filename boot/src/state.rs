@@ -0,0 +1,67 @@
+//! The update state in EEPROM, written by the firmware while receiving and
+//! by the bootloader while installing
+
+use protocol::update::Status;
+
+use crate::flash;
+use crate::image::Manifest;
+use crate::layout::{EEPROM_START, OTA_STATE};
+
+/// Absolute address of the state
+pub const ADDRESS: u32 = EEPROM_START + OTA_STATE;
+pub const WORDS: usize = 21;
+
+#[derive(Clone, Copy)]
+pub struct State {
+    pub status: Status,
+    /// Resets in `Testing` without the firmware confirming itself
+    pub attempts: u8,
+    /// Page and step of a swap, so it can go on after a power loss
+    pub progress: u32,
+    pub manifest: Manifest,
+    /// Version of the last update that confirmed itself, only newer ones
+    /// are installed. 0 in erased EEPROM, until the first update.
+    pub installed: u32,
+}
+
+impl State {
+    pub fn load() -> State {
+        let word = flash::read(ADDRESS);
+        let mut signature = [0; 64];
+        for (i, chunk) in signature.chunks_mut(4).enumerate() {
+            chunk.copy_from_slice(&flash::read(ADDRESS + 16 + 4 * i as u32).to_le_bytes());
+        }
+
+        State {
+            status: Status::from_u8(word as u8),
+            attempts: (word >> 8) as u8,
+            progress: flash::read(ADDRESS + 4),
+            manifest: Manifest {
+                version: flash::read(ADDRESS + 8),
+                size: flash::read(ADDRESS + 12),
+                signature: signature,
+            },
+            installed: flash::read(ADDRESS + 80),
+        }
+    }
+
+    /// Status and attempts, progress, version, size, the signature and the
+    /// installed version
+    pub fn to_words(&self) -> [u32; WORDS] {
+        let mut words = [0; WORDS];
+        words[0] = self.status as u32 | (self.attempts as u32) << 8;
+        words[1] = self.progress;
+        words[2] = self.manifest.version;
+        words[3] = self.manifest.size;
+        for (i, chunk) in self.manifest.signature.chunks(4).enumerate() {
+            words[4 + i] = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        words[20] = self.installed;
+        words
+    }
+
+    /// True if `version` may replace the installed firmware
+    pub fn is_newer(&self, version: u32) -> bool {
+        version > self.installed
+    }
+}
@@ -3,19 +3,31 @@
 
 extern crate panic_semihosting;
 
-use cortex_m::peripheral::{syst, Peripherals, DWT};
-use cortex_m_semihosting::hprintln;
-use stm32l0xx_hal as hal;
+// The firmware's board and logger, most of them is unused here
+#[allow(dead_code)]
+#[path = "../src/board.rs"]
+mod board;
+#[allow(dead_code)]
+#[macro_use]
+#[path = "../src/logger.rs"]
+mod logger;
+#[allow(dead_code)]
+#[path = "../src/log_messages.rs"]
+mod log_messages;
+
+use board::Board;
+use log_messages as msg;
+use logger::Module;
 
 use stm32l0xx_hal::{
-    adc, exti::TriggerEdge, gpio::*, pac, prelude::*, rcc::Config, spi, stm32, syscfg, timer,
+    adc, exti::TriggerEdge, gpio::*, pac, prelude::*, rcc::Config, syscfg, timer,
 };
 
 #[rtfm::app(device = stm32l0xx_hal::pac, peripherals = true)]
 const APP: () = {
     struct Resources {
         EXT: pac::EXTI,
-        BUTTON: gpioa::PA4<Input<PullUp>>,
+        BUTTON: board::Button,
         HEATER: gpioa::PA5<Output<PushPull>>,
         DAT: gpioa::PA2<Analog>,
         ADC: adc::Adc,
@@ -26,15 +38,14 @@ const APP: () = {
 
     #[init]
     fn init(cx: init::Context) -> init::LateResources {
+        // Read the values with `host/src/bin/logdecode.rs`
+        logger::init();
+
         // Configure the clock.
         let mut rcc = cx.device.RCC.freeze(Config::hsi16());
         let mut syscfg = syscfg::SYSCFG::new(cx.device.SYSCFG, &mut rcc);
 
-        // Acquire the GPIOB peripheral. This also enables the clock for GPIOB in
-        // the RCC register.
-        let gpioa = cx.device.GPIOA.split(&mut rcc);
-        let gpiob = cx.device.GPIOB.split(&mut rcc);
-        let gpioc = cx.device.GPIOC.split(&mut rcc);
+        let board = Board::take(cx.device.GPIOA, cx.device.GPIOB, cx.device.GPIOC, &mut rcc);
 
         // Configure timer
         let mut tim2 = timer::Timer::tim2(cx.device.TIM2, 1000.ms(), &mut rcc);
@@ -44,16 +55,14 @@ const APP: () = {
         let adc = adc::Adc::new(cx.device.ADC, &mut rcc);
 
         // Configure breathalyzer pins
-        let mut heater = gpioa.pa5.into_push_pull_output();
-        let dat = gpioa.pa2.into_analog();
-
-        // Configure button input
-        let button = gpioa.pa4.into_pull_up_input();
+        let mut heater = board.heater.into_push_pull_output();
+        let dat = board.sensor.into_analog();
 
         // External interrupt
         let exti = cx.device.EXTI;
 
         // Configure external interrupt for button
+        let button = board.button;
         exti.listen(
             &mut syscfg,
             button.port(),
@@ -62,7 +71,7 @@ const APP: () = {
         );
 
         // Start heating the alchohol sensor (needs warmup)
-        heater.set_low();
+        heater.set_low().ok();
 
         init::LateResources {
             EXT: exti,
@@ -74,17 +83,27 @@ const APP: () = {
         }
     }
 
+    // The button is on PB2 on the PCB and the discovery kit
+    #[task(binds = EXTI2_3, priority = 2, resources = [BUTTON, EXT, BREATHALYZER_ON])]
+    fn exti2_3(cx: exti2_3::Context) {
+        cx.resources.EXT.clear_irq(cx.resources.BUTTON.pin_number());
+        *cx.resources.BREATHALYZER_ON = !*cx.resources.BREATHALYZER_ON;
+    }
+
+    // and on PA4 on the breadboard
     #[task(binds = EXTI4_15, priority = 2, resources = [BUTTON, EXT, BREATHALYZER_ON])]
     fn exti4_15(cx: exti4_15::Context) {
         cx.resources.EXT.clear_irq(cx.resources.BUTTON.pin_number());
         *cx.resources.BREATHALYZER_ON = !*cx.resources.BREATHALYZER_ON;
     }
 
-    #[task(binds = TIM2, priority = 2, resources = [ADC, DAT, BREATHALYZER_ON])]
+    #[task(binds = TIM2, priority = 2, resources = [ADC, DAT, BREATHALYZER_ON, TIMER])]
     fn breathalyzer(cx: breathalyzer::Context) {
+        cx.resources.TIMER.clear_irq();
+
         if *cx.resources.BREATHALYZER_ON {
             let value: u16 = cx.resources.ADC.read(cx.resources.DAT).unwrap();
-            hprintln!("Value: {:#}", value).unwrap();
+            info!(Module::Sensor, msg::SENSOR_VALUE, value);
         }
     }
 
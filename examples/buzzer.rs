@@ -1,22 +1,22 @@
-//#![cfg_attr(not(test), no_std)]
 #![no_main]
 #![no_std]
 
 extern crate panic_semihosting;
 
-use cortex_m::peripheral::{syst, Peripherals, DWT};
-use cortex_m_semihosting::hprintln;
-use stm32l0xx_hal as hal;
+// The firmware's board, most of it is unused here
+#[allow(dead_code)]
+#[path = "../src/board.rs"]
+mod board;
 
-use stm32l0xx_hal::{
-    adc, exti::TriggerEdge, gpio::*, pac, prelude::*, rcc, rcc::Config, spi, stm32, syscfg, timer,
-};
+use board::Board;
+
+use stm32l0xx_hal::{exti::TriggerEdge, gpio::*, pac, prelude::*, rcc::Config, syscfg, timer};
 
 #[rtfm::app(device = stm32l0xx_hal::pac, peripherals = true)]
 const APP: () = {
     struct Resources {
         EXT: pac::EXTI,
-        BUTTON: gpioa::PA4<Input<PullUp>>,
+        BUTTON: board::Button,
         BUZZER: gpioa::PA3<Output<PushPull>>,
         #[init(false)]
         BUZZER_ON: bool,
@@ -26,7 +26,6 @@ const APP: () = {
         PWM_ON: bool,
         TIMER_PWM: timer::Timer<pac::TIM2>,
         TIMER_INTERVAL: timer::Timer<pac::TIM3>,
-        RCC: rcc::Rcc,
     }
 
     #[init]
@@ -41,23 +40,13 @@ const APP: () = {
         tim2.listen();
         tim3.listen();
 
-        // Acquire the GPIOB peripheral. This also enables the clock for GPIOB in
-        // the RCC register.
-        let gpioa = cx.device.GPIOA.split(&mut rcc);
-        let gpiob = cx.device.GPIOB.split(&mut rcc);
-        let gpioc = cx.device.GPIOC.split(&mut rcc);
-
-        // Reset button
-        let reset = gpioc.pc0.into_push_pull_output();
-
-        // Configure inputs
-        let sx1276_dio0 = gpiob.pb4.into_pull_up_input();
-        let button = gpioa.pa4.into_pull_up_input();
+        let board = Board::take(cx.device.GPIOA, cx.device.GPIOB, cx.device.GPIOC, &mut rcc);
 
         // External interrupt
         let exti = cx.device.EXTI;
 
         // Configure external interrupt for button
+        let button = board.button;
         exti.listen(
             &mut syscfg,
             button.port(),
@@ -65,13 +54,8 @@ const APP: () = {
             TriggerEdge::Falling,
         );
 
-        let sck = gpiob.pb3;
-        let miso = gpioa.pa6;
-        let mosi = gpioa.pa7;
-        let nss = gpioa.pa15.into_push_pull_output();
-
         // Configure outputs
-        let mut buzzer = gpioa.pa3.into_push_pull_output();
+        let buzzer = board.buzzer.into_push_pull_output();
 
         // Return the initialised resources.
         init::LateResources {
@@ -80,14 +64,21 @@ const APP: () = {
             BUZZER: buzzer,
             TIMER_PWM: tim2,
             TIMER_INTERVAL: tim3,
-            RCC: rcc,
         }
     }
 
+    // The button is on PB2 on the PCB and the discovery kit
+    #[task(binds = EXTI2_3, priority = 2, resources = [BUTTON, EXT], spawn = [button_event])]
+    fn exti2_3(cx: exti2_3::Context) {
+        cx.resources.EXT.clear_irq(cx.resources.BUTTON.pin_number());
+        cx.spawn.button_event().ok();
+    }
+
+    // and on PA4 on the breadboard
     #[task(binds = EXTI4_15, priority = 2, resources = [BUTTON, EXT], spawn = [button_event])]
     fn exti4_15(cx: exti4_15::Context) {
         cx.resources.EXT.clear_irq(cx.resources.BUTTON.pin_number());
-        cx.spawn.button_event().unwrap();
+        cx.spawn.button_event().ok();
     }
 
     #[task(binds = TIM2, priority = 1, resources = [BUZZER, STATE, TIMER_PWM, BUZZER_ON, PWM_ON])]
@@ -105,19 +96,15 @@ const APP: () = {
         }
     }
 
-    #[task(binds = TIM3, priority = 1, resources = [TIMER_PWM, TIMER_INTERVAL, STATE, PWM_ON])]
+    #[task(binds = TIM3, priority = 1, resources = [TIMER_INTERVAL, PWM_ON])]
     fn tim3(cx: tim3::Context) {
         cx.resources.TIMER_INTERVAL.clear_irq();
         *cx.resources.PWM_ON = !*cx.resources.PWM_ON;
     }
 
-    #[task(priority = 1, resources = [BUZZER, STATE])]
+    #[task(priority = 1, resources = [STATE])]
     fn button_event(cx: button_event::Context) {
-        if *cx.resources.STATE {
-            *cx.resources.STATE = false;
-        } else {
-            *cx.resources.STATE = true;
-        }
+        *cx.resources.STATE = !*cx.resources.STATE;
     }
 
     // Interrupt handlers used to dispatch software tasks
@@ -3,6 +3,13 @@
 
 extern crate panic_semihosting;
 
+// The firmware's board, most of it is unused here
+#[allow(dead_code)]
+#[path = "../src/board.rs"]
+mod board;
+
+use board::Board;
+
 use rtfm::app;
 use ssd1306::{mode::TerminalMode, prelude::*, Builder};
 use stm32l0xx_hal as hal;
@@ -29,17 +36,13 @@ const APP: () = {
         let mut rcc = cx.device.RCC.freeze(Config::hsi16());
         let mut syscfg = syscfg::SYSCFG::new(cx.device.SYSCFG, &mut rcc);
 
-        // Acquire the GPIOB peripheral. This also enables the clock for GPIOB in
-        // the RCC register.
-        let gpioa = cx.device.GPIOA.split(&mut rcc);
-        let gpiob = cx.device.GPIOB.split(&mut rcc);
-        let gpioc = cx.device.GPIOC.split(&mut rcc);
+        let board = Board::take(cx.device.GPIOA, cx.device.GPIOB, cx.device.GPIOC, &mut rcc);
 
-        let mut cs = gpiob.pb12.into_push_pull_output();
-        cs.set_low(); // not sure if needed, did not try without it
+        let mut cs = board.oled_cs;
+        cs.set_low().ok(); // not sure if needed, did not try without it
 
-        let sck = gpiob.pb13;
-        let mosi = gpiob.pb15;
+        let sck = board.oled_sck;
+        let mosi = board.oled_mosi;
 
         // Initialise the SPI peripheral.
         let mut spi =
@@ -47,8 +50,8 @@ const APP: () = {
                 .SPI2
                 .spi((sck, NoMiso, mosi), spi::MODE_0, 1_000_000.hz(), &mut rcc);
 
-        let dc = gpiob.pb8.into_push_pull_output();
-        let mut res = gpiob.pb9.into_push_pull_output();
+        let dc = board.oled_dc.into_push_pull_output();
+        let mut res = board.oled_reset.into_push_pull_output();
 
         let mut delay = Delay::new(cx.core.SYST, rcc.clocks);
 
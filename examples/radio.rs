@@ -6,6 +6,22 @@ mod longfi_bindings;
 
 extern crate panic_semihosting;
 
+// The firmware's board and logger, most of them is unused here
+#[allow(dead_code)]
+#[path = "../src/board.rs"]
+mod board;
+#[allow(dead_code)]
+#[macro_use]
+#[path = "../src/logger.rs"]
+mod logger;
+#[allow(dead_code)]
+#[path = "../src/log_messages.rs"]
+mod log_messages;
+
+use board::Board;
+use log_messages as msg;
+use logger::Module;
+
 use hal::{
     exti::TriggerEdge,
     gpio::*,
@@ -23,34 +39,35 @@ use stm32l0xx_hal as hal;
 use communicator::{Message, Channel};
 use heapless::consts::*;
 
-#[rtfm::app(device = stm32l0xx_hal::pac)]
+#[rtfm::app(device = stm32l0xx_hal::pac, peripherals = true)]
 const APP: () = {
-    static mut INT: pac::EXTI = ();
-    static mut SX1276_DIO0: gpiob::PB4<Input<PullUp>> = ();
-    static mut BUFFER: [u8; 512] = [0; 512];
-    static mut LONGFI: LongFi = ();
-    static mut LED: gpiob::PB2<Output<PushPull>> = ();
-    static mut STATE: bool = false;
-    static mut COUNTER_1: u32 = 0;
-    static mut COUNTER_2: u32 = 0;
+    struct Resources {
+        INT: pac::EXTI,
+        SX1276_DIO0: gpiob::PB4<Input<PullUp>>,
+        #[init([0; 512])]
+        BUFFER: [u8; 512],
+        LONGFI: LongFi,
+        #[init(0)]
+        COUNTER_1: u32,
+        #[init(0)]
+        COUNTER_2: u32,
+    }
 
     #[init(resources = [BUFFER])]
-    fn init() -> init::LateResources {
+    fn init(cx: init::Context) -> init::LateResources {
+        // Read the values with `host/src/bin/logdecode.rs`
+        logger::init();
+
         // Configure the clock.
-        let mut rcc = device.RCC.freeze(Config::hsi16());
-        let mut syscfg = syscfg::SYSCFG::new(device.SYSCFG, &mut rcc);
+        let mut rcc = cx.device.RCC.freeze(Config::hsi16());
+        let mut syscfg = syscfg::SYSCFG::new(cx.device.SYSCFG, &mut rcc);
 
-        // Acquire the GPIOB peripheral. This also enables the clock for GPIOB in
-        // the RCC register.
-        let gpioa = device.GPIOA.split(&mut rcc);
-        let gpiob = device.GPIOB.split(&mut rcc);
-        let gpioc = device.GPIOC.split(&mut rcc);
+        let board = Board::take(cx.device.GPIOA, cx.device.GPIOB, cx.device.GPIOC, &mut rcc);
 
-        let exti = device.EXTI;
+        let exti = cx.device.EXTI;
 
-        // Configure PB4 as input.
-        let sx1276_dio0 = gpiob.pb4.into_pull_up_input();
-        // Configure the external interrupt on the falling edge for the pin 2.
+        let sx1276_dio0 = board.radio_dio0;
+        // Configure the external interrupt on the rising edge for the pin 4.
         exti.listen(
             &mut syscfg,
             sx1276_dio0.port(),
@@ -58,30 +75,21 @@ const APP: () = {
             TriggerEdge::Rising,
         );
 
-        let sck = gpiob.pb3;
-        let miso = gpioa.pa6;
-        let mosi = gpioa.pa7;
-        let nss = gpioa.pa15.into_push_pull_output();
-        longfi_bindings::set_spi_nss(nss);
+        longfi_bindings::set_spi_nss(board.radio_nss);
 
         // Initialise the SPI peripheral.
-        let mut _spi = device
+        let mut _spi = cx.device
             .SPI1
-            .spi((sck, miso, mosi), spi::MODE_0, 1_000_000.hz(), &mut rcc);
+            .spi((board.radio_sck, board.radio_miso, board.radio_mosi), spi::MODE_0, 1_000_000.hz(), &mut rcc);
 
-        let reset = gpioc.pc0.into_push_pull_output();
-        longfi_bindings::set_radio_reset(reset);
+        longfi_bindings::set_radio_reset(board.radio_reset);
 
-        let ant_sw = AntennaSwitches::new(
-            gpioa.pa1.into_push_pull_output(),
-            gpioc.pc2.into_push_pull_output(),
-            gpioc.pc1.into_push_pull_output(),
-        );
+        let (ant_rx, ant_tx_rfo, ant_tx_boost) = board.antenna;
+        let ant_sw = AntennaSwitches::new(ant_rx, ant_tx_rfo, ant_tx_boost);
 
         longfi_bindings::set_antenna_switch(ant_sw);
 
-        let en_tcxo = gpioa.pa8.into_push_pull_output();
-        longfi_bindings::set_tcxo_pins(en_tcxo);
+        longfi_bindings::set_tcxo_pins(board.tcxo);
 
         static mut BINDINGS: longfi_device::BoardBindings = longfi_device::BoardBindings {
             reset: Some(longfi_bindings::radio_reset),
@@ -100,33 +108,30 @@ const APP: () = {
 
         let mut longfi_radio = unsafe { LongFi::new(&mut BINDINGS, rf_config).unwrap() };
 
-        longfi_radio.set_buffer(resources.BUFFER);
+        longfi_radio.set_buffer(cx.resources.BUFFER);
 
         longfi_radio.receive();
 
-        // Configure PB5 as output.
-        let mut led = gpiob.pb2.into_push_pull_output();
-        led.set_low().ok();
-
         // Return the initialised resources.
         init::LateResources {
             INT: exti,
             SX1276_DIO0: sx1276_dio0,
             LONGFI: longfi_radio,
-            LED: led,
         }
     }
 
-    #[task(capacity = 4, priority = 2, resources = [BUFFER, LONGFI, LED, STATE, COUNTER_1, COUNTER_2])]
-    fn radio_event(event: RfEvent) {
-        let longfi_radio = resources.LONGFI;
+    #[task(capacity = 4, priority = 2, resources = [BUFFER, LONGFI, COUNTER_1, COUNTER_2])]
+    fn radio_event(cx: radio_event::Context, event: RfEvent) {
+        let longfi_radio = cx.resources.LONGFI;
         let client_event = longfi_radio.handle_event(event);
         match client_event {
             ClientEvent::ClientEvent_TxDone => {
+                info!(Module::Radio, msg::TX_DONE);
                 longfi_radio.receive();
             }
             ClientEvent::ClientEvent_Rx => {
                 let rx_packet = longfi_radio.get_rx();
+                info!(Module::Radio, msg::RX_PACKET, rx_packet.len);
 
                 {
                     let buf = unsafe {
@@ -137,33 +142,32 @@ const APP: () = {
                     if let Some(message) = message {
                         // Let's assume we only have permission to use ID 2:
                         if message.id != 2 {
-                            longfi_radio.set_buffer(resources.BUFFER);
+                            longfi_radio.set_buffer(cx.resources.BUFFER);
                             longfi_radio.receive();
                             return;
                         }
 
                         let binary = application(
                             message,
-                            resources.COUNTER_1,
-                            resources.COUNTER_2,
-                            resources.STATE,
-                            resources.LED
+                            cx.resources.COUNTER_1,
+                            cx.resources.COUNTER_2,
                         );
+                        info!(Module::Radio, msg::TX_START, binary.len());
                         longfi_radio.send(&binary);
                     }
                 }
 
-                longfi_radio.set_buffer(resources.BUFFER);
+                longfi_radio.set_buffer(cx.resources.BUFFER);
                 longfi_radio.receive();
             }
             ClientEvent::ClientEvent_None => {}
         }
     }
 
-    #[interrupt(priority = 1, resources = [SX1276_DIO0, INT], spawn = [radio_event])]
-    fn EXTI4_15() {
-        resources.INT.clear_irq(resources.SX1276_DIO0.pin_number());
-        spawn.radio_event(RfEvent::DIO0).unwrap();
+    #[task(binds = EXTI4_15, priority = 1, resources = [SX1276_DIO0, INT], spawn = [radio_event])]
+    fn exti4_15(cx: exti4_15::Context) {
+        cx.resources.INT.clear_irq(cx.resources.SX1276_DIO0.pin_number());
+        cx.spawn.radio_event(RfEvent::DIO0).unwrap();
     }
 
     // Interrupt handlers used to dispatch software tasks
@@ -172,13 +176,13 @@ const APP: () = {
     }
 };
 
-// Example application: increment counter:
+// Example application: increment counter. The LED it used to toggle on PB2
+// is the button on the PCB and the discovery kit, so the traffic is logged
+// instead.
 fn application(
     message: Message,
     counter_1: &mut u32,
     counter_2: &mut u32,
-    state: &mut bool,
-    led: &mut gpiob::PB2<Output<PushPull>>,
 ) -> heapless::Vec<u8, U90> {
     let data = if let Channel::One = message.channel {
         *counter_1 += message.data;
@@ -193,16 +197,5 @@ fn application(
         channel: message.channel,
         data,
     };
-    let binary = Message::serialize(&response).unwrap();
-
-    // toggle the LED
-    if *state {
-        led.set_low().unwrap();
-        *state = false;
-    } else {
-        led.set_high().unwrap();
-        *state = true;
-    }
-
-    binary
-}
\ No newline at end of file
+    Message::serialize(&response).unwrap()
+}
@@ -0,0 +1,411 @@
+//! Provisions, reads and configures a breathalyzer over its serial console.
+//!
+//! `cargo run --bin breathctl -- --port /dev/ttyUSB0 info`
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveDateTime;
+use structopt::StructOpt;
+
+use e7020e_host::{category_name, Device, Error, EPOCH_2000};
+use protocol::severity::Band;
+use protocol::{CalibrationPoint, Name, Request, Response, HISTORY_CHUNK};
+
+#[derive(StructOpt)]
+#[structopt(name = "breathctl", about = "Configure and read out a breathalyzer")]
+struct Opt {
+    /// Serial port of the device
+    #[structopt(short, long, default_value = "/dev/ttyUSB0")]
+    port: String,
+    #[structopt(subcommand)]
+    command: Cmd,
+}
+
+#[derive(StructOpt)]
+enum Cmd {
+    /// Show firmware version, clock, battery and calibration state
+    Info,
+    /// Read or change settings
+    Settings(SettingsCmd),
+    /// Read or change the severity bands
+    Bands(BandsCmd),
+    /// Read the measurement history
+    History(HistoryCmd),
+    /// Manage the sensor calibration
+    Calibrate(CalibrateCmd),
+    /// Manage the network keys
+    Keys(KeysCmd),
+    /// Manage the radio identity
+    Identity(IdentityCmd),
+    /// Manage the device clock
+    Clock(ClockCmd),
+}
+
+#[derive(StructOpt)]
+enum SettingsCmd {
+    /// Show one or all settings
+    Get { name: Option<String> },
+    /// Change and store a setting
+    Set { name: String, value: u16 },
+}
+
+#[derive(StructOpt)]
+enum BandsCmd {
+    /// Show the bands and where they start
+    Get,
+    /// Change and store a band, where it starts is the `band<index>` setting
+    Set {
+        index: u8,
+        name: String,
+        /// Drawn in front of the name, e.g. "!!"
+        #[structopt(long, default_value = "")]
+        icon: String,
+        /// Beeps once the result is shown
+        #[structopt(long, default_value = "0")]
+        beeps: u8,
+        /// Alert the server about results in the band
+        #[structopt(long)]
+        alert: bool,
+    },
+}
+
+#[derive(StructOpt)]
+enum HistoryCmd {
+    /// Print every stored measurement, newest first
+    Export {
+        /// Print as CSV instead of a table
+        #[structopt(long)]
+        csv: bool,
+        /// Only the measurements of this profile, 0 is the guest
+        #[structopt(long)]
+        profile: Option<u8>,
+    },
+}
+
+#[derive(StructOpt)]
+enum CalibrateCmd {
+    /// Load calibration points from a CSV file of `ratio,bac` lines, where
+    /// ratio is the reading divided by the baseline and bac is in per mille
+    Import { file: PathBuf },
+}
+
+#[derive(StructOpt)]
+enum KeysCmd {
+    /// Store the network keys, given as hex strings
+    Provision {
+        #[structopt(long)]
+        dev_eui: String,
+        #[structopt(long)]
+        app_eui: String,
+        #[structopt(long)]
+        app_key: String,
+        /// The app key stored now, needed to replace it
+        #[structopt(long)]
+        current_key: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+enum IdentityCmd {
+    /// Store the LongFi OUI and device id, given as hex. The device uses
+    /// them from the next reset, before that the id comes from its unique id
+    Provision {
+        #[structopt(long)]
+        oui: String,
+        #[structopt(long)]
+        device_id: String,
+        /// The stored app key, needed once the device has keys
+        #[structopt(long)]
+        current_key: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+enum ClockCmd {
+    /// Set the device clock to the UTC time of this computer
+    Sync,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let result = Device::open(&opt.port).and_then(|mut device| run(&mut device, opt.command));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(device: &mut Device, command: Cmd) -> Result<(), Error> {
+    match command {
+        Cmd::Info => info(device),
+        Cmd::Settings(SettingsCmd::Get { name }) => settings_get(device, name),
+        Cmd::Settings(SettingsCmd::Set { name, value }) => {
+            device.command(&Request::SetSetting {
+                name: to_name(&name),
+                value,
+            })?;
+            println!("{} = {}", name, value);
+            Ok(())
+        }
+        Cmd::Bands(BandsCmd::Get) => bands_get(device),
+        Cmd::Bands(BandsCmd::Set {
+            index,
+            name,
+            icon,
+            beeps,
+            alert,
+        }) => {
+            let band = Band::new(&name, &icon, beeps, alert).unwrap_or_else(|| {
+                eprintln!("a name of 1 to 10 and an icon of up to 3 characters, at most 5 beeps");
+                process::exit(1);
+            });
+            device.command(&Request::SetBand { index, band })?;
+            println!("band {} = {}", index, band);
+            Ok(())
+        }
+        Cmd::History(HistoryCmd::Export { csv, profile }) => history_export(device, csv, profile),
+        Cmd::Calibrate(CalibrateCmd::Import { file }) => calibrate_import(device, &file),
+        Cmd::Keys(KeysCmd::Provision {
+            dev_eui,
+            app_eui,
+            app_key,
+            current_key,
+        }) => {
+            device.command(&Request::ProvisionKeys {
+                dev_eui: parse_hex(&dev_eui),
+                app_eui: parse_hex(&app_eui),
+                app_key: parse_hex(&app_key),
+                current_key: current_key.map(|key| parse_hex(&key)),
+            })?;
+            println!("keys stored");
+            Ok(())
+        }
+        Cmd::Identity(IdentityCmd::Provision {
+            oui,
+            device_id,
+            current_key,
+        }) => {
+            let oui: [u8; 4] = parse_hex(&oui);
+            let device_id: [u8; 2] = parse_hex(&device_id);
+            device.command(&Request::ProvisionIdentity {
+                oui: u32::from_be_bytes(oui),
+                device_id: u16::from_be_bytes(device_id),
+                current_key: current_key.map(|key| parse_hex(&key)),
+            })?;
+            println!("identity stored, used after a reset");
+            Ok(())
+        }
+        Cmd::Clock(ClockCmd::Sync) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let seconds = (now - EPOCH_2000) as u32;
+            device.command(&Request::SetClock { seconds })?;
+            println!("clock set to {}", format_time(seconds));
+            Ok(())
+        }
+    }
+}
+
+fn info(device: &mut Device) -> Result<(), Error> {
+    match device.request(&Request::Info)? {
+        Response::Info(info) => {
+            println!("firmware     {}", info.version);
+            println!("uptime       {} s", info.uptime);
+            println!("clock        {}", format_time(info.clock));
+            println!("battery      {} mV", info.battery_mv);
+            println!("history      {} records", info.history_len);
+            println!("clean air    {}", info.clean_air);
+            println!("cal. points  {}", info.calibration_points);
+            println!("keys         {}", if info.keys_provisioned { "provisioned" } else { "missing" });
+            let uid: String = info.uid.iter().rev().map(|b| format!("{:02x}", b)).collect();
+            println!("uid          {}", uid);
+            println!(
+                "identity     oui {:08x} device {:04x} ({})",
+                info.oui,
+                info.device_id,
+                if info.identity_provisioned { "provisioned" } else { "from uid" }
+            );
+            println!("readout prot level {}", info.readout_protection);
+            if info.keys_provisioned && info.readout_protection == 0 {
+                println!("warning: the keys can be read over SWD, see the README");
+            }
+            Ok(())
+        }
+        response => Err(Error::Unexpected(response)),
+    }
+}
+
+fn settings_get(device: &mut Device, name: Option<String>) -> Result<(), Error> {
+    let mut settings = Vec::new();
+    loop {
+        let (total, page) = match device.request(&Request::GetSettings {
+            start: settings.len() as u8,
+        })? {
+            Response::Settings { total, settings } => (total, settings),
+            response => return Err(Error::Unexpected(response)),
+        };
+        settings.extend(page.iter().cloned());
+        if page.is_empty() || settings.len() >= total as usize {
+            break;
+        }
+    }
+
+    let mut found = false;
+    for setting in settings.iter() {
+        if name.as_ref().map_or(true, |name| name.as_str() == setting.name.as_str()) {
+            println!(
+                "{} = {}    ({} to {})",
+                setting.name, setting.value, setting.min, setting.max
+            );
+            found = true;
+        }
+    }
+
+    if !found {
+        eprintln!("unknown setting");
+    }
+    Ok(())
+}
+
+fn bands_get(device: &mut Device) -> Result<(), Error> {
+    let bands = match device.request(&Request::Bands)? {
+        Response::Bands(bands) => bands,
+        response => return Err(Error::Unexpected(response)),
+    };
+
+    for (i, band) in bands.iter().enumerate() {
+        let start = if i == 0 {
+            "below band 1".to_string()
+        } else {
+            format!("from setting band{}", i)
+        };
+        println!(
+            "{}  {:<14} {} beeps{}  {}",
+            i,
+            band.to_string(),
+            band.beeps,
+            if band.alert { ", alerts" } else { "" },
+            start
+        );
+    }
+    Ok(())
+}
+
+fn history_export(device: &mut Device, csv: bool, profile: Option<u8>) -> Result<(), Error> {
+    if csv {
+        println!("time,category,raw,baseline,flags,profile");
+    }
+
+    let mut start = 0;
+    loop {
+        let (total, records) = match device.request(&Request::History {
+            start,
+            count: HISTORY_CHUNK,
+        })? {
+            Response::History { total, records } => (total, records),
+            response => return Err(Error::Unexpected(response)),
+        };
+
+        let wanted = records
+            .iter()
+            .filter(|record| profile.map_or(true, |profile| record.profile == profile));
+        for record in wanted {
+            let time = format_time(record.time);
+            if csv {
+                println!(
+                    "{},{},{},{},{},{}",
+                    time, record.category, record.raw, record.baseline, record.flags, record.profile
+                );
+            } else {
+                println!(
+                    "{}  {:14}  raw {:5}  baseline {:5}  profile {}",
+                    time,
+                    category_name(record.category), record.raw, record.baseline, record.profile
+                );
+            }
+        }
+
+        start += records.len() as u32;
+        if records.is_empty() || start >= total {
+            return Ok(());
+        }
+    }
+}
+
+fn calibrate_import(device: &mut Device, file: &PathBuf) -> Result<(), Error> {
+    let text = fs::read_to_string(file)?;
+    let mut points = heapless::Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split(',').map(|f| f.trim().parse::<f32>());
+        let point = match (fields.next(), fields.next()) {
+            (Some(Ok(ratio)), Some(Ok(bac))) => CalibrationPoint {
+                ratio: (ratio * 1000.0).round() as u16,
+                bac: (bac * 100.0).round() as u16,
+            },
+            // A header line
+            _ if n == 0 => continue,
+            _ => {
+                eprintln!("line {}: expected ratio,bac", n + 1);
+                process::exit(1);
+            }
+        };
+
+        if points.push(point).is_err() {
+            eprintln!("at most {} points fit on the device", points.capacity());
+            process::exit(1);
+        }
+    }
+
+    let count = points.len();
+    device.command(&Request::SetCalibration { points })?;
+    println!("{} points stored", count);
+    Ok(())
+}
+
+fn format_time(seconds: u32) -> String {
+    NaiveDateTime::from_timestamp((EPOCH_2000 + seconds as u64) as i64, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn to_name(name: &str) -> Name {
+    let mut n = Name::new();
+    if n.push_str(name).is_err() {
+        eprintln!("setting names are at most {} characters", n.capacity());
+        process::exit(1);
+    }
+    n
+}
+
+fn parse_hex<T: Default + AsMut<[u8]>>(hex: &str) -> T {
+    let mut bytes = T::default();
+    let hex: String = hex.chars().filter(|c| *c != ':' && *c != '-').collect();
+
+    if hex.len() != bytes.as_mut().len() * 2 {
+        eprintln!("expected {} hex digits in {}", bytes.as_mut().len() * 2, hex);
+        process::exit(1);
+    }
+
+    for (i, byte) in bytes.as_mut().iter_mut().enumerate() {
+        match u8::from_str_radix(&hex[2 * i..2 * i + 2], 16) {
+            Ok(b) => *byte = b,
+            Err(_) => {
+                eprintln!("{} is not hex", hex);
+                process::exit(1);
+            }
+        }
+    }
+    bytes
+}
@@ -0,0 +1,208 @@
+//! Builds signed command downlinks and checks the replies.
+//!
+//! `cargo run --bin downlink -- --app-key 00112233... --counter 5 limit 0.2`
+//! prints the hex frame to queue as a downlink, on LoRaWAN port 2.
+//! `... --app-key 00112233... reply c205000000...` verifies and prints the
+//! reply uplink. The counter must be above the last one the device accepted,
+//! it starts over when the keys are provisioned.
+
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveDateTime;
+use structopt::StructOpt;
+
+use e7020e_host::{category_name, EPOCH_2000};
+use protocol::command::{self, Command, Key, Reply, MAX_COMMAND, REPLY_RECORDS};
+use protocol::CalibrationPoint;
+
+#[derive(StructOpt)]
+#[structopt(name = "downlink", about = "Sign breathalyzer commands and check the replies")]
+struct Opt {
+    /// The AppKey the device was provisioned with, as hex
+    #[structopt(long)]
+    app_key: String,
+    /// Counter of the command, above the last one sent
+    #[structopt(long, default_value = "1")]
+    counter: u32,
+    #[structopt(subcommand)]
+    command: Cmd,
+}
+
+#[derive(StructOpt)]
+enum Cmd {
+    /// Set the legal limit in per mille
+    Limit { bac: f32 },
+    /// Set the minutes between status uplinks, 0 turns them off
+    Report { minutes: u16 },
+    /// Replace the calibration points, given as ratio:bac pairs
+    Calibrate { points: Vec<String> },
+    /// Set the device clock to the UTC time of this computer
+    Time,
+    /// Request measurements, `start` records back from the newest
+    History {
+        #[structopt(default_value = "0")]
+        start: u32,
+    },
+    /// Let the device measure again
+    Enable,
+    /// Make the device refuse to measure
+    Disable,
+    /// Request a self test
+    SelfTest,
+    /// Ask how far a firmware update has come, see `firmware` to send one
+    UpdateStatus,
+    /// Install a received firmware update
+    UpdateApply,
+    /// Start a measurement, the device shows `requester` while it runs
+    Measure { requester: u16 },
+    /// Tell the device an alert reached the contact, it stops sending it
+    AckAlert { id: u16 },
+    /// Verify and print a reply uplink given as hex
+    Reply { hex: String },
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let key = command::command_key(&parse_key(&opt.app_key));
+
+    let command = match opt.command {
+        Cmd::Limit { bac } => Command::SetLegalLimit {
+            bac: (bac * 100.0).round() as u16,
+        },
+        Cmd::Report { minutes } => Command::SetReportInterval { minutes: minutes },
+        Cmd::Calibrate { points } => Command::SetCalibration {
+            points: parse_points(&points),
+        },
+        Cmd::Time => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            Command::SetTime {
+                seconds: (now - EPOCH_2000) as u32,
+            }
+        }
+        Cmd::History { start } => Command::GetHistory {
+            start: start,
+            count: REPLY_RECORDS,
+        },
+        Cmd::Enable => Command::SetEnabled { enabled: true },
+        Cmd::Disable => Command::SetEnabled { enabled: false },
+        Cmd::SelfTest => Command::SelfTest,
+        Cmd::UpdateStatus => Command::UpdateStatus,
+        Cmd::UpdateApply => Command::UpdateApply,
+        Cmd::Measure { requester } => Command::Measure {
+            requester: requester,
+        },
+        Cmd::AckAlert { id } => Command::AcknowledgeAlert { id: id },
+        Cmd::Reply { hex } => {
+            print_reply(&key, &parse_hex(&hex));
+            return;
+        }
+    };
+
+    let mut buf = [0; MAX_COMMAND];
+    match command::encode_command(&key, opt.counter, &command, &mut buf) {
+        Ok(frame) => println!("{}", to_hex(frame)),
+        Err(e) => {
+            eprintln!("command too long: {:?}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn print_reply(key: &Key, frame: &[u8]) {
+    let (counter, reply) = match command::decode_reply(key, frame) {
+        Ok(reply) => reply,
+        Err((counter, rejection)) => {
+            eprintln!("invalid reply to command {}: {:?}", counter, rejection);
+            process::exit(1);
+        }
+    };
+
+    print!("command {}: ", counter);
+    match reply {
+        Reply::Done => println!("done"),
+        Reply::Rejected(rejection) => println!("rejected, {:?}", rejection),
+        Reply::History { total, records } => {
+            println!("{} records stored", total);
+            for record in records.iter() {
+                let time = NaiveDateTime::from_timestamp(record.time as i64 + EPOCH_2000 as i64, 0);
+                println!(
+                    "{}  {} raw {} baseline {} flags {:#04x} profile {}",
+                    time,
+                    category_name(record.category), record.raw, record.baseline, record.flags, record.profile
+                );
+            }
+        }
+        Reply::SelfTest(result) => {
+            println!("self test");
+            println!("faults       {:#04x}", result.faults);
+            println!("raw          {}", result.raw);
+            println!("baseline     {}", result.baseline);
+            println!("warm         {}", result.warm);
+            println!("temperature  {} °C", result.temperature);
+            println!("battery      {} mV", result.battery_mv);
+            println!("keys         {}", result.keys_provisioned);
+        }
+        Reply::Update(status) => {
+            println!("update to version {}, {:?}", status.version, status.status);
+            println!("received     {} of {}", status.received, status.total);
+            if status.next_missing < status.total {
+                println!("missing      from {}", status.next_missing);
+            }
+        }
+    }
+}
+
+fn parse_points(points: &[String]) -> heapless::Vec<CalibrationPoint, heapless::consts::U4> {
+    let mut parsed = heapless::Vec::new();
+    for point in points {
+        let mut fields = point.split(':').map(|f| f.parse::<f32>());
+        let point = match (fields.next(), fields.next()) {
+            (Some(Ok(ratio)), Some(Ok(bac))) => CalibrationPoint {
+                ratio: (ratio * 1000.0).round() as u16,
+                bac: (bac * 100.0).round() as u16,
+            },
+            _ => {
+                eprintln!("{}: expected ratio:bac", point);
+                process::exit(1);
+            }
+        };
+        if parsed.push(point).is_err() {
+            eprintln!("at most {} points fit in a downlink", parsed.capacity());
+            process::exit(1);
+        }
+    }
+    parsed
+}
+
+fn parse_key(hex: &str) -> Key {
+    let bytes = parse_hex(hex);
+    if bytes.len() != 16 {
+        eprintln!("expected 32 hex digits in {}", hex);
+        process::exit(1);
+    }
+    let mut key = [0; 16];
+    key.copy_from_slice(&bytes);
+    key
+}
+
+fn parse_hex(hex: &str) -> Vec<u8> {
+    let hex: String = hex.chars().filter(|c| *c != ':' && *c != '-').collect();
+    let bytes: Option<Vec<u8>> = if hex.len() % 2 == 0 {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    } else {
+        None
+    };
+
+    bytes.unwrap_or_else(|| {
+        eprintln!("{} is not hex", hex);
+        process::exit(1);
+    })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,197 @@
+//! Pretends to be a breathalyzer on a pty, for testing the host tools
+//! without hardware.
+//!
+//! `cargo run --bin fake_device` prints the pty to pass to `breathctl --port`.
+
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::fcntl::OFlag;
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt};
+
+use e7020e_host::{read_frame, write_frame};
+use protocol::settings::SETTINGS_INFO;
+use protocol::severity::{Band, Severity, DEFAULT_BANDS, DEFAULT_STARTS};
+use protocol::{
+    ErrorCode, HistoryRecord, Info, Name, Request, Response, Setting, HISTORY_CHUNK,
+    SETTINGS_CHUNK,
+};
+
+struct FakeDevice {
+    booted: Instant,
+    clock: u32,
+    clock_set: Instant,
+    settings: Vec<(&'static str, u16, u16, u16)>,
+    history: Vec<HistoryRecord>,
+    calibration_points: u8,
+    bands: [Band; 6],
+    app_key: Option<[u8; 16]>,
+    identity: Option<(u32, u16)>,
+}
+
+impl FakeDevice {
+    fn new() -> FakeDevice {
+        // A few days of made up measurements, newest first
+        let history = (0..20)
+            .map(|i| {
+                let raw = 2800 - (i as u16 % 6) * 150;
+                HistoryRecord {
+                    time: 640_000_000 - i * 7200,
+                    raw: raw,
+                    baseline: 3000,
+                    category: Severity::of(raw, 3000, &DEFAULT_STARTS).category(),
+                    flags: 0,
+                    profile: (i % 3) as u8,
+                }
+            })
+            .collect();
+
+        FakeDevice {
+            booted: Instant::now(),
+            clock: 640_000_000,
+            clock_set: Instant::now(),
+            settings: SETTINGS_INFO
+                .iter()
+                .map(|info| (info.name, info.default, info.min, info.max))
+                .collect(),
+            history,
+            calibration_points: 0,
+            bands: DEFAULT_BANDS,
+            app_key: None,
+            identity: None,
+        }
+    }
+
+    fn handle(&mut self, request: Request) -> Response {
+        match request {
+            Request::Info => {
+                let mut version = heapless::String::new();
+                version.push_str("fake").ok();
+
+                Response::Info(Info {
+                    version,
+                    uptime: self.booted.elapsed().as_secs() as u32,
+                    clock: self.clock + self.clock_set.elapsed().as_secs() as u32,
+                    battery_mv: 3850,
+                    history_len: self.history.len() as u32,
+                    clean_air: 3000,
+                    calibration_points: self.calibration_points,
+                    keys_provisioned: self.app_key.is_some(),
+                    uid: [0x20, 0x33, 0x36, 0x47, 0x39, 0x30, 0x35, 0x17, 0x00, 0x5c, 0x00, 0x42],
+                    oui: self.identity.map_or(0xBEEF_9A3C, |(oui, _)| oui),
+                    device_id: self.identity.map_or(0x4e1f, |(_, device_id)| device_id),
+                    identity_provisioned: self.identity.is_some(),
+                    readout_protection: 0,
+                })
+            }
+            Request::GetSettings { start } => {
+                let mut list = heapless::Vec::new();
+                let page = self
+                    .settings
+                    .iter()
+                    .skip(start as usize)
+                    .take(SETTINGS_CHUNK as usize);
+                for (name, value, min, max) in page {
+                    let mut setting_name = Name::new();
+                    setting_name.push_str(name).ok();
+                    list.push(Setting {
+                        name: setting_name,
+                        value: *value,
+                        min: *min,
+                        max: *max,
+                    })
+                    .ok();
+                }
+                Response::Settings {
+                    total: self.settings.len() as u8,
+                    settings: list,
+                }
+            }
+            Request::SetSetting { name, value } => {
+                match self.settings.iter_mut().find(|s| s.0 == name.as_str()) {
+                    None => Response::Error(ErrorCode::UnknownSetting),
+                    Some(s) if value < s.2 || value > s.3 => Response::Error(ErrorCode::OutOfRange),
+                    Some(s) => {
+                        s.1 = value;
+                        Response::Done
+                    }
+                }
+            }
+            Request::History { start, count } => {
+                let mut records = heapless::Vec::new();
+                let count = count.min(HISTORY_CHUNK) as usize;
+                for record in self.history.iter().skip(start as usize).take(count) {
+                    records.push(*record).ok();
+                }
+                Response::History {
+                    total: self.history.len() as u32,
+                    records,
+                }
+            }
+            Request::SetCalibration { points } => {
+                self.calibration_points = points.len() as u8;
+                Response::Done
+            }
+            Request::ProvisionKeys { current_key, .. }
+            | Request::ProvisionIdentity { current_key, .. }
+                if self.app_key.is_some() && current_key != self.app_key =>
+            {
+                Response::Error(ErrorCode::Unauthorized)
+            }
+            Request::ProvisionKeys { app_key, .. } => {
+                self.app_key = Some(app_key);
+                Response::Done
+            }
+            Request::SetClock { seconds } => {
+                self.clock = seconds;
+                self.clock_set = Instant::now();
+                Response::Done
+            }
+            Request::ProvisionIdentity { oui, device_id, .. } => {
+                self.identity = Some((oui, device_id));
+                Response::Done
+            }
+            Request::Bands => Response::Bands(self.bands.iter().cloned().collect()),
+            Request::SetBand { index, band } => match self.bands.get_mut(index as usize) {
+                Some(stored) => {
+                    *stored = band;
+                    Response::Done
+                }
+                None => Response::Error(ErrorCode::BadRequest),
+            },
+        }
+    }
+}
+
+fn main() -> nix::Result<()> {
+    let mut master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)?;
+    grantpt(&master)?;
+    unlockpt(&master)?;
+    println!("{}", ptsname_r(&master)?);
+
+    let mut device = FakeDevice::new();
+    loop {
+        // Fails while no one has the pty open
+        let mut frame = match read_frame(&mut master) {
+            Ok(frame) => frame,
+            Err(_) => {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        let response = match protocol::decode(&mut frame) {
+            Ok(request) => {
+                eprintln!("{:?}", request);
+                device.handle(request)
+            }
+            Err(_) => Response::Error(ErrorCode::BadRequest),
+        };
+
+        if write_frame(&mut master, &response).is_err() {
+            eprintln!("could not answer");
+        }
+        master.flush().ok();
+    }
+}
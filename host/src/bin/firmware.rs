@@ -0,0 +1,250 @@
+//! Signs firmware images and turns them into update downlinks.
+//!
+//! `cargo run --bin firmware -- keygen keys/` makes a new signing key, the
+//! `firmware.pub` goes in boot/keys/ before building the bootloader and the
+//! firmware. Keep `firmware.key` somewhere safe and out of the repository.
+//! `firmware send --key .. --app-key .. --counter 10 --version 2 app.bin`
+//! prints the downlinks of an update, one `<port> <hex>` line each: the
+//! start and signature commands followed by the fragments, which are signed
+//! with the command key. `--from` resumes at the first missing fragment
+//! reported by `downlink update-status`. The version has to be above the
+//! installed one.
+//!
+//! `firmware flash --key .. --port /dev/ttyUSB0 --version 2 app.bin` pushes
+//! an image to the bootloader over the serial console instead, no debugger
+//! needed.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use structopt::StructOpt;
+
+use e7020e_host::{Device, Error};
+use protocol::boot::{BootRequest, BootResponse};
+use protocol::command::{self, Command, Key, COMMAND_PORT, MAX_COMMAND};
+use protocol::update::{self, FRAGMENT_PORT, FRAGMENT_SIZE, MAX_FRAGMENT, MAX_RADIO_FRAGMENT};
+
+#[derive(StructOpt)]
+#[structopt(name = "firmware", about = "Sign firmware and send it over the radio")]
+enum Opt {
+    /// Make a new signing key, firmware.key and firmware.pub in `dir`
+    Keygen { dir: PathBuf },
+    /// Print the downlinks that send a firmware image, made with objcopy -O binary
+    Send {
+        image: PathBuf,
+        /// Signing key from keygen, kept out of the repository
+        #[structopt(long)]
+        key: PathBuf,
+        /// Version of the image, shown in the update status
+        #[structopt(long)]
+        version: u32,
+        /// The AppKey the device was provisioned with, as hex
+        #[structopt(long)]
+        app_key: String,
+        /// Counter of the first command, above the last one sent
+        #[structopt(long)]
+        counter: u32,
+        /// First fragment to send, the commands are left out if not 0
+        #[structopt(long, default_value = "0")]
+        from: u16,
+    },
+    /// Restart the device and push a firmware image to its bootloader
+    Flash {
+        image: PathBuf,
+        #[structopt(long, default_value = "/dev/ttyUSB0")]
+        port: String,
+        /// Signing key from keygen, kept out of the repository
+        #[structopt(long)]
+        key: PathBuf,
+        /// Version of the image
+        #[structopt(long)]
+        version: u32,
+    },
+}
+
+/// `Hello` is sent this often while waiting for the bootloader
+const HELLO_INTERVAL: Duration = Duration::from_millis(200);
+const HELLO_TRIES: u32 = 25;
+
+fn main() {
+    match Opt::from_args() {
+        Opt::Keygen { dir } => keygen(&dir),
+        Opt::Send {
+            image,
+            key,
+            version,
+            app_key,
+            counter,
+            from,
+        } => send(&image, &key, version, &app_key, counter, from),
+        Opt::Flash {
+            image,
+            port,
+            key,
+            version,
+        } => {
+            let result = Device::open(&port).and_then(|mut device| flash(&mut device, &image, &key, version));
+            if let Err(e) = result {
+                eprintln!("{}: {}", port, e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn keygen(dir: &PathBuf) {
+    let keypair = Keypair::generate(&mut OsRng);
+    let result = fs::write(dir.join("firmware.key"), keypair.secret.as_bytes())
+        .and_then(|_| fs::write(dir.join("firmware.pub"), keypair.public.as_bytes()));
+
+    if let Err(e) = result {
+        eprintln!("{}: {}", dir.display(), e);
+        process::exit(1);
+    }
+}
+
+fn send(image: &PathBuf, key: &PathBuf, version: u32, app_key: &str, counter: u32, from: u16) {
+    let image = read(image);
+    let half = sign(&image, key, version);
+    let key = command::command_key(&parse_key(app_key));
+
+    if from == 0 {
+        let commands = [
+            Command::UpdateStart {
+                version: version,
+                size: image.len() as u32,
+            },
+            Command::UpdateSignature { half: 0, bytes: half[0] },
+            Command::UpdateSignature { half: 1, bytes: half[1] },
+        ];
+        for (i, command) in commands.iter().enumerate() {
+            let mut buf = [0; MAX_COMMAND];
+            let frame = command::encode_command(&key, counter + i as u32, command, &mut buf).unwrap();
+            println!("{} {}", COMMAND_PORT, to_hex(frame));
+        }
+    }
+
+    for (index, data) in image.chunks(FRAGMENT_SIZE).enumerate().skip(from as usize) {
+        let mut buf = [0; MAX_RADIO_FRAGMENT];
+        let frame = update::encode_radio_fragment(&key, index as u16, data, &mut buf);
+        println!("{} {}", FRAGMENT_PORT, to_hex(frame));
+    }
+
+    eprintln!(
+        "{} fragments, then check with `downlink update-status` and install with `downlink update-apply`",
+        update::fragment_count(image.len() as u32)
+    );
+}
+
+fn flash(device: &mut Device, image: &PathBuf, key: &PathBuf, version: u32) -> Result<(), Error> {
+    let image = read(image);
+    let signature = sign(&image, key, version);
+
+    // The bootloader drops the line as a bad frame if it is already running
+    device.write_line("reboot")?;
+    device.set_timeout(HELLO_INTERVAL)?;
+    let mut slot_size = None;
+    for _ in 0..HELLO_TRIES {
+        if let Ok(BootResponse::Ready { slot_size: size }) = device.exchange(&BootRequest::Hello) {
+            slot_size = Some(size);
+            break;
+        }
+        thread::sleep(HELLO_INTERVAL);
+    }
+    let slot_size = slot_size.unwrap_or_else(|| {
+        eprintln!("no answer from the bootloader, reset the device and try again");
+        process::exit(1);
+    });
+    if image.len() as u32 > slot_size {
+        eprintln!("the image is {} bytes, the slot only {}", image.len(), slot_size);
+        process::exit(1);
+    }
+    device.set_timeout(Duration::from_secs(2))?;
+
+    expect_done(device.exchange(&BootRequest::Start {
+        version: version,
+        size: image.len() as u32,
+        signature: (signature[0], signature[1]),
+    })?);
+
+    let total = update::fragment_count(image.len() as u32);
+    for (index, data) in image.chunks(FRAGMENT_SIZE).enumerate() {
+        let mut buf = [0; MAX_FRAGMENT];
+        let frame = update::encode_fragment(index as u16, data, &mut buf);
+        let fragment = BootRequest::Fragment(heapless::Vec::from_slice(frame).unwrap());
+        expect_done(device.exchange(&fragment)?);
+        eprint!("\r{} of {} fragments", index + 1, total);
+    }
+    eprintln!();
+
+    expect_done(device.exchange(&BootRequest::Install)?);
+    eprintln!("installing, the new firmware starts in a minute or two");
+    Ok(())
+}
+
+fn expect_done(response: BootResponse) {
+    if response != BootResponse::Done {
+        eprintln!("\nbootloader answered {:?}", response);
+        process::exit(1);
+    }
+}
+
+/// The signature of an image, in the two halves it is sent in
+fn sign(image: &[u8], key: &PathBuf, version: u32) -> [[u8; 32]; 2] {
+    let keypair = load_keypair(&read(key));
+
+    let mut digest = [0; 32];
+    digest.copy_from_slice(&Sha256::digest(image));
+    let message = update::signed_message(version, image.len() as u32, &digest);
+    let signature = keypair.sign(&message).to_bytes();
+
+    let mut half = [[0; 32]; 2];
+    half[0].copy_from_slice(&signature[..32]);
+    half[1].copy_from_slice(&signature[32..]);
+    half
+}
+
+fn load_keypair(bytes: &[u8]) -> Keypair {
+    let secret = SecretKey::from_bytes(bytes).unwrap_or_else(|_| {
+        eprintln!("not a firmware key");
+        process::exit(1);
+    });
+    let public = PublicKey::from(&secret);
+    Keypair {
+        secret: secret,
+        public: public,
+    }
+}
+
+fn read(path: &PathBuf) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        process::exit(1);
+    })
+}
+
+fn parse_key(hex: &str) -> Key {
+    let hex: String = hex.chars().filter(|c| *c != ':' && *c != '-').collect();
+    let mut key = [0; 16];
+    if hex.len() != 32 {
+        eprintln!("expected 32 hex digits in {}", hex);
+        process::exit(1);
+    }
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap_or_else(|_| {
+            eprintln!("{} is not hex", hex);
+            process::exit(1);
+        });
+    }
+    key
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,94 @@
+//! Decodes the binary log stream written by `src/logger.rs`.
+//!
+//! Reads the raw RTT bytes from a file or stdin, for example
+//! `nc localhost 9090 | cargo run --bin logdecode`
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+
+#[path = "../../../src/log_messages.rs"]
+#[allow(dead_code)]
+mod log_messages;
+
+use log_messages::{LEVELS, MESSAGES, MODULES};
+
+fn main() -> io::Result<()> {
+    let input: Box<dyn Read> = match env::args().nth(1) {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+
+    let mut frame = Vec::new();
+    for byte in BufReader::new(input).bytes() {
+        let byte = byte?;
+        if byte != 0 {
+            frame.push(byte);
+            continue;
+        }
+
+        match decode(&frame) {
+            Some(line) => println!("{}", line),
+            None => eprintln!("bad frame {:02x?}", frame),
+        }
+        frame.clear();
+    }
+
+    Ok(())
+}
+
+fn decode(encoded: &[u8]) -> Option<String> {
+    let raw = cobs::decode_vec(encoded).ok()?;
+    if raw.len() < 3 {
+        return None;
+    }
+
+    let level = LEVELS.get((raw[0] >> 4) as usize).unwrap_or(&"?");
+    let module = MODULES.get((raw[0] & 0x0F) as usize).unwrap_or(&"?");
+    let id = u16::from_le_bytes([raw[1], raw[2]]);
+
+    let mut rest = &raw[3..];
+    let time = varint(&mut rest)?;
+    let mut args = Vec::new();
+    while !rest.is_empty() {
+        args.push(varint(&mut rest)?);
+    }
+
+    let text = match MESSAGES.iter().find(|(i, _)| *i == id) {
+        Some((_, format)) => format_message(format, &args),
+        None => format!("unknown message {} {:?}", id, args),
+    };
+
+    Some(format!("[{:>6}s] {:<5} {}: {}", time, level, module, text))
+}
+
+// Replaces each `{}` with the next argument
+fn format_message(format: &str, args: &[u32]) -> String {
+    let mut args = args.iter();
+    let mut out = String::new();
+    let mut parts = format.split("{}").peekable();
+
+    while let Some(part) = parts.next() {
+        out.push_str(part);
+        if parts.peek().is_some() {
+            match args.next() {
+                Some(arg) => out.push_str(&arg.to_string()),
+                None => out.push('?'),
+            }
+        }
+    }
+    out
+}
+
+fn varint(buf: &mut &[u8]) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
@@ -0,0 +1,249 @@
+//! Decodes uplink payloads from the breathalyzer.
+//!
+//! `cargo run --bin telemetry -- 01070050...`, one hex payload per argument,
+//! or one per line on stdin. `--lpp` decodes Cayenne LPP payloads from
+//! firmware built with the `cayenne-lpp` feature, and `--json` prints one
+//! JSON object per payload, keyed like ThingsBoard telemetry. Alerts are
+//! recognized by their marker and decoded as well.
+
+use std::io::{self, BufRead};
+use std::process;
+
+use serde_json::{json, Map, Value};
+
+use e7020e_host::category_name;
+use protocol::alert::Alert;
+use protocol::cayenne::{self, channel};
+use protocol::telemetry::{fault, reset, Telemetry, BAC_UNKNOWN};
+
+const FAULTS: [(u8, &str); 6] = [
+    (fault::UNCALIBRATED, "uncalibrated"),
+    (fault::SENSOR_RANGE, "sensor range"),
+    (fault::BATTERY_LOW, "battery low"),
+    (fault::WATCHDOG_RESET, "watchdog reset"),
+    (fault::CRASHED, "crashed"),
+    (fault::MOUTH_ALCOHOL, "mouth alcohol"),
+];
+
+#[derive(Clone, Copy)]
+struct Options {
+    lpp: bool,
+    json: bool,
+}
+
+fn main() {
+    let (flags, args): (Vec<String>, Vec<String>) =
+        std::env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let options = Options {
+        lpp: flags.iter().any(|f| f == "--lpp"),
+        json: flags.iter().any(|f| f == "--json"),
+    };
+    let mut ok = true;
+
+    if args.is_empty() {
+        for line in io::stdin().lock().lines() {
+            ok &= decode(line.unwrap_or_default().trim(), options);
+        }
+    } else {
+        for arg in args.iter() {
+            ok &= decode(arg, options);
+        }
+    }
+
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn decode(hex: &str, options: Options) -> bool {
+    if hex.is_empty() {
+        return true;
+    }
+
+    let bytes = match parse_hex(hex) {
+        Some(bytes) => bytes,
+        None => {
+            eprintln!("{}: not hex", hex);
+            return false;
+        }
+    };
+
+    // Firmware built for Cayenne LPP sends alerts the same way
+    if let Some(alert) = Alert::decode(&bytes) {
+        if options.json {
+            println!("{}", alert_json(&alert));
+        } else {
+            print_alert(&alert);
+        }
+        return true;
+    }
+
+    if options.lpp {
+        return match cayenne::decode(&bytes) {
+            Ok(values) => {
+                let object = lpp_json(&values);
+                if options.json {
+                    println!("{}", Value::Object(object));
+                } else {
+                    for (name, value) in object.iter() {
+                        println!("{:<16} {}", name, value);
+                    }
+                    println!();
+                }
+                true
+            }
+            Err(()) => {
+                eprintln!("{}: malformed", hex);
+                false
+            }
+        };
+    }
+
+    match Telemetry::decode(&bytes) {
+        Ok(t) => {
+            if options.json {
+                println!("{}", telemetry_json(&t));
+            } else {
+                print(&t);
+            }
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: {:?}", hex, e);
+            false
+        }
+    }
+}
+
+fn fault_names(faults: u8) -> Vec<&'static str> {
+    FAULTS
+        .iter()
+        .filter(|(bit, _)| faults & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+// The same keys as the LPP form where the fields overlap
+fn telemetry_json(t: &Telemetry) -> Value {
+    json!({
+        "version": t.version,
+        "seq": t.seq,
+        "uptime": t.uptime,
+        "bac": if t.bac == BAC_UNKNOWN { Value::Null } else { json!(t.bac as f64 / 100.0) },
+        "category": t.category,
+        "severity": category_name(t.category),
+        "raw": t.raw,
+        "baseline": t.baseline,
+        "temperature": t.temperature,
+        "battery_v": t.battery_mv as f64 / 1000.0,
+        "battery_percent": t.battery_percent,
+        "faults": fault_names(t.faults),
+        "profile": t.profile,
+        "reset": t.reset.map(reset::name),
+        "crash": t.crash.map(|site| json!({
+            "file": format!("{:04x}", site.file),
+            "line": site.line,
+        })),
+        "link": t.link.map(|link| json!({
+            "rssi": link.rssi,
+            "snr": link.snr,
+            "tx": link.tx,
+            "tx_done": link.tx_done,
+            "latency_ms": link.latency_ms,
+            "crc_errors": link.crc_errors,
+            "retries": link.retries,
+            "duty_percent": link.duty_permille as f64 / 10.0,
+        })),
+    })
+}
+
+fn alert_json(alert: &Alert) -> Value {
+    json!({
+        "alert": alert.id,
+        "attempt": alert.attempt,
+        "bac": if alert.bac == BAC_UNKNOWN { Value::Null } else { json!(alert.bac as f64 / 100.0) },
+        "category": alert.category,
+        "severity": category_name(alert.category),
+        "profile": alert.profile,
+        "time": alert.time,
+    })
+}
+
+fn lpp_json(values: &[cayenne::LppValue]) -> Map<String, Value> {
+    let mut object = Map::new();
+    for value in values {
+        let name = channel::NAMES
+            .iter()
+            .find(|(c, _)| *c == value.channel)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| format!("channel_{}", value.channel));
+
+        let json = if value.channel == channel::FAULTS {
+            json!(fault_names(value.raw as u8))
+        } else if value.scale() == 1 {
+            json!(value.raw)
+        } else {
+            json!(value.raw as f64 / value.scale() as f64)
+        };
+        object.insert(name, json);
+    }
+    object
+}
+
+fn print(t: &Telemetry) {
+    println!("version      {}", t.version);
+    println!("seq          {}", t.seq);
+    println!("uptime       {} s", t.uptime);
+    if t.bac == BAC_UNKNOWN {
+        println!("bac          unknown");
+    } else {
+        println!("bac          {}.{:02} ‰", t.bac / 100, t.bac % 100);
+    }
+    println!("severity     {}", category_name(t.category));
+    println!("raw          {}", t.raw);
+    println!("baseline     {}", t.baseline);
+    println!("temperature  {} °C", t.temperature);
+    println!("battery      {} mV {}%", t.battery_mv, t.battery_percent);
+
+    let faults = fault_names(t.faults);
+    println!("faults       {}", if faults.is_empty() { "none".to_string() } else { faults.join(", ") });
+    println!("profile      {}", t.profile);
+    if let Some(cause) = t.reset {
+        println!("reset        {}", reset::name(cause));
+    }
+    if let Some(site) = t.crash {
+        println!("crash        file {:04x} line {}", site.file, site.line);
+    }
+
+    if let Some(link) = t.link {
+        println!("rssi         {} dBm snr {} dB", link.rssi, link.snr);
+        println!("tx           {} done {} retries {}", link.tx, link.tx_done, link.retries);
+        println!("latency      {} ms", link.latency_ms);
+        println!("crc errors   {}", link.crc_errors);
+        println!("duty cycle   {}.{} %", link.duty_permille / 10, link.duty_permille % 10);
+    }
+    println!();
+}
+
+fn print_alert(alert: &Alert) {
+    println!("alert        {}, attempt {}", alert.id, alert.attempt);
+    if alert.bac == BAC_UNKNOWN {
+        println!("bac          unknown");
+    } else {
+        println!("bac          {}.{:02} ‰", alert.bac / 100, alert.bac % 100);
+    }
+    println!("severity     {}", category_name(alert.category));
+    println!("profile      {}", alert.profile);
+    println!("time         {} s since 2000", alert.time);
+    println!();
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
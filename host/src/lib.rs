@@ -0,0 +1,147 @@
+//! Talks to a breathalyzer over its serial console with `protocol` frames.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use protocol::severity::Severity;
+use protocol::{ErrorCode, Request, Response, MAX_FRAME};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serialport::{SerialPort, SerialPortSettings};
+
+pub const BAUD_RATE: u32 = 115_200;
+
+/// Seconds between 1970-01-01 and 2000-01-01, where the device clock starts
+pub const EPOCH_2000: u64 = 946_684_800;
+
+/// The name of a measurement category, the number if the band is unknown
+pub fn category_name(category: u8) -> String {
+    Severity::from_category(category)
+        .map_or_else(|| format!("category {}", category), |severity| severity.to_string())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Serial(serialport::Error),
+    Protocol(protocol::Error),
+    /// The device answered with an error
+    Device(ErrorCode),
+    /// The device answered with the wrong kind of response
+    Unexpected(Response),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Serial(e) => write!(f, "serial port error: {}", e),
+            Error::Protocol(e) => write!(f, "bad frame: {:?}", e),
+            Error::Device(code) => write!(f, "device error: {:?}", code),
+            Error::Unexpected(response) => write!(f, "unexpected response: {:?}", response),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Error {
+        Error::Serial(e)
+    }
+}
+
+impl From<protocol::Error> for Error {
+    fn from(e: protocol::Error) -> Error {
+        Error::Protocol(e)
+    }
+}
+
+pub struct Device {
+    port: Box<dyn SerialPort>,
+}
+
+impl Device {
+    /// Opens a serial port, or the pty printed by `fake_device`
+    pub fn open(path: &str) -> Result<Device, Error> {
+        let settings = SerialPortSettings {
+            baud_rate: BAUD_RATE,
+            timeout: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let port = serialport::open_with_settings(path, &settings)?;
+        Ok(Device { port })
+    }
+
+    /// Sends a request and waits for the response. Error responses are
+    /// returned as `Error::Device`.
+    pub fn request(&mut self, request: &Request) -> Result<Response, Error> {
+        write_frame(&mut self.port, request)?;
+
+        let mut frame = read_frame(&mut self.port)?;
+        match protocol::decode(&mut frame)? {
+            Response::Error(code) => Err(Error::Device(code)),
+            response => Ok(response),
+        }
+    }
+
+    /// Sends a request that is answered with `Done`
+    pub fn command(&mut self, request: &Request) -> Result<(), Error> {
+        match self.request(request)? {
+            Response::Done => Ok(()),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    /// Sends any message and waits for the answer, for the bootloader
+    pub fn exchange<T: Serialize, R: DeserializeOwned>(&mut self, message: &T) -> Result<R, Error> {
+        write_frame(&mut self.port, message)?;
+        let mut frame = read_frame(&mut self.port)?;
+        Ok(protocol::decode(&mut frame)?)
+    }
+
+    /// Sends a console command
+    pub fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        self.port.write_all(line.as_bytes())?;
+        self.port.write_all(b"\n")?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        Ok(self.port.set_timeout(timeout)?)
+    }
+}
+
+/// Reads the next zero delimited frame, text in between is skipped
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut frame = Vec::new();
+    let mut in_frame = false;
+    let mut byte = [0];
+
+    loop {
+        reader.read_exact(&mut byte)?;
+        match (in_frame, byte[0]) {
+            (false, 0) => in_frame = true,
+            (false, _) => {}
+            (true, 0) if frame.is_empty() => {}
+            (true, 0) => return Ok(frame),
+            (true, b) => frame.push(b),
+        }
+    }
+}
+
+/// Writes a message with the delimiters
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<(), Error> {
+    let mut buf = [0; MAX_FRAME];
+    let encoded = protocol::encode(message, &mut buf)?;
+    writer.write_all(&[0])?;
+    writer.write_all(encoded)?;
+    writer.flush()?;
+    Ok(())
+}
@@ -1,11 +1,16 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 192K
-  RAM : ORIGIN = 0x20000000, LENGTH = 20K
+  /* Slot A after the bootloader, see boot/src/layout.rs */
+  FLASH : ORIGIN = 0x08008000, LENGTH = 80K
+  RAM : ORIGIN = 0x20000000, LENGTH = 20K - 256
+  /* Not initialised at boot, keeps the crash record over a reset */
+  CRASH : ORIGIN = 0x20004F00, LENGTH = 256
 }
 
 /* This is where the call stack will be allocated. */
 /* The stack is of the full descending type. */
 /* NOTE Do NOT modify `_stack_start` unless you know what you are doing */
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
+
+_crash_record = ORIGIN(CRASH);
@@ -0,0 +1,375 @@
+//! Alert uplink sent when a result reaches the alert threshold.
+//!
+//! Alerts are kept apart from the routine telemetry: the payload starts with
+//! `ALERT_MARKER` instead of a telemetry version, followed by the postcard
+//! encoding of `Alert`. The device sends it again, with the same id, until
+//! the server answers with the signed `Command::AcknowledgeAlert`, see
+//! `Outbox`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::severity::Severity;
+use crate::MAX_PROFILES;
+
+/// Above any telemetry version, so the two can't be confused
+pub const ALERT_MARKER: u8 = 0xA1;
+
+/// LoRaWAN port of alerts
+pub const ALERT_PORT: u8 = 4;
+
+/// Largest encoded payload
+pub const MAX_ALERT: usize = 20;
+
+/// First wait for the acknowledgement, doubled after every attempt
+pub const RETRY_S: u16 = 60;
+pub const MAX_RETRY_S: u16 = 900;
+
+/// Sends of an alert before it is given up on, a little over two hours
+/// with the backoff
+pub const MAX_ATTEMPTS: u8 = 12;
+
+/// A worse band is always alerted, the same one again only after this
+pub const REPEAT_AFTER_S: u32 = 1800;
+
+/// Alerts waiting for an acknowledgement at once
+pub const MAX_PENDING: usize = 4;
+
+// Id, attempt, category, BAC, profile and time, an id of 0 is empty
+const STORED_ALERT_SIZE: usize = 2 + 1 + 1 + 2 + 1 + 4;
+/// Size of `Outbox::store`, the next id and the pending alerts
+pub const STORED_SIZE: usize = 2 + MAX_PENDING * STORED_ALERT_SIZE;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Alert {
+    /// The same in every attempt, what the acknowledgement refers to
+    pub id: u16,
+    /// Times it was sent before, 0 the first time
+    pub attempt: u8,
+    /// Index of the band, see `severity`
+    pub category: u8,
+    /// Blood alcohol content in 0.01 per mille, `telemetry::BAC_UNKNOWN`
+    /// without calibration
+    pub bac: u16,
+    /// Who blew, 0 is the guest
+    pub profile: u8,
+    /// Seconds since 2000-01-01 of the measurement
+    pub time: u32,
+}
+
+impl Alert {
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], postcard::Error> {
+        if buf.is_empty() {
+            return Err(postcard::Error::SerializeBufferFull);
+        }
+        buf[0] = ALERT_MARKER;
+        let len = postcard::to_slice(self, &mut buf[1..])?.len();
+        Ok(&buf[..1 + len])
+    }
+
+    /// `None` unless the payload is an alert
+    pub fn decode(payload: &[u8]) -> Option<Alert> {
+        match payload.split_first() {
+            Some((&ALERT_MARKER, rest)) => postcard::from_bytes(rest).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Pending {
+    alert: Alert,
+    wait_s: u16,
+    retry_s: u16,
+}
+
+/// What became of an alert as time passed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tick {
+    /// Due to be sent again
+    Retry(Alert),
+    /// Sent `MAX_ATTEMPTS` times without an answer, it is dropped
+    GaveUp(Alert),
+}
+
+/// The alerts of a device: which are waiting for an acknowledgement, when
+/// they go out again, and which results were alerted recently. A profile
+/// isn't alerted again for the same or a lower band within
+/// `REPEAT_AFTER_S`, so blowing again right away doesn't flood the contact.
+pub struct Outbox {
+    /// Not acknowledged yet
+    pending: [Option<Pending>; MAX_PENDING],
+    /// Band and time of the newest alert of each profile
+    last: [Option<(u8, u32)>; MAX_PROFILES + 1],
+    next_id: u16,
+}
+
+impl Outbox {
+    pub fn new() -> Outbox {
+        Outbox {
+            pending: [None; MAX_PENDING],
+            last: [None; MAX_PROFILES + 1],
+            next_id: 1,
+        }
+    }
+
+    /// The alert to send for a result of `profile` at `time`, seconds since
+    /// 2000-01-01, or `None` if one was sent for it recently
+    pub fn raise(&mut self, severity: Severity, bac: u16, profile: u8, time: u32) -> Option<Alert> {
+        let category = severity.category();
+        let last = self.last.get_mut(profile as usize)?;
+        if let Some((band, last_time)) = *last {
+            if category <= band && time.saturating_sub(last_time) < REPEAT_AFTER_S {
+                return None;
+            }
+        }
+        *last = Some((category, time));
+
+        let alert = Alert {
+            id: self.next_id,
+            attempt: 0,
+            category: category,
+            bac: bac,
+            profile: profile,
+            time: time,
+        };
+        // 0 marks a free slot in `store`
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        // The oldest gives way if the server hasn't answered any of them
+        let slot = match self.pending.iter().position(|pending| pending.is_none()) {
+            Some(free) => free,
+            None => (0..MAX_PENDING)
+                .min_by_key(|i| self.pending[*i].map_or(0, |pending| pending.alert.time))
+                .unwrap_or(0),
+        };
+        self.pending[slot] = Some(Pending {
+            alert: alert,
+            wait_s: 0,
+            retry_s: RETRY_S,
+        });
+        Some(alert)
+    }
+
+    /// Called as time passes, `elapsed_s` since the last call. At most one
+    /// alert is due at a time, the others wait for the next call.
+    pub fn tick(&mut self, elapsed_s: u16) -> Option<Tick> {
+        let mut due = None;
+        for slot in self.pending.iter_mut() {
+            let pending = match slot {
+                Some(pending) => pending,
+                None => continue,
+            };
+            pending.wait_s = pending.wait_s.saturating_add(elapsed_s);
+            if due.is_some() || pending.wait_s < pending.retry_s {
+                continue;
+            }
+
+            if pending.alert.attempt >= MAX_ATTEMPTS - 1 {
+                due = Some(Tick::GaveUp(pending.alert));
+                *slot = None;
+            } else {
+                pending.wait_s = 0;
+                pending.retry_s = (pending.retry_s * 2).min(MAX_RETRY_S);
+                pending.alert.attempt += 1;
+                due = Some(Tick::Retry(pending.alert));
+            }
+        }
+        due
+    }
+
+    /// Stops the retries of `id`, `None` if it isn't waiting for an answer
+    pub fn acknowledge(&mut self, id: u16) -> Option<Alert> {
+        let slot = self
+            .pending
+            .iter_mut()
+            .find(|pending| pending.map_or(false, |pending| pending.alert.id == id))?;
+        slot.take().map(|pending| pending.alert)
+    }
+
+    /// The next id and the pending alerts, for the firmware to keep across
+    /// resets. The waits and when each profile was alerted are left out.
+    pub fn store(&self) -> [u8; STORED_SIZE] {
+        let mut bytes = [0; STORED_SIZE];
+        bytes[..2].copy_from_slice(&self.next_id.to_le_bytes());
+
+        let entries = bytes[2..].chunks_mut(STORED_ALERT_SIZE);
+        for (entry, pending) in entries.zip(self.pending.iter()) {
+            if let Some(pending) = pending {
+                let alert = &pending.alert;
+                entry[..2].copy_from_slice(&alert.id.to_le_bytes());
+                entry[2] = alert.attempt;
+                entry[3] = alert.category;
+                entry[4..6].copy_from_slice(&alert.bac.to_le_bytes());
+                entry[6] = alert.profile;
+                entry[7..11].copy_from_slice(&alert.time.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// From `store`, the retries of the pending alerts start over
+    pub fn restore(bytes: &[u8; STORED_SIZE]) -> Outbox {
+        let mut outbox = Outbox::new();
+        outbox.next_id = u16::from_le_bytes([bytes[0], bytes[1]]).max(1);
+
+        let entries = bytes[2..].chunks(STORED_ALERT_SIZE);
+        for (entry, pending) in entries.zip(outbox.pending.iter_mut()) {
+            let id = u16::from_le_bytes([entry[0], entry[1]]);
+            if id != 0 {
+                *pending = Some(Pending {
+                    alert: Alert {
+                        id: id,
+                        attempt: entry[2],
+                        category: entry[3],
+                        bac: u16::from_le_bytes([entry[4], entry[5]]),
+                        profile: entry[6],
+                        time: u32::from_le_bytes([entry[7], entry[8], entry[9], entry[10]]),
+                    },
+                    wait_s: 0,
+                    retry_s: RETRY_S,
+                });
+            }
+        }
+        outbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn severe() -> Severity {
+        Severity::from_category(5).unwrap()
+    }
+
+    fn high() -> Severity {
+        Severity::from_category(3).unwrap()
+    }
+
+    fn retry(tick: Option<Tick>) -> Option<Alert> {
+        match tick {
+            Some(Tick::Retry(alert)) => Some(alert),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let alert = Alert {
+            id: 12,
+            attempt: 2,
+            category: 5,
+            bac: 91,
+            profile: 1,
+            time: 700_000_000,
+        };
+        let mut buf = [0; MAX_ALERT];
+        let payload = alert.encode(&mut buf).unwrap();
+        assert_eq!(payload[0], ALERT_MARKER);
+        assert_eq!(Alert::decode(payload), Some(alert));
+        assert_eq!(Alert::decode(&payload[1..]), None);
+    }
+
+    #[test]
+    fn the_same_band_isnt_alerted_again_soon() {
+        let mut outbox = Outbox::new();
+
+        let first = outbox.raise(high(), 60, 1, 1000).unwrap();
+        assert_eq!((first.id, first.attempt), (1, 0));
+        assert!(outbox.raise(high(), 60, 1, 1000 + REPEAT_AFTER_S - 1).is_none());
+
+        // Someone else, a worse band or after a while
+        assert_eq!(outbox.raise(high(), 60, 2, 1100).map(|a| a.id), Some(2));
+        assert_eq!(outbox.raise(severe(), 90, 1, 1200).map(|a| a.id), Some(3));
+        assert!(outbox.raise(high(), 60, 1, 1300).is_none());
+        assert_eq!(outbox.raise(high(), 60, 1, 1200 + REPEAT_AFTER_S).map(|a| a.id), Some(4));
+    }
+
+    #[test]
+    fn unknown_profiles_arent_alerted() {
+        assert!(Outbox::new().raise(high(), 60, MAX_PROFILES as u8 + 1, 0).is_none());
+    }
+
+    #[test]
+    fn retries_back_off_until_acknowledged() {
+        let mut outbox = Outbox::new();
+        let id = outbox.raise(high(), 60, 1, 1000).unwrap().id;
+
+        assert!(outbox.tick(56).is_none());
+        let again = retry(outbox.tick(4)).unwrap();
+        assert_eq!((again.id, again.attempt), (id, 1));
+
+        // Then after 2 and 4 minutes, in ticks of 4 seconds
+        let mut waited = 0;
+        while outbox.tick(4).is_none() {
+            waited += 4;
+        }
+        assert_eq!(waited, 116);
+        assert!(outbox.tick(236).is_none());
+        assert_eq!(retry(outbox.tick(4)).map(|a| a.attempt), Some(3));
+
+        assert_eq!(outbox.acknowledge(id).map(|a| a.id), Some(id));
+        assert!(outbox.acknowledge(id).is_none());
+        assert!(outbox.tick(MAX_RETRY_S).is_none());
+    }
+
+    #[test]
+    fn alerts_are_given_up_on() {
+        let mut outbox = Outbox::new();
+        let alert = outbox.raise(high(), 60, 1, 1000).unwrap();
+
+        for attempt in 1..MAX_ATTEMPTS {
+            assert_eq!(retry(outbox.tick(MAX_RETRY_S)).map(|a| a.attempt), Some(attempt));
+        }
+        assert!(outbox.tick(MAX_RETRY_S - 1).is_none());
+        let last = Alert { attempt: MAX_ATTEMPTS - 1, ..alert };
+        assert_eq!(outbox.tick(1), Some(Tick::GaveUp(last)));
+        assert!(outbox.tick(MAX_RETRY_S).is_none());
+        assert!(outbox.acknowledge(alert.id).is_none());
+    }
+
+    #[test]
+    fn one_alert_is_due_at_a_time() {
+        let mut outbox = Outbox::new();
+        outbox.raise(high(), 60, 1, 1000).unwrap();
+        outbox.raise(high(), 60, 2, 1000).unwrap();
+
+        assert_eq!(retry(outbox.tick(RETRY_S)).map(|a| a.id), Some(1));
+        assert_eq!(retry(outbox.tick(0)).map(|a| a.id), Some(2));
+        assert!(outbox.tick(0).is_none());
+    }
+
+    #[test]
+    fn the_oldest_gives_way_when_full() {
+        let mut outbox = Outbox::new();
+        for profile in 0..=MAX_PROFILES as u8 {
+            outbox.raise(high(), 60, profile, 1000 + profile as u32).unwrap();
+        }
+        assert!(outbox.acknowledge(1).is_none());
+        for id in 2..=MAX_PENDING as u16 + 1 {
+            assert!(outbox.acknowledge(id).is_some());
+        }
+    }
+
+    #[test]
+    fn pending_alerts_and_ids_are_restored() {
+        let mut outbox = Outbox::restore(&[0; STORED_SIZE]);
+        outbox.raise(high(), 60, 1, 1000).unwrap();
+        let second = outbox.raise(severe(), 90, 2, 1100).unwrap();
+        outbox.acknowledge(1).unwrap();
+
+        let mut outbox = Outbox::restore(&outbox.store());
+        assert!(outbox.tick(RETRY_S - 1).is_none());
+        assert_eq!(retry(outbox.tick(1)), Some(Alert { attempt: 1, ..second }));
+        assert_eq!(outbox.raise(high(), 60, 3, 1200).map(|a| a.id), Some(3));
+        assert_eq!(outbox.store()[..2], 4u16.to_le_bytes());
+    }
+
+    #[test]
+    fn ids_skip_zero() {
+        let mut outbox = Outbox::new();
+        outbox.next_id = u16::max_value();
+        assert_eq!(outbox.raise(high(), 60, 1, 0).map(|a| a.id), Some(u16::max_value()));
+        assert_eq!(outbox.raise(high(), 60, 2, 0).map(|a| a.id), Some(1));
+    }
+}
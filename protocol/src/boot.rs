@@ -0,0 +1,44 @@
+//! Messages between the bootloader and `firmware flash` on the serial port.
+//!
+//! Framed like the console requests. For a second after every reset the
+//! bootloader waits for `Hello`, and it keeps waiting if there is no
+//! firmware to start. The image is sent in the fragments of the radio
+//! updates, in order, and installed like one once its signature checks out.
+
+use heapless::{consts::*, Vec};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum BootRequest {
+    /// Keeps the bootloader from starting the firmware
+    Hello,
+    Start {
+        version: u32,
+        size: u32,
+        signature: ([u8; 32], [u8; 32]),
+    },
+    /// A frame from `update::encode_fragment`
+    Fragment(Vec<u8, U64>),
+    /// Checks the signature and installs the image, then starts it
+    Install,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum BootError {
+    BadRequest,
+    TooLarge,
+    /// Corrupt, or not the next one
+    BadFragment,
+    Incomplete,
+    BadSignature,
+    Flash,
+    /// Not newer than the installed firmware
+    Outdated,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum BootResponse {
+    Ready { slot_size: u32 },
+    Done,
+    Error(BootError),
+}
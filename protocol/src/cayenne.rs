@@ -0,0 +1,223 @@
+//! Cayenne LPP encoding of the telemetry, for network servers and
+//! ThingsBoard integrations that only understand standard formats.
+//!
+//! Every value is a channel byte, a type byte and a big endian value.
+
+use heapless::{consts::*, Vec};
+
+use crate::telemetry::{Telemetry, BAC_UNKNOWN};
+
+pub const DIGITAL_INPUT: u8 = 0;
+pub const ANALOG_INPUT: u8 = 2;
+pub const TEMPERATURE: u8 = 103;
+
+/// Channel numbers and the names the host gives them
+pub mod channel {
+    pub const BAC: u8 = 1;
+    pub const CATEGORY: u8 = 2;
+    pub const RATIO: u8 = 3;
+    pub const TEMPERATURE: u8 = 4;
+    pub const BATTERY_V: u8 = 5;
+    pub const BATTERY_PERCENT: u8 = 6;
+    pub const FAULTS: u8 = 7;
+    pub const RSSI: u8 = 8;
+    pub const SNR: u8 = 9;
+    pub const PROFILE: u8 = 10;
+
+    pub const NAMES: [(u8, &str); 10] = [
+        (BAC, "bac"),
+        (CATEGORY, "category"),
+        (RATIO, "ratio"),
+        (TEMPERATURE, "temperature"),
+        (BATTERY_V, "battery_v"),
+        (BATTERY_PERCENT, "battery_percent"),
+        (FAULTS, "faults"),
+        (RSSI, "rssi"),
+        (SNR, "snr"),
+        (PROFILE, "profile"),
+    ];
+}
+
+/// One decoded value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LppValue {
+    pub channel: u8,
+    pub kind: u8,
+    /// The value as sent, before applying `scale`
+    pub raw: i32,
+}
+
+impl LppValue {
+    /// Divide `raw` by this to get the value
+    pub fn scale(&self) -> i32 {
+        match self.kind {
+            ANALOG_INPUT => 100,
+            TEMPERATURE => 10,
+            _ => 1,
+        }
+    }
+}
+
+pub type Payload = Vec<u8, U48>;
+
+/// Encodes a telemetry payload, BAC is left out without calibration. Fails
+/// if the values don't fit `Payload`.
+pub fn encode(t: &Telemetry) -> Result<Payload, ()> {
+    let mut payload = Payload::new();
+
+    if t.bac != BAC_UNKNOWN {
+        // Per mille with two decimals, the same resolution as `bac`
+        push(&mut payload, channel::BAC, ANALOG_INPUT, &(t.bac as i16).to_be_bytes())?;
+    }
+    push(&mut payload, channel::CATEGORY, DIGITAL_INPUT, &[t.category])?;
+    if t.baseline != 0 {
+        let ratio = (t.raw as i32 * 10_000 / t.baseline as i32).min(i16::MAX as i32) as i16;
+        push(&mut payload, channel::RATIO, ANALOG_INPUT, &ratio.to_be_bytes())?;
+    }
+    push(
+        &mut payload,
+        channel::TEMPERATURE,
+        TEMPERATURE,
+        &(t.temperature as i16 * 10).to_be_bytes(),
+    )?;
+    push(
+        &mut payload,
+        channel::BATTERY_V,
+        ANALOG_INPUT,
+        &((t.battery_mv / 10) as i16).to_be_bytes(),
+    )?;
+    push(
+        &mut payload,
+        channel::BATTERY_PERCENT,
+        ANALOG_INPUT,
+        &(t.battery_percent as i16 * 100).to_be_bytes(),
+    )?;
+    push(&mut payload, channel::FAULTS, DIGITAL_INPUT, &[t.faults])?;
+    push(&mut payload, channel::PROFILE, DIGITAL_INPUT, &[t.profile])?;
+    if let Some(link) = t.link {
+        push(&mut payload, channel::RSSI, ANALOG_INPUT, &link.rssi.saturating_mul(100).to_be_bytes())?;
+        push(&mut payload, channel::SNR, ANALOG_INPUT, &(link.snr as i16 * 100).to_be_bytes())?;
+    }
+
+    Ok(payload)
+}
+
+fn push(payload: &mut Payload, channel: u8, kind: u8, value: &[u8]) -> Result<(), ()> {
+    payload.push(channel).map_err(|_| ())?;
+    payload.push(kind).map_err(|_| ())?;
+    payload.extend_from_slice(value)
+}
+
+/// Decodes the types used by `encode`
+pub fn decode(payload: &[u8]) -> Result<Vec<LppValue, U16>, ()> {
+    let mut values = Vec::new();
+    let mut rest = payload;
+
+    while rest.len() >= 2 {
+        let (channel, kind) = (rest[0], rest[1]);
+        let size = match kind {
+            DIGITAL_INPUT => 1,
+            ANALOG_INPUT | TEMPERATURE => 2,
+            _ => return Err(()),
+        };
+        if rest.len() < 2 + size {
+            return Err(());
+        }
+
+        let raw = match size {
+            1 => rest[2] as i32,
+            _ => i16::from_be_bytes([rest[2], rest[3]]) as i32,
+        };
+        values.push(LppValue { channel, kind, raw }).map_err(|_| ())?;
+        rest = &rest[2 + size..];
+    }
+
+    if rest.is_empty() {
+        Ok(values)
+    } else {
+        Err(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::{fault, LinkStats, TELEMETRY_VERSION};
+
+    fn telemetry() -> Telemetry {
+        Telemetry {
+            version: TELEMETRY_VERSION,
+            seq: 7,
+            uptime: 3600,
+            bac: 42,
+            category: 2,
+            raw: 2900,
+            baseline: 3400,
+            temperature: -5,
+            battery_mv: 3710,
+            battery_percent: 64,
+            faults: fault::BATTERY_LOW,
+            link: Some(LinkStats { rssi: -97, snr: -4, ..LinkStats::default() }),
+            profile: 3,
+            reset: None,
+            crash: None,
+        }
+    }
+
+    fn value(values: &[LppValue], channel: u8) -> Option<i32> {
+        values.iter().find(|value| value.channel == channel).map(|value| value.raw)
+    }
+
+    #[test]
+    fn round_trip() {
+        let payload = encode(&telemetry()).unwrap();
+        let values = decode(&payload).unwrap();
+
+        assert_eq!(value(&values, channel::BAC), Some(42));
+        assert_eq!(value(&values, channel::CATEGORY), Some(2));
+        assert_eq!(value(&values, channel::RATIO), Some(8529));
+        assert_eq!(value(&values, channel::TEMPERATURE), Some(-50));
+        assert_eq!(value(&values, channel::BATTERY_V), Some(371));
+        assert_eq!(value(&values, channel::BATTERY_PERCENT), Some(6400));
+        assert_eq!(value(&values, channel::FAULTS), Some(fault::BATTERY_LOW as i32));
+        assert_eq!(value(&values, channel::PROFILE), Some(3));
+        assert_eq!(value(&values, channel::RSSI), Some(-9700));
+        assert_eq!(value(&values, channel::SNR), Some(-400));
+    }
+
+    #[test]
+    fn optional_channels_are_left_out() {
+        let t = Telemetry { bac: BAC_UNKNOWN, baseline: 0, link: None, ..telemetry() };
+        let values = decode(&encode(&t).unwrap()).unwrap();
+
+        assert_eq!(values.len(), 6);
+        assert_eq!(value(&values, channel::BAC), None);
+        assert_eq!(value(&values, channel::RATIO), None);
+        assert_eq!(value(&values, channel::RSSI), None);
+    }
+
+    #[test]
+    fn extremes_saturate() {
+        let t = Telemetry {
+            raw: u16::MAX,
+            baseline: 1,
+            link: Some(LinkStats { rssi: i16::MIN, ..LinkStats::default() }),
+            ..telemetry()
+        };
+        let values = decode(&encode(&t).unwrap()).unwrap();
+
+        assert_eq!(value(&values, channel::RATIO), Some(i16::MAX as i32));
+        assert_eq!(value(&values, channel::RSSI), Some(i16::MIN as i32));
+    }
+
+    #[test]
+    fn bad_payloads() {
+        assert_eq!(decode(&[]), Ok(Vec::new()));
+        // Unknown type
+        assert_eq!(decode(&[channel::BAC, 1, 0]), Err(()));
+        // Value cut short
+        assert_eq!(decode(&[channel::BAC, ANALOG_INPUT, 0]), Err(()));
+        // A lone channel byte
+        assert_eq!(decode(&[channel::CATEGORY, DIGITAL_INPUT, 1, channel::BAC]), Err(()));
+    }
+}
@@ -0,0 +1,343 @@
+//! Remote commands sent as downlinks, and the replies the device sends back.
+//!
+//! A frame is a marker byte, a little endian counter, the postcard encoding
+//! of the message and a 4 byte AES-CMAC over everything before it. The
+//! command key is derived from the provisioned AppKey, so only whoever
+//! provisioned the device can command it. The device only accepts counters
+//! above the last accepted one, so recorded frames can't be replayed. A
+//! reply carries the counter of its command.
+
+use aes::{Aes128, BlockCipher, NewBlockCipher};
+use cmac::{Cmac, Mac, NewMac};
+use heapless::{consts::*, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::update::UpdateStatus;
+use crate::{CalibrationPoint, HistoryRecord};
+
+pub const COMMAND_MARKER: u8 = 0xC1;
+pub const REPLY_MARKER: u8 = 0xC2;
+
+/// LoRaWAN port of commands and replies, LongFi tells them apart by the marker
+pub const COMMAND_PORT: u8 = 2;
+
+/// Largest frame, what fits in a LoRaWAN uplink at the slowest data rate
+pub const MAX_COMMAND: usize = 51;
+
+const HEADER_SIZE: usize = 5;
+pub(crate) const MAC_SIZE: usize = 4;
+
+/// Records in one `Reply::History`
+pub const REPLY_RECORDS: u8 = 2;
+
+pub type Key = [u8; 16];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Blood alcohol content in 0.01 per mille from which a result is over the limit
+    SetLegalLimit { bac: u16 },
+    /// Minutes between status uplinks, 0 turns them off
+    SetReportInterval { minutes: u16 },
+    /// Replaces all calibration points, more can be loaded over the console
+    SetCalibration { points: Vec<CalibrationPoint, U4> },
+    /// Seconds since 2000-01-01 00:00:00 UTC
+    SetTime { seconds: u32 },
+    /// Up to `REPLY_RECORDS` records starting `start` records back from the newest
+    GetHistory { start: u32, count: u8 },
+    /// A disabled device refuses to measure
+    SetEnabled { enabled: bool },
+    SelfTest,
+    /// Starts receiving a firmware update, see the update module
+    UpdateStart { version: u32, size: u32 },
+    /// Half of the image signature, `half` 0 or 1
+    UpdateSignature { half: u8, bytes: [u8; 32] },
+    /// Asks how far the update has come
+    UpdateStatus,
+    /// Checks the signature and installs the update at the next reset
+    UpdateApply,
+    /// Starts a measurement, `requester` is shown on the display
+    Measure { requester: u16 },
+    /// The alert with `id` reached the contact, stops the retries
+    AcknowledgeAlert { id: u16 },
+}
+
+impl Command {
+    /// Number of the command for logs, in the order of the enum
+    pub fn kind(&self) -> u8 {
+        match self {
+            Command::SetLegalLimit { .. } => 0,
+            Command::SetReportInterval { .. } => 1,
+            Command::SetCalibration { .. } => 2,
+            Command::SetTime { .. } => 3,
+            Command::GetHistory { .. } => 4,
+            Command::SetEnabled { .. } => 5,
+            Command::SelfTest => 6,
+            Command::UpdateStart { .. } => 7,
+            Command::UpdateSignature { .. } => 8,
+            Command::UpdateStatus => 9,
+            Command::UpdateApply => 10,
+            Command::Measure { .. } => 11,
+            Command::AcknowledgeAlert { .. } => 12,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    /// Not a command this firmware understands
+    Unknown,
+    /// Wrong or missing MAC, or no keys have been provisioned. Anyone could
+    /// have sent the frame, so the device doesn't answer it.
+    Unauthorized,
+    /// The counter was not above the last accepted one
+    Replayed,
+    OutOfRange,
+    Storage,
+    /// Measuring, try again later
+    Busy,
+    /// No update is being received
+    NoUpdate,
+    /// Fragments of the update are missing
+    Incomplete,
+    /// The update is not signed by the firmware key
+    BadSignature,
+    /// Remote measurements are turned off, or the device is disabled
+    Disabled,
+    /// Too soon after the last remote measurement, or the sensor is still
+    /// recovering from the last measurement
+    TooSoon,
+    /// The update is not newer than the installed firmware
+    Outdated,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SelfTestResult {
+    /// `telemetry::fault` bits
+    pub faults: u8,
+    pub raw: u16,
+    pub baseline: u16,
+    /// The heater is on and warmed up
+    pub warm: bool,
+    pub temperature: i8,
+    pub battery_mv: u16,
+    pub keys_provisioned: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Reply {
+    Done,
+    History {
+        total: u32,
+        records: Vec<HistoryRecord, U2>,
+    },
+    SelfTest(SelfTestResult),
+    Update(UpdateStatus),
+    Rejected(Rejection),
+}
+
+/// The key commands and replies are authenticated with, derived from the AppKey
+pub fn command_key(app_key: &Key) -> Key {
+    let mut key = *b"e7020e commands\0";
+    let cipher = Aes128::new(app_key.into());
+    cipher.encrypt_block((&mut key).into());
+    key
+}
+
+pub(crate) fn mac(key: &Key, data: &[u8]) -> [u8; MAC_SIZE] {
+    let mut mac = Cmac::<Aes128>::new_varkey(key).unwrap();
+    mac.update(data);
+    let code = mac.finalize().into_bytes();
+    [code[0], code[1], code[2], code[3]]
+}
+
+fn seal<'a, T: Serialize>(
+    key: &Key,
+    marker: u8,
+    counter: u32,
+    message: &T,
+    buf: &'a mut [u8],
+) -> Result<&'a [u8], postcard::Error> {
+    if buf.len() < HEADER_SIZE + MAC_SIZE {
+        return Err(postcard::Error::SerializeBufferFull);
+    }
+    buf[0] = marker;
+    buf[1..HEADER_SIZE].copy_from_slice(&counter.to_le_bytes());
+
+    let end = buf.len() - MAC_SIZE;
+    let len = HEADER_SIZE + postcard::to_slice(message, &mut buf[HEADER_SIZE..end])?.len();
+    let code = mac(key, &buf[..len]);
+    buf[len..len + MAC_SIZE].copy_from_slice(&code);
+    Ok(&buf[..len + MAC_SIZE])
+}
+
+// Returns the counter with the error as well, 0 if there isn't one. Any
+// error but `Unauthorized` is for a frame with a valid MAC.
+fn open<'a, T: Deserialize<'a>>(
+    key: &Key,
+    marker: u8,
+    frame: &'a [u8],
+) -> Result<(u32, T), (u32, Rejection)> {
+    if frame.len() < HEADER_SIZE + MAC_SIZE || frame[0] != marker {
+        return Err((0, Rejection::Unauthorized));
+    }
+
+    let counter = u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]);
+    let end = frame.len() - MAC_SIZE;
+    if mac(key, &frame[..end]) != frame[end..] {
+        return Err((counter, Rejection::Unauthorized));
+    }
+
+    postcard::from_bytes(&frame[HEADER_SIZE..end])
+        .map(|message| (counter, message))
+        .map_err(|_| (counter, Rejection::Unknown))
+}
+
+pub fn encode_command<'a>(
+    key: &Key,
+    counter: u32,
+    command: &Command,
+    buf: &'a mut [u8],
+) -> Result<&'a [u8], postcard::Error> {
+    seal(key, COMMAND_MARKER, counter, command, buf)
+}
+
+/// Checks the MAC and decodes, the counter is left for the caller to check
+pub fn decode_command(key: &Key, frame: &[u8]) -> Result<(u32, Command), (u32, Rejection)> {
+    open(key, COMMAND_MARKER, frame)
+}
+
+/// Checks the MAC and that the counter is above `last`, the highest one
+/// accepted so far. Counters start at 1, as a new device's `last` is 0.
+pub fn accept_command(
+    key: &Key,
+    last: u32,
+    frame: &[u8],
+) -> Result<(u32, Command), (u32, Rejection)> {
+    let (counter, command) = decode_command(key, frame)?;
+    if counter <= last {
+        return Err((counter, Rejection::Replayed));
+    }
+    Ok((counter, command))
+}
+
+pub fn encode_reply<'a>(
+    key: &Key,
+    counter: u32,
+    reply: &Reply,
+    buf: &'a mut [u8],
+) -> Result<&'a [u8], postcard::Error> {
+    seal(key, REPLY_MARKER, counter, reply, buf)
+}
+
+pub fn decode_reply(key: &Key, frame: &[u8]) -> Result<(u32, Reply), (u32, Rejection)> {
+    open(key, REPLY_MARKER, frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: Key = [7; 16];
+
+    fn sealed<'a>(counter: u32, command: &Command, buf: &'a mut [u8; MAX_COMMAND]) -> &'a [u8] {
+        encode_command(&KEY, counter, command, buf).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; MAX_COMMAND];
+        let command = Command::Measure { requester: 17 };
+        let frame = sealed(42, &command, &mut buf);
+
+        assert_eq!(frame[0], COMMAND_MARKER);
+        assert_eq!(decode_command(&KEY, frame), Ok((42, command)));
+
+        let reply = Reply::Rejected(Rejection::TooSoon);
+        let frame = encode_reply(&KEY, 42, &reply, &mut buf).unwrap();
+        assert_eq!(decode_reply(&KEY, frame), Ok((42, reply)));
+    }
+
+    #[test]
+    fn tampering_is_unauthorized() {
+        let mut buf = [0; MAX_COMMAND];
+        let len = sealed(42, &Command::SetLegalLimit { bac: 20 }, &mut buf).len();
+
+        for i in 0..len {
+            let mut frame = buf;
+            frame[i] ^= 0x01;
+            let rejection = decode_command(&KEY, &frame[..len]).map_err(|(_, rejection)| rejection);
+            assert_eq!(rejection, Err(Rejection::Unauthorized), "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn wrong_key_is_unauthorized() {
+        let mut buf = [0; MAX_COMMAND];
+        let frame = sealed(42, &Command::SelfTest, &mut buf);
+        assert_eq!(decode_command(&[8; 16], frame), Err((42, Rejection::Unauthorized)));
+    }
+
+    #[test]
+    fn short_frames_and_replies_are_unauthorized() {
+        let mut buf = [0; MAX_COMMAND];
+        let frame = sealed(42, &Command::SelfTest, &mut buf);
+        let short = &frame[..HEADER_SIZE + MAC_SIZE - 1];
+        assert_eq!(decode_command(&KEY, short), Err((0, Rejection::Unauthorized)));
+        assert_eq!(decode_command(&KEY, &[]), Err((0, Rejection::Unauthorized)));
+
+        // A reply sent back as a command
+        let mut buf = [0; MAX_COMMAND];
+        let frame = encode_reply(&KEY, 42, &Reply::Done, &mut buf).unwrap();
+        assert_eq!(decode_command(&KEY, frame), Err((0, Rejection::Unauthorized)));
+    }
+
+    #[test]
+    fn unknown_command_keeps_the_counter() {
+        // Authentic, but no such variant
+        let mut buf = [0; MAX_COMMAND];
+        let frame = seal(&KEY, COMMAND_MARKER, 42, &200u8, &mut buf).unwrap();
+        assert_eq!(decode_command(&KEY, frame), Err((42, Rejection::Unknown)));
+    }
+
+    #[test]
+    fn counter_has_to_increase() {
+        let mut buf = [0; MAX_COMMAND];
+        let mut accept = |last, counter| {
+            accept_command(&KEY, last, sealed(counter, &Command::SelfTest, &mut buf))
+        };
+
+        assert_eq!(accept(0, 0), Err((0, Rejection::Replayed)));
+        assert_eq!(accept(0, 1), Ok((1, Command::SelfTest)));
+        assert_eq!(accept(5, 5), Err((5, Rejection::Replayed)));
+        assert_eq!(accept(5, 4), Err((4, Rejection::Replayed)));
+        assert_eq!(accept(5, 9), Ok((9, Command::SelfTest)));
+    }
+
+    #[test]
+    fn forgeries_are_refused_before_the_counter() {
+        let mut buf = [0; MAX_COMMAND];
+        let len = sealed(1000, &Command::SelfTest, &mut buf).len();
+        buf[len - 1] ^= 0xFF;
+        assert_eq!(accept_command(&KEY, 0, &buf[..len]), Err((1000, Rejection::Unauthorized)));
+        assert_eq!(accept_command(&KEY, 2000, &buf[..len]), Err((1000, Rejection::Unauthorized)));
+    }
+
+    #[test]
+    fn mac_is_aes_cmac() {
+        // RFC 4493 example 2
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+        ];
+        let message = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+        ];
+        assert_eq!(mac(&key, &message), [0x07, 0x0a, 0x16, 0xb4]);
+        assert_eq!(mac(&key, &[]), [0xbb, 0x1d, 0x69, 0x29]);
+    }
+
+    #[test]
+    fn command_key_depends_on_the_app_key() {
+        assert_ne!(command_key(&[1; 16]), command_key(&[2; 16]));
+        assert_ne!(command_key(&[1; 16]), [1; 16]);
+    }
+}
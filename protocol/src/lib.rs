@@ -0,0 +1,205 @@
+//! Messages between the breathalyzer and the host tools.
+//!
+//! Requests and responses are serialized with `postcard` and COBS encoded.
+//! On the serial console a frame starts and ends with a zero byte, which
+//! keeps it apart from text commands. Both sides use this crate so the
+//! protocol can't drift.
+
+#![no_std]
+
+use heapless::{consts::*, String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::severity::Band;
+
+pub use postcard::Error;
+
+pub mod alert;
+pub mod boot;
+pub mod cayenne;
+pub mod command;
+pub mod settings;
+pub mod severity;
+pub mod telemetry;
+pub mod update;
+
+/// Largest encoded frame, without the delimiters
+pub const MAX_FRAME: usize = 128;
+
+/// Records in one `History` response
+pub const HISTORY_CHUNK: u8 = 8;
+
+/// Settings in one `Settings` response
+pub const SETTINGS_CHUNK: u8 = 5;
+
+/// Profiles a device stores, ids 1 to this, 0 is the guest
+pub const MAX_PROFILES: usize = 4;
+
+pub type Name = String<U8>;
+
+/// Maps the sensor response to alcohol content, see `Calibration` in the firmware
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationPoint {
+    /// Reading divided by the baseline, in 0.1 %
+    pub ratio: u16,
+    /// Blood alcohol content in 0.01 per mille
+    pub bac: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct HistoryRecord {
+    /// Seconds since 2000-01-01 00:00:00
+    pub time: u32,
+    pub raw: u16,
+    pub baseline: u16,
+    pub category: u8,
+    pub flags: u8,
+    /// 0 is the guest profile
+    pub profile: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Setting {
+    pub name: Name,
+    pub value: u16,
+    pub min: u16,
+    pub max: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Info {
+    pub version: String<U16>,
+    pub uptime: u32,
+    /// Seconds since 2000-01-01 according to the device clock
+    pub clock: u32,
+    pub battery_mv: u16,
+    pub history_len: u32,
+    pub clean_air: u16,
+    pub calibration_points: u8,
+    pub keys_provisioned: bool,
+    /// The 96 bit unique id of the MCU
+    pub uid: [u8; 12],
+    pub oui: u32,
+    pub device_id: u16,
+    /// False if `device_id` is derived from `uid`
+    pub identity_provisioned: bool,
+    /// Readout protection level, 0 leaves the keys readable over SWD
+    pub readout_protection: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Request {
+    Info,
+    /// Up to `SETTINGS_CHUNK` settings starting with the `start`:th
+    GetSettings {
+        start: u8,
+    },
+    SetSetting {
+        name: Name,
+        value: u16,
+    },
+    /// Up to `count` records starting `start` records back from the newest
+    History {
+        start: u32,
+        count: u8,
+    },
+    SetCalibration {
+        points: Vec<CalibrationPoint, U8>,
+    },
+    /// Refused once keys are stored unless `current_key` is the stored
+    /// app key
+    ProvisionKeys {
+        dev_eui: [u8; 8],
+        app_eui: [u8; 8],
+        app_key: [u8; 16],
+        current_key: Option<[u8; 16]>,
+    },
+    SetClock {
+        seconds: u32,
+    },
+    /// Used for the radio from the next reset, refused like `ProvisionKeys`
+    ProvisionIdentity {
+        oui: u32,
+        device_id: u16,
+        current_key: Option<[u8; 16]>,
+    },
+    /// All severity bands, where they start is in the settings
+    Bands,
+    SetBand {
+        index: u8,
+        band: Band,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    BadRequest,
+    UnknownSetting,
+    OutOfRange,
+    Storage,
+    Busy,
+    /// Keys are stored and the request didn't come with them
+    Unauthorized,
+    /// Keys aren't stored while the readout protection is at level 0
+    Unprotected,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Response {
+    Info(Info),
+    Settings {
+        total: u8,
+        settings: Vec<Setting, U8>,
+    },
+    History {
+        total: u32,
+        records: Vec<HistoryRecord, U8>,
+    },
+    Bands(Vec<Band, U6>),
+    Done,
+    Error(ErrorCode),
+}
+
+/// Encodes a message into `buf`, the returned frame ends with the zero delimiter
+pub fn encode<'a, T: Serialize>(message: &T, buf: &'a mut [u8]) -> Result<&'a mut [u8], Error> {
+    postcard::to_slice_cobs(message, buf)
+}
+
+/// Decodes a frame in place, with or without the zero delimiter
+pub fn decode<'a, T: Deserialize<'a>>(frame: &'a mut [u8]) -> Result<T, Error> {
+    postcard::from_bytes_cobs(frame)
+}
+
+/// Keys and identity can be provisioned until keys are stored, after that
+/// only with the stored app key as `current_key`
+pub fn may_provision(stored_key: Option<&[u8; 16]>, current_key: Option<&[u8; 16]>) -> bool {
+    match (stored_key, current_key) {
+        (None, _) => true,
+        (Some(stored), Some(current)) => same_key(stored, current),
+        (Some(_), None) => false,
+    }
+}
+
+// In the same time wherever they differ
+fn same_key(a: &[u8; 16], b: &[u8; 16]) -> bool {
+    a.iter().zip(b.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provisioning_needs_the_stored_key() {
+        let stored = [3; 16];
+        assert!(may_provision(None, None));
+        assert!(may_provision(None, Some(&[9; 16])));
+        assert!(!may_provision(Some(&stored), None));
+        assert!(!may_provision(Some(&stored), Some(&[9; 16])));
+        assert!(may_provision(Some(&stored), Some(&[3; 16])));
+
+        let mut key = [3; 16];
+        key[15] = 4;
+        assert!(!may_provision(Some(&stored), Some(&key)));
+    }
+}
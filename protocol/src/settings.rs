@@ -0,0 +1,106 @@
+//! The device settings. The firmware stores the values in the order of
+//! `SETTINGS_INFO`, so settings are only ever appended unless the firmware
+//! migrates the old order, and the host tools show and check them with the
+//! same names and limits.
+
+use crate::severity::{BAND_COUNT, DEFAULT_STARTS};
+
+pub struct SettingInfo {
+    pub name: &'static str,
+    pub default: u16,
+    pub min: u16,
+    pub max: u16,
+    pub help: &'static str,
+}
+
+const fn setting(
+    name: &'static str,
+    default: u16,
+    min: u16,
+    max: u16,
+    help: &'static str,
+) -> SettingInfo {
+    SettingInfo {
+        name: name,
+        default: default,
+        min: min,
+        max: max,
+        help: help,
+    }
+}
+
+pub const SETTINGS_INFO: [SettingInfo; 20] = [
+    setting("dim", 20, 5, 3600, "seconds before the display dims"),
+    setting("off", 60, 5, 3600, "seconds before the display turns off"),
+    setting("heater", 120, 30, 3600, "idle seconds before the heater turns off"),
+    setting("rx", 30, 0, 600, "seconds to listen for downlinks after a send"),
+    setting("blow", 3, 1, 10, "seconds to blow for a measurement"),
+    // The Swedish limit for driving, 0.2 per mille
+    setting("limit", 20, 1, 500, "legal limit in 0.01 per mille"),
+    setting("report", 0, 0, 1440, "minutes between status uplinks, 0 for none"),
+    setting("enabled", 1, 0, 1, "0 refuses to measure"),
+    setting("trigger", 5, 0, 1440, "minutes between remote measurements, 0 refuses them"),
+    setting("cooldown", 30, 10, 600, "least seconds between measurements"),
+    // Each one has to be between the ones around it
+    setting("band1", DEFAULT_STARTS[0], 1, 99, "percent below the baseline where band 1 starts"),
+    setting("band2", DEFAULT_STARTS[1], 1, 99, "percent below the baseline where band 2 starts"),
+    setting("band3", DEFAULT_STARTS[2], 1, 99, "percent below the baseline where band 3 starts"),
+    setting("band4", DEFAULT_STARTS[3], 1, 99, "percent below the baseline where band 4 starts"),
+    setting("band5", DEFAULT_STARTS[4], 1, 99, "percent below the baseline where band 5 starts"),
+    // Rough estimates of the current of each power state, replace them with
+    // values measured on the board. STOP is dominated by the LDO quiescent
+    // current, the radio is transmitting at full power.
+    setting("istop", 550, 1, 65535, "current in STOP mode in 10 uA"),
+    setting("iawake", 1200, 1, 65535, "current awake with the display on in 10 uA"),
+    setting("iwarmup", 16000, 1, 65535, "current while the heater warms up in 10 uA"),
+    setting("imeasure", 16500, 1, 65535, "current while measuring in 10 uA"),
+    setting("iradio", 13000, 1, 65535, "current while transmitting in 10 uA"),
+];
+
+/// The setting called `name`
+pub fn find(name: &str) -> Option<&'static SettingInfo> {
+    SETTINGS_INFO.iter().find(|info| info.name == name)
+}
+
+/// The values a `band1` to `band5` setting may take with the bands starting
+/// at `starts`, above the band below and below the band above. `None` for
+/// the other settings.
+pub fn band_start_range(name: &str, starts: &[u16; BAND_COUNT - 1]) -> Option<(u16, u16)> {
+    let i = match name {
+        "band1" => 0,
+        "band2" => 1,
+        "band3" => 2,
+        "band4" => 3,
+        "band5" => 4,
+        _ => return None,
+    };
+    let low = if i == 0 { 0 } else { starts[i - 1] };
+    let high = starts.get(i + 1).map_or(100, |start| *start);
+    Some((low + 1, high.saturating_sub(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique_and_defaults_in_range() {
+        for (i, info) in SETTINGS_INFO.iter().enumerate() {
+            assert!(info.min <= info.default && info.default <= info.max, "{}", info.name);
+            assert_eq!(find(info.name).map(|found| found.name), Some(info.name));
+            assert!(SETTINGS_INFO[i + 1..].iter().all(|other| other.name != info.name));
+        }
+        assert!(find("alert").is_none());
+    }
+
+    #[test]
+    fn band_starts_stay_between_their_neighbours() {
+        assert_eq!(band_start_range("band1", &DEFAULT_STARTS), Some((1, 14)));
+        assert_eq!(band_start_range("band3", &DEFAULT_STARTS), Some((16, 30)));
+        assert_eq!(band_start_range("band5", &DEFAULT_STARTS), Some((32, 99)));
+        assert_eq!(band_start_range("limit", &DEFAULT_STARTS), None);
+
+        // No room left between the two around it
+        assert_eq!(band_start_range("band2", &[10, 15, 11, 31, 39]), Some((11, 10)));
+    }
+}
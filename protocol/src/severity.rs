@@ -0,0 +1,223 @@
+//! Severity bands of a measurement. The category in the history and the
+//! telemetry is the index of the band, so the firmware and the host tools
+//! agree on what it means.
+//!
+//! Each device stores its own bands, see `Request::SetBand`, starting out
+//! as `DEFAULT_BANDS`. Where each one starts is up to the device settings,
+//! see `DEFAULT_STARTS`.
+
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+pub const BAND_COUNT: usize = 6;
+
+pub const MAX_NAME: usize = 10;
+pub const MAX_ICON: usize = 3;
+pub const MAX_BEEPS: u8 = 5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Band {
+    /// ASCII, padded with zeros
+    pub name: [u8; MAX_NAME],
+    /// Drawn in front of the name, the display only has one colour
+    pub icon: [u8; MAX_ICON],
+    /// Beeps once the result is shown
+    pub beeps: u8,
+    /// Readings in the band are worth alerting someone about, see `alert`
+    pub alert: bool,
+}
+
+const fn band(name: [u8; MAX_NAME], icon: [u8; MAX_ICON], beeps: u8, alert: bool) -> Band {
+    Band {
+        name: name,
+        icon: icon,
+        beeps: beeps,
+        alert: alert,
+    }
+}
+
+pub const DEFAULT_BANDS: [Band; BAND_COUNT] = [
+    band(*b"None\0\0\0\0\0\0", [0; 3], 0, false),
+    band(*b"Low\0\0\0\0\0\0\0", [0; 3], 1, false),
+    band(*b"Moderate\0\0", *b"!\0\0", 1, false),
+    band(*b"High\0\0\0\0\0\0", *b"!\0\0", 2, true),
+    band(*b"Very high\0", *b"!!\0", 3, true),
+    band(*b"Severe\0\0\0\0", *b"!!!", 3, true),
+];
+
+/// Where bands 1 and up start, as how far the reading is below the
+/// baseline in percent. Band 0 is everything below the first.
+pub const DEFAULT_STARTS: [u16; BAND_COUNT - 1] = [7, 15, 23, 31, 39];
+
+impl Band {
+    /// `None` unless the name is 1 to `MAX_NAME` and the icon up to
+    /// `MAX_ICON` printable ASCII characters, and there are at most
+    /// `MAX_BEEPS` beeps
+    pub fn new(name: &str, icon: &str, beeps: u8, alert: bool) -> Option<Band> {
+        let printable = |s: &str| s.bytes().all(|c| c.is_ascii_graphic() || c == b' ');
+        if name.is_empty()
+            || name.len() > MAX_NAME
+            || icon.len() > MAX_ICON
+            || !printable(name)
+            || !printable(icon)
+            || beeps > MAX_BEEPS
+        {
+            return None;
+        }
+
+        let mut band = band([0; MAX_NAME], [0; MAX_ICON], beeps, alert);
+        band.name[..name.len()].copy_from_slice(name.as_bytes());
+        band.icon[..icon.len()].copy_from_slice(icon.as_bytes());
+        Some(band)
+    }
+
+    pub fn name(&self) -> &str {
+        text(&self.name)
+    }
+
+    pub fn icon(&self) -> &str {
+        text(&self.icon)
+    }
+}
+
+fn text(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("?")
+}
+
+/// The icon and the name
+impl fmt::Display for Band {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.icon().is_empty() {
+            f.write_str(self.name())
+        } else {
+            write!(f, "{} {}", self.icon(), self.name())
+        }
+    }
+}
+
+#[cfg(feature = "ufmt")]
+impl ufmt::uDisplay for Band {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        if !self.icon().is_empty() {
+            f.write_str(self.icon())?;
+            f.write_str(" ")?;
+        }
+        f.write_str(self.name())
+    }
+}
+
+/// The band of a result
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Severity(u8);
+
+impl Severity {
+    /// The band of a reading of `raw` against the clean air `baseline`
+    pub fn of(raw: u16, baseline: u16, starts: &[u16; BAND_COUNT - 1]) -> Severity {
+        let drop = if baseline == 0 {
+            0
+        } else {
+            100u32.saturating_sub(raw as u32 * 100 / baseline as u32)
+        };
+        let band = starts.iter().take_while(|start| drop >= **start as u32).count();
+        Severity(band as u8)
+    }
+
+    /// From a stored or received category, `None` if there is no such band
+    pub fn from_category(category: u8) -> Option<Severity> {
+        if (category as usize) < BAND_COUNT {
+            Some(Severity(category))
+        } else {
+            None
+        }
+    }
+
+    pub fn category(self) -> u8 {
+        self.0
+    }
+
+    /// The band in `bands`, the device's own or `DEFAULT_BANDS`
+    pub fn band(self, bands: &[Band; BAND_COUNT]) -> &Band {
+        &bands[self.0 as usize]
+    }
+}
+
+/// With the default band names, a device may be set up with others
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.band(&DEFAULT_BANDS), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::{consts::*, String};
+
+    fn display(band: &Band) -> String<U16> {
+        let mut s = String::new();
+        fmt::write(&mut s, format_args!("{}", band)).unwrap();
+        s
+    }
+
+    #[test]
+    fn bands_start_at_their_drop() {
+        let of = |raw| Severity::of(raw, 1000, &DEFAULT_STARTS).category();
+        assert_eq!(of(1000), 0);
+        assert_eq!(of(940), 0);
+        assert_eq!(of(930), 1);
+        assert_eq!(of(850), 2);
+        assert_eq!(of(770), 3);
+        assert_eq!(of(690), 4);
+        assert_eq!(of(610), 5);
+        assert_eq!(of(0), 5);
+    }
+
+    #[test]
+    fn readings_above_the_baseline_are_none() {
+        assert_eq!(Severity::of(1200, 1000, &DEFAULT_STARTS).category(), 0);
+    }
+
+    #[test]
+    fn no_baseline_is_none() {
+        assert_eq!(Severity::of(500, 0, &DEFAULT_STARTS).category(), 0);
+    }
+
+    #[test]
+    fn other_starts() {
+        let starts = [1, 2, 50, 98, 99];
+        assert_eq!(Severity::of(990, 1000, &starts).category(), 1);
+        assert_eq!(Severity::of(600, 1000, &starts).category(), 2);
+        assert_eq!(Severity::of(20, 1000, &starts).category(), 4);
+    }
+
+    #[test]
+    fn categories() {
+        assert_eq!(Severity::from_category(5).map(Severity::category), Some(5));
+        assert_eq!(Severity::from_category(BAND_COUNT as u8), None);
+    }
+
+    #[test]
+    fn names_and_icons() {
+        assert_eq!(display(&DEFAULT_BANDS[0]), "None");
+        assert_eq!(display(&DEFAULT_BANDS[4]), "!! Very high");
+        assert_eq!(display(&DEFAULT_BANDS[5]), "!!! Severe");
+
+        let band = Band::new("Drunk", "", 2, true).unwrap();
+        assert_eq!(band.name(), "Drunk");
+        assert_eq!(display(&band), "Drunk");
+    }
+
+    #[test]
+    fn bad_bands() {
+        assert!(Band::new("", "", 0, false).is_none());
+        assert!(Band::new("Far too long", "", 0, false).is_none());
+        assert!(Band::new("High", "!!!!", 0, false).is_none());
+        assert!(Band::new("Hi\tgh", "", 0, false).is_none());
+        assert!(Band::new("High", "", MAX_BEEPS + 1, false).is_none());
+        assert!(Band::new("Very high", "!!!", MAX_BEEPS, true).is_some());
+    }
+}
@@ -0,0 +1,496 @@
+//! Uplink payload sent after every measurement.
+//!
+//! The payload is the postcard encoding of `Telemetry`, without COBS since
+//! the radio packet has its own length. The first byte is always the schema
+//! version. Fields are only ever added at the end, together with a new
+//! version, so older payloads stay decodable.
+
+use serde::{Deserialize, Serialize};
+
+pub const TELEMETRY_VERSION: u8 = 5;
+
+/// Largest encoded payload
+pub const MAX_TELEMETRY: usize = 56;
+
+/// `bac` when the sensor has no calibration points
+pub const BAC_UNKNOWN: u16 = 0xFFFF;
+
+/// Bits of `Telemetry::faults`
+pub mod fault {
+    /// No clean air reading, the baseline is only what was seen since boot
+    pub const UNCALIBRATED: u8 = 1 << 0;
+    /// The reading was above the baseline, the sensor may not be warm
+    pub const SENSOR_RANGE: u8 = 1 << 1;
+    pub const BATTERY_LOW: u8 = 1 << 2;
+    /// The last reset was caused by the watchdog
+    pub const WATCHDOG_RESET: u8 = 1 << 3;
+    /// The last reset followed a panic
+    pub const CRASHED: u8 = 1 << 4;
+    /// The reading recovered too fast for alcohol from the lungs, it is
+    /// most likely far too high
+    pub const MOUTH_ALCOHOL: u8 = 1 << 5;
+}
+
+/// Values of `Telemetry::reset`, the same as the firmware's `ResetCause`
+pub mod reset {
+    pub const UNKNOWN: u8 = 0;
+    /// Power-on or brown-out, the hardware does not tell them apart
+    pub const BROWN_OUT: u8 = 1;
+    pub const PIN: u8 = 2;
+    pub const SOFTWARE: u8 = 3;
+    pub const WATCHDOG: u8 = 4;
+    pub const WINDOW_WATCHDOG: u8 = 5;
+    pub const LOW_POWER: u8 = 6;
+    pub const OPTION_BYTES: u8 = 7;
+    pub const FIREWALL: u8 = 8;
+    /// The software reset after a panic or a fault
+    pub const CRASH: u8 = 9;
+
+    pub const NAMES: [&str; 10] = [
+        "unknown",
+        "brown-out",
+        "pin",
+        "software",
+        "watchdog",
+        "window watchdog",
+        "low power",
+        "option bytes",
+        "firewall",
+        "crash",
+    ];
+
+    pub fn name(cause: u8) -> &'static str {
+        NAMES.get(cause as usize).copied().unwrap_or("unknown")
+    }
+}
+
+/// Radio link statistics since boot, sent with some of the uplinks
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkStats {
+    /// Of the last received packet, in dBm
+    pub rssi: i16,
+    /// Of the last received packet, in dB
+    pub snr: i8,
+    pub tx: u16,
+    pub tx_done: u16,
+    /// Average time from starting a transmission to TX done
+    pub latency_ms: u16,
+    pub crc_errors: u16,
+    /// Failed joins that were tried again, LongFi doesn't retry
+    pub retries: u16,
+    /// Time on air since boot in 0.1 %
+    pub duty_permille: u16,
+}
+
+/// Where the firmware panicked before the last reset
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CrashSite {
+    /// `CrashSite::hash_file` of the source file, the path doesn't fit
+    pub file: u16,
+    pub line: u16,
+}
+
+impl CrashSite {
+    /// CRC-16/XMODEM of the path as the panic gives it, e.g. `src/main.rs`
+    pub fn hash_file(path: &str) -> u16 {
+        crc16::State::<crc16::XMODEM>::calculate(path.as_bytes())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Telemetry {
+    pub version: u8,
+    /// Increments with every uplink, wraps around
+    pub seq: u16,
+    /// Seconds since boot
+    pub uptime: u32,
+    /// Blood alcohol content in 0.01 per mille, `BAC_UNKNOWN` without calibration
+    pub bac: u16,
+    /// Index of the band, see `severity`, 0 is none
+    pub category: u8,
+    pub raw: u16,
+    pub baseline: u16,
+    /// MCU die temperature in degrees Celsius
+    pub temperature: i8,
+    pub battery_mv: u16,
+    pub battery_percent: u8,
+    pub faults: u8,
+    /// Added in version 2, `None` in version 1 payloads
+    pub link: Option<LinkStats>,
+    /// Who blew, 0 is the guest and what older versions decode to
+    pub profile: u8,
+    /// Cause of the last reset, see `reset`. Only in the first uplink after
+    /// boot, added in version 4.
+    pub reset: Option<u8>,
+    /// With `reset::CRASH`, added in version 5
+    pub crash: Option<CrashSite>,
+}
+
+// Version 4, before the crash site
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct TelemetryV4 {
+    version: u8,
+    seq: u16,
+    uptime: u32,
+    bac: u16,
+    category: u8,
+    raw: u16,
+    baseline: u16,
+    temperature: i8,
+    battery_mv: u16,
+    battery_percent: u8,
+    faults: u8,
+    link: Option<LinkStats>,
+    profile: u8,
+    reset: Option<u8>,
+}
+
+// Version 3, before the reset cause
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct TelemetryV3 {
+    version: u8,
+    seq: u16,
+    uptime: u32,
+    bac: u16,
+    category: u8,
+    raw: u16,
+    baseline: u16,
+    temperature: i8,
+    battery_mv: u16,
+    battery_percent: u8,
+    faults: u8,
+    link: Option<LinkStats>,
+    profile: u8,
+}
+
+// Version 2, before the profiles
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct TelemetryV2 {
+    version: u8,
+    seq: u16,
+    uptime: u32,
+    bac: u16,
+    category: u8,
+    raw: u16,
+    baseline: u16,
+    temperature: i8,
+    battery_mv: u16,
+    battery_percent: u8,
+    faults: u8,
+    link: Option<LinkStats>,
+}
+
+// Version 1, before the link statistics
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct TelemetryV1 {
+    version: u8,
+    seq: u16,
+    uptime: u32,
+    bac: u16,
+    category: u8,
+    raw: u16,
+    baseline: u16,
+    temperature: i8,
+    battery_mv: u16,
+    battery_percent: u8,
+    faults: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TelemetryError {
+    Empty,
+    /// A schema this decoder doesn't know, holds the version byte
+    Version(u8),
+    Malformed,
+}
+
+impl Telemetry {
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a mut [u8], postcard::Error> {
+        postcard::to_slice(self, buf)
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Telemetry, TelemetryError> {
+        match payload.first() {
+            None => Err(TelemetryError::Empty),
+            Some(&TELEMETRY_VERSION) => {
+                postcard::from_bytes(payload).map_err(|_| TelemetryError::Malformed)
+            }
+            Some(4) => {
+                let t: TelemetryV4 = postcard::from_bytes(payload).map_err(|_| TelemetryError::Malformed)?;
+                Ok(Telemetry {
+                    version: t.version,
+                    seq: t.seq,
+                    uptime: t.uptime,
+                    bac: t.bac,
+                    category: t.category,
+                    raw: t.raw,
+                    baseline: t.baseline,
+                    temperature: t.temperature,
+                    battery_mv: t.battery_mv,
+                    battery_percent: t.battery_percent,
+                    faults: t.faults,
+                    link: t.link,
+                    profile: t.profile,
+                    reset: t.reset,
+                    crash: None,
+                })
+            }
+            Some(3) => {
+                let t: TelemetryV3 = postcard::from_bytes(payload).map_err(|_| TelemetryError::Malformed)?;
+                Ok(Telemetry {
+                    version: t.version,
+                    seq: t.seq,
+                    uptime: t.uptime,
+                    bac: t.bac,
+                    category: t.category,
+                    raw: t.raw,
+                    baseline: t.baseline,
+                    temperature: t.temperature,
+                    battery_mv: t.battery_mv,
+                    battery_percent: t.battery_percent,
+                    faults: t.faults,
+                    link: t.link,
+                    profile: t.profile,
+                    reset: None,
+                    crash: None,
+                })
+            }
+            Some(2) => {
+                let t: TelemetryV2 = postcard::from_bytes(payload).map_err(|_| TelemetryError::Malformed)?;
+                Ok(Telemetry {
+                    version: t.version,
+                    seq: t.seq,
+                    uptime: t.uptime,
+                    bac: t.bac,
+                    category: t.category,
+                    raw: t.raw,
+                    baseline: t.baseline,
+                    temperature: t.temperature,
+                    battery_mv: t.battery_mv,
+                    battery_percent: t.battery_percent,
+                    faults: t.faults,
+                    link: t.link,
+                    profile: 0,
+                    reset: None,
+                    crash: None,
+                })
+            }
+            Some(1) => {
+                let t: TelemetryV1 = postcard::from_bytes(payload).map_err(|_| TelemetryError::Malformed)?;
+                Ok(Telemetry {
+                    version: t.version,
+                    seq: t.seq,
+                    uptime: t.uptime,
+                    bac: t.bac,
+                    category: t.category,
+                    raw: t.raw,
+                    baseline: t.baseline,
+                    temperature: t.temperature,
+                    battery_mv: t.battery_mv,
+                    battery_percent: t.battery_percent,
+                    faults: t.faults,
+                    link: None,
+                    profile: 0,
+                    reset: None,
+                    crash: None,
+                })
+            }
+            Some(&version) => Err(TelemetryError::Version(version)),
+        }
+    }
+
+    pub fn has_fault(&self, fault: u8) -> bool {
+        self.faults & fault != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK: LinkStats = LinkStats {
+        rssi: -97,
+        snr: -4,
+        tx: 120,
+        tx_done: 118,
+        latency_ms: 1480,
+        crc_errors: 3,
+        retries: 1,
+        duty_permille: 7,
+    };
+
+    fn telemetry() -> Telemetry {
+        Telemetry {
+            version: TELEMETRY_VERSION,
+            seq: 513,
+            uptime: 86_400,
+            bac: 42,
+            category: 2,
+            raw: 2900,
+            baseline: 3400,
+            temperature: -5,
+            battery_mv: 3710,
+            battery_percent: 64,
+            faults: fault::BATTERY_LOW | fault::CRASHED,
+            link: Some(LINK),
+            profile: 3,
+            reset: Some(reset::CRASH),
+            crash: Some(CrashSite { file: CrashSite::hash_file("src/main.rs"), line: 731 }),
+        }
+    }
+
+    fn decode<T: Serialize>(old: &T) -> Result<Telemetry, TelemetryError> {
+        let mut buf = [0; MAX_TELEMETRY];
+        let payload = postcard::to_slice(old, &mut buf).unwrap();
+        Telemetry::decode(payload)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; MAX_TELEMETRY];
+        let payload = telemetry().encode(&mut buf).unwrap();
+        assert_eq!(Telemetry::decode(payload), Ok(telemetry()));
+    }
+
+    #[test]
+    fn largest_fits() {
+        let largest = Telemetry {
+            seq: u16::MAX,
+            uptime: u32::MAX,
+            bac: u16::MAX,
+            category: u8::MAX,
+            raw: u16::MAX,
+            baseline: u16::MAX,
+            temperature: i8::MIN,
+            battery_mv: u16::MAX,
+            battery_percent: u8::MAX,
+            faults: u8::MAX,
+            link: Some(LinkStats {
+                rssi: i16::MIN,
+                snr: i8::MIN,
+                tx: u16::MAX,
+                tx_done: u16::MAX,
+                latency_ms: u16::MAX,
+                crc_errors: u16::MAX,
+                retries: u16::MAX,
+                duty_permille: u16::MAX,
+            }),
+            profile: u8::MAX,
+            reset: Some(u8::MAX),
+            crash: Some(CrashSite { file: u16::MAX, line: u16::MAX }),
+            ..telemetry()
+        };
+        let mut buf = [0; MAX_TELEMETRY];
+        assert!(largest.encode(&mut buf).is_ok());
+    }
+
+    #[test]
+    fn version_4_has_no_crash_site() {
+        let t = telemetry();
+        let old = TelemetryV4 {
+            version: 4,
+            seq: t.seq,
+            uptime: t.uptime,
+            bac: t.bac,
+            category: t.category,
+            raw: t.raw,
+            baseline: t.baseline,
+            temperature: t.temperature,
+            battery_mv: t.battery_mv,
+            battery_percent: t.battery_percent,
+            faults: t.faults,
+            link: t.link,
+            profile: t.profile,
+            reset: t.reset,
+        };
+        assert_eq!(decode(&old), Ok(Telemetry { version: 4, crash: None, ..t }));
+    }
+
+    #[test]
+    fn version_3_has_no_reset_cause() {
+        let t = telemetry();
+        let old = TelemetryV3 {
+            version: 3,
+            seq: t.seq,
+            uptime: t.uptime,
+            bac: t.bac,
+            category: t.category,
+            raw: t.raw,
+            baseline: t.baseline,
+            temperature: t.temperature,
+            battery_mv: t.battery_mv,
+            battery_percent: t.battery_percent,
+            faults: t.faults,
+            link: t.link,
+            profile: t.profile,
+        };
+        assert_eq!(decode(&old), Ok(Telemetry { version: 3, reset: None, crash: None, ..t }));
+    }
+
+    #[test]
+    fn version_2_is_the_guest() {
+        let t = telemetry();
+        let old = TelemetryV2 {
+            version: 2,
+            seq: t.seq,
+            uptime: t.uptime,
+            bac: t.bac,
+            category: t.category,
+            raw: t.raw,
+            baseline: t.baseline,
+            temperature: t.temperature,
+            battery_mv: t.battery_mv,
+            battery_percent: t.battery_percent,
+            faults: t.faults,
+            link: t.link,
+        };
+        assert_eq!(decode(&old), Ok(Telemetry { version: 2, profile: 0, reset: None, crash: None, ..t }));
+    }
+
+    #[test]
+    fn version_1_has_no_link_statistics() {
+        let t = telemetry();
+        let old = TelemetryV1 {
+            version: 1,
+            seq: t.seq,
+            uptime: t.uptime,
+            bac: t.bac,
+            category: t.category,
+            raw: t.raw,
+            baseline: t.baseline,
+            temperature: t.temperature,
+            battery_mv: t.battery_mv,
+            battery_percent: t.battery_percent,
+            faults: t.faults,
+        };
+        assert_eq!(
+            decode(&old),
+            Ok(Telemetry { version: 1, link: None, profile: 0, reset: None, crash: None, ..t })
+        );
+    }
+
+    #[test]
+    fn bad_payloads() {
+        assert_eq!(Telemetry::decode(&[]), Err(TelemetryError::Empty));
+        assert_eq!(
+            Telemetry::decode(&[TELEMETRY_VERSION + 1, 0, 0]),
+            Err(TelemetryError::Version(TELEMETRY_VERSION + 1))
+        );
+
+        let mut buf = [0; MAX_TELEMETRY];
+        let len = telemetry().encode(&mut buf).unwrap().len();
+        assert_eq!(Telemetry::decode(&buf[..len - 1]), Err(TelemetryError::Malformed));
+        assert_eq!(Telemetry::decode(&buf[..3]), Err(TelemetryError::Malformed));
+    }
+
+    #[test]
+    fn reset_names() {
+        assert_eq!(reset::name(reset::PIN), "pin");
+        assert_eq!(reset::name(reset::CRASH), "crash");
+        assert_eq!(reset::name(200), "unknown");
+    }
+}
@@ -0,0 +1,233 @@
+//! Firmware updates over the radio.
+//!
+//! The transfer is controlled with the update commands in `command`, and
+//! the image itself is sent as numbered fragments that are authenticated
+//! with the command key as well, so nobody else can write to the update
+//! slot. Fragments can arrive in any order and more than once, the device
+//! keeps track of the ones it has across resets so a transfer can be resumed
+//! with the missing ones. The image is signed with Ed25519 over
+//! `signed_message`, and only installed by the bootloader if the signature
+//! checks out and the version is above the installed one.
+//!
+//! The serial bootloader gets fragments with a CRC instead, see `boot`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::command::{self, Key, MAC_SIZE};
+
+pub const FRAGMENT_MARKER: u8 = 0xC3;
+
+/// LoRaWAN port of fragments, LongFi tells them apart by the marker
+pub const FRAGMENT_PORT: u8 = 3;
+
+/// Image bytes per fragment, a multiple of the flash word
+pub const FRAGMENT_SIZE: usize = 32;
+
+const HEADER_SIZE: usize = 3;
+// Marker, index and CRC
+const FRAGMENT_OVERHEAD: usize = HEADER_SIZE + 2;
+pub const MAX_FRAGMENT: usize = FRAGMENT_SIZE + FRAGMENT_OVERHEAD;
+/// A fragment sent over the radio, with a MAC instead of the CRC
+pub const MAX_RADIO_FRAGMENT: usize = HEADER_SIZE + FRAGMENT_SIZE + MAC_SIZE;
+
+/// Where an update is, stored by the firmware and the bootloader
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    /// Nothing going on, erased EEPROM reads as this
+    Idle = 0,
+    /// Fragments are being received
+    Downloading = 1,
+    /// Received and verified, installed by the bootloader at the next reset
+    Pending = 2,
+    /// The bootloader is swapping the image in
+    Installing = 3,
+    /// The new firmware runs but hasn't confirmed itself yet
+    Testing = 4,
+    /// The new firmware didn't confirm itself, the old one is swapped back
+    Reverting = 5,
+    /// The old firmware runs again
+    RolledBack = 6,
+    /// The bootloader found the image invalid and left the firmware alone
+    Rejected = 7,
+}
+
+impl Status {
+    pub fn from_u8(status: u8) -> Status {
+        match status {
+            1 => Status::Downloading,
+            2 => Status::Pending,
+            3 => Status::Installing,
+            4 => Status::Testing,
+            5 => Status::Reverting,
+            6 => Status::RolledBack,
+            7 => Status::Rejected,
+            _ => Status::Idle,
+        }
+    }
+}
+
+/// Reply to `Command::UpdateStatus`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct UpdateStatus {
+    pub status: Status,
+    /// Version of the update, not of the running firmware
+    pub version: u32,
+    pub received: u16,
+    pub total: u16,
+    /// First fragment still missing, `total` if none are
+    pub next_missing: u16,
+}
+
+/// Fragments an image of `size` bytes is sent in
+pub fn fragment_count(size: u32) -> u16 {
+    ((size + FRAGMENT_SIZE as u32 - 1) / FRAGMENT_SIZE as u32) as u16
+}
+
+/// What the image signature is over: version, size and the SHA-256 of the image
+pub fn signed_message(version: u32, size: u32, digest: &[u8; 32]) -> [u8; 40] {
+    let mut message = [0; 40];
+    message[..4].copy_from_slice(&version.to_le_bytes());
+    message[4..8].copy_from_slice(&size.to_le_bytes());
+    message[8..].copy_from_slice(digest);
+    message
+}
+
+// Marker, little endian index and the data. The last fragment is padded
+// with zeros, which is what erased flash reads as.
+fn fill(index: u16, data: &[u8], buf: &mut [u8]) {
+    buf[0] = FRAGMENT_MARKER;
+    buf[1..HEADER_SIZE].copy_from_slice(&index.to_le_bytes());
+
+    let len = data.len().min(FRAGMENT_SIZE);
+    buf[HEADER_SIZE..HEADER_SIZE + len].copy_from_slice(&data[..len]);
+    for byte in buf[HEADER_SIZE + len..HEADER_SIZE + FRAGMENT_SIZE].iter_mut() {
+        *byte = 0;
+    }
+}
+
+fn index(frame: &[u8]) -> (u16, &[u8]) {
+    let index = u16::from_le_bytes([frame[1], frame[2]]);
+    (index, &frame[HEADER_SIZE..HEADER_SIZE + FRAGMENT_SIZE])
+}
+
+/// Header and data followed by a CRC over them, for the serial bootloader
+pub fn encode_fragment<'a>(index: u16, data: &[u8], buf: &'a mut [u8; MAX_FRAGMENT]) -> &'a [u8] {
+    fill(index, data, buf);
+    let crc = crc16::State::<crc16::XMODEM>::calculate(&buf[..MAX_FRAGMENT - 2]);
+    buf[MAX_FRAGMENT - 2..].copy_from_slice(&crc.to_le_bytes());
+    &buf[..]
+}
+
+/// The index and data of a fragment, if the CRC matches
+pub fn decode_fragment(frame: &[u8]) -> Result<(u16, &[u8]), ()> {
+    if frame.len() != MAX_FRAGMENT || frame[0] != FRAGMENT_MARKER {
+        return Err(());
+    }
+
+    let crc = u16::from_le_bytes([frame[MAX_FRAGMENT - 2], frame[MAX_FRAGMENT - 1]]);
+    if crc != crc16::State::<crc16::XMODEM>::calculate(&frame[..MAX_FRAGMENT - 2]) {
+        return Err(());
+    }
+    Ok(index(frame))
+}
+
+/// Header and data followed by a MAC over them with the command key, for
+/// the radio
+pub fn encode_radio_fragment<'a>(
+    key: &Key,
+    index: u16,
+    data: &[u8],
+    buf: &'a mut [u8; MAX_RADIO_FRAGMENT],
+) -> &'a [u8] {
+    fill(index, data, buf);
+    let end = MAX_RADIO_FRAGMENT - MAC_SIZE;
+    let code = command::mac(key, &buf[..end]);
+    buf[end..].copy_from_slice(&code);
+    &buf[..]
+}
+
+/// The index and data of a radio fragment, if the MAC is right
+pub fn decode_radio_fragment<'a>(key: &Key, frame: &'a [u8]) -> Result<(u16, &'a [u8]), ()> {
+    if frame.len() != MAX_RADIO_FRAGMENT || frame[0] != FRAGMENT_MARKER {
+        return Err(());
+    }
+
+    let end = MAX_RADIO_FRAGMENT - MAC_SIZE;
+    if command::mac(key, &frame[..end]) != frame[end..] {
+        return Err(());
+    }
+    Ok(index(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: Key = [7; 16];
+
+    #[test]
+    fn fragment_count_rounds_up() {
+        assert_eq!(fragment_count(0), 0);
+        assert_eq!(fragment_count(1), 1);
+        assert_eq!(fragment_count(FRAGMENT_SIZE as u32), 1);
+        assert_eq!(fragment_count(FRAGMENT_SIZE as u32 + 1), 2);
+    }
+
+    #[test]
+    fn signed_message_layout() {
+        let message = signed_message(0x0102_0304, 0x0A0B_0C0D, &[0xEE; 32]);
+        assert_eq!(message[..8], [4, 3, 2, 1, 0x0D, 0x0C, 0x0B, 0x0A]);
+        assert_eq!(message[8..], [0xEE; 32]);
+    }
+
+    #[test]
+    fn serial_fragment_round_trip() {
+        let data = [0x5A; FRAGMENT_SIZE];
+        let mut buf = [0; MAX_FRAGMENT];
+        let frame = encode_fragment(300, &data, &mut buf);
+        assert_eq!(decode_fragment(frame), Ok((300, &data[..])));
+    }
+
+    #[test]
+    fn last_fragment_is_padded() {
+        let mut buf = [0xFF; MAX_RADIO_FRAGMENT];
+        let frame = encode_radio_fragment(&KEY, 2, &[1, 2, 3], &mut buf);
+        let (index, data) = decode_radio_fragment(&KEY, frame).unwrap();
+
+        assert_eq!(index, 2);
+        assert_eq!(data[..3], [1, 2, 3]);
+        assert!(data[3..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn corrupt_serial_fragments_are_refused() {
+        let mut buf = [0; MAX_FRAGMENT];
+        encode_fragment(1, &[0x5A; FRAGMENT_SIZE], &mut buf);
+
+        for i in 0..MAX_FRAGMENT {
+            let mut frame = buf;
+            frame[i] ^= 0x01;
+            assert_eq!(decode_fragment(&frame), Err(()), "byte {}", i);
+        }
+        assert_eq!(decode_fragment(&buf[..MAX_FRAGMENT - 1]), Err(()));
+    }
+
+    #[test]
+    fn radio_fragments_need_the_key() {
+        let data = [0x5A; FRAGMENT_SIZE];
+        let mut buf = [0; MAX_RADIO_FRAGMENT];
+        encode_radio_fragment(&KEY, 9, &data, &mut buf);
+        assert_eq!(decode_radio_fragment(&KEY, &buf), Ok((9, &data[..])));
+
+        // Anyone can make a valid CRC, only the key holder a valid MAC
+        assert_eq!(decode_radio_fragment(&[8; 16], &buf), Err(()));
+        for i in 0..MAX_RADIO_FRAGMENT {
+            let mut frame = buf;
+            frame[i] ^= 0x01;
+            assert_eq!(decode_radio_fragment(&KEY, &frame), Err(()), "byte {}", i);
+        }
+
+        let mut serial = [0; MAX_FRAGMENT];
+        assert_eq!(decode_radio_fragment(&KEY, encode_fragment(9, &data, &mut serial)), Err(()));
+    }
+}
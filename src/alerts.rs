@@ -0,0 +1,91 @@
+//! Alerts for results in bands with the `alert` flag, pushed to the server
+//! apart from the telemetry, see `protocol::alert`. Which alerts go out and
+//! when they are sent again is up to `Outbox`.
+//!
+//! The unacknowledged alerts and the next id are kept in the EEPROM, so a
+//! reset neither forgets an alert nor gives a new one an id the server has
+//! seen. They are only written when an alert is added or removed, not on
+//! every retry, so their retries start over after a reset.
+
+use crate::diagnostics::{push_line, Lines};
+use crate::nvm::{Nvm, ALERTS};
+use protocol::alert::{self, Alert, Outbox, Tick};
+use protocol::severity::Severity;
+
+const MAGIC: u16 = 0x414D;
+// Magic, outbox and checksum
+const STORED_SIZE: usize = 2 + alert::STORED_SIZE + 2;
+
+/// Shown once the server has acknowledged an alert
+pub fn confirmation() -> Lines {
+    let mut lines = Lines::new();
+    push_line(&mut lines, format_args!("Alert sent"));
+    push_line(&mut lines, format_args!("to contact"));
+    lines
+}
+
+pub struct Alerts {
+    outbox: Outbox,
+}
+
+impl Alerts {
+    /// Loads the unacknowledged alerts, none if there are none stored
+    pub fn load(nvm: &Nvm) -> Alerts {
+        let mut bytes = [0; STORED_SIZE];
+        nvm.read(ALERTS, &mut bytes);
+
+        let magic = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let checksum = u16::from_le_bytes([bytes[STORED_SIZE - 2], bytes[STORED_SIZE - 1]]);
+        if magic != MAGIC
+            || checksum != crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2])
+        {
+            return Alerts { outbox: Outbox::new() };
+        }
+
+        let mut stored = [0; alert::STORED_SIZE];
+        stored.copy_from_slice(&bytes[2..STORED_SIZE - 2]);
+        Alerts {
+            outbox: Outbox::restore(&stored),
+        }
+    }
+
+    fn save(&self, nvm: &mut Nvm) -> Result<(), ()> {
+        let mut bytes = [0; STORED_SIZE];
+        bytes[..2].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[2..STORED_SIZE - 2].copy_from_slice(&self.outbox.store());
+
+        let checksum = crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2]);
+        bytes[STORED_SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+        nvm.write(ALERTS, &bytes)
+    }
+
+    /// The alert to send for a result, see `Outbox::raise`
+    pub fn raise(
+        &mut self,
+        nvm: &mut Nvm,
+        severity: Severity,
+        bac: u16,
+        profile: u8,
+        time: u32,
+    ) -> Option<Alert> {
+        let alert = self.outbox.raise(severity, bac, profile, time)?;
+        self.save(nvm).ok();
+        Some(alert)
+    }
+
+    /// Called as time passes, `elapsed_s` since the last call
+    pub fn tick(&mut self, nvm: &mut Nvm, elapsed_s: u16) -> Option<Tick> {
+        let tick = self.outbox.tick(elapsed_s);
+        if let Some(Tick::GaveUp(_)) = tick {
+            self.save(nvm).ok();
+        }
+        tick
+    }
+
+    /// Stops the retries of `id`, `None` if it isn't waiting for an answer
+    pub fn acknowledge(&mut self, nvm: &mut Nvm, id: u16) -> Option<Alert> {
+        let alert = self.outbox.acknowledge(id)?;
+        self.save(nvm).ok();
+        Some(alert)
+    }
+}
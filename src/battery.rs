@@ -0,0 +1,142 @@
+use embedded_hal::adc::{Channel, OneShot};
+use stm32l0xx_hal::{adc::Adc, pac};
+
+#[cfg(feature = "battery-divider")]
+use stm32l0xx_hal::gpio::{gpioa::PA0, Analog, Floating, Input};
+
+/// Factory calibration of VREFINT, measured with VDDA = 3.0 V
+const VREFINT_CAL: *const u16 = 0x1FF8_0078 as *const u16;
+const VREFINT_CAL_MV: u32 = 3000;
+
+/// Ratio of the battery voltage divider on PA0
+#[cfg(feature = "battery-divider")]
+const DIVIDER_RATIO: u32 = 2;
+
+// With the divider the thresholds are for the battery itself, without it
+// only VDD is known which starts dropping once the LDO is out of headroom.
+#[cfg(feature = "battery-divider")]
+pub const LOW_MV: u16 = 3500;
+#[cfg(feature = "battery-divider")]
+pub const CRITICAL_MV: u16 = 3300;
+#[cfg(not(feature = "battery-divider"))]
+pub const LOW_MV: u16 = 3100;
+#[cfg(not(feature = "battery-divider"))]
+pub const CRITICAL_MV: u16 = 2900;
+
+/// Discharge curve as (mV, percent), highest voltage first
+#[cfg(feature = "battery-divider")]
+const SOC_CURVE: [(u16, u8); 6] = [
+    (4200, 100),
+    (4000, 85),
+    (3800, 60),
+    (3700, 40),
+    (3500, 10),
+    (3300, 0),
+];
+#[cfg(not(feature = "battery-divider"))]
+const SOC_CURVE: [(u16, u8); 4] = [(3300, 100), (3200, 40), (3100, 10), (2900, 0)];
+
+/// Internal reference voltage on ADC channel 17
+pub struct VRefInt;
+
+impl Channel<Adc> for VRefInt {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        17
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Ok,
+    /// Too low for the heater to give stable readings
+    Low,
+    /// The device has to shut down
+    Critical,
+}
+
+pub struct Battery {
+    pub mv: u16,
+    pub vdd_mv: u16,
+    pub percent: u8,
+    pub level: Level,
+    #[cfg(feature = "battery-divider")]
+    pub divider: PA0<Analog>,
+}
+
+impl Battery {
+    pub fn new(#[cfg(feature = "battery-divider")] divider: PA0<Input<Floating>>) -> Battery {
+        // Enable VREFINT
+        unsafe {
+            (*pac::ADC::ptr()).ccr.modify(|_, w| w.vrefen().set_bit());
+        }
+
+        Battery {
+            mv: 0,
+            vdd_mv: 0,
+            percent: 100,
+            level: Level::Ok,
+            #[cfg(feature = "battery-divider")]
+            divider: divider.into_analog(),
+        }
+    }
+
+    /// Measures the supply and updates the state of charge
+    pub fn update(&mut self, adc: &mut Adc) -> Level {
+        let raw: u16 = adc.read(&mut VRefInt).unwrap();
+        if raw == 0 {
+            return self.level;
+        }
+
+        let cal = unsafe { core::ptr::read_volatile(VREFINT_CAL) } as u32;
+        let vdd = (VREFINT_CAL_MV * cal / raw as u32) as u16;
+        self.vdd_mv = vdd;
+
+        #[cfg(feature = "battery-divider")]
+        let mv = {
+            let raw: u16 = adc.read(&mut self.divider).unwrap();
+            (raw as u32 * vdd as u32 * DIVIDER_RATIO / 4095) as u16
+        };
+        #[cfg(not(feature = "battery-divider"))]
+        let mv = vdd;
+
+        // Smooth out the dips caused by the heater and the radio
+        self.mv = if self.mv == 0 {
+            mv
+        } else {
+            ((self.mv as u32 * 3 + mv as u32) / 4) as u16
+        };
+
+        self.percent = state_of_charge(self.mv);
+        self.level = if self.mv <= CRITICAL_MV {
+            Level::Critical
+        } else if self.mv <= LOW_MV {
+            Level::Low
+        } else {
+            Level::Ok
+        };
+
+        self.level
+    }
+}
+
+/// Interpolates the state of charge in percent from the discharge curve
+pub fn state_of_charge(mv: u16) -> u8 {
+    if mv >= SOC_CURVE[0].0 {
+        return SOC_CURVE[0].1;
+    }
+
+    for pair in SOC_CURVE.windows(2) {
+        let (high_mv, high_pct) = pair[0];
+        let (low_mv, low_pct) = pair[1];
+
+        if mv >= low_mv {
+            let span = (high_mv - low_mv) as u32;
+            let pct = (high_pct - low_pct) as u32 * (mv - low_mv) as u32 / span;
+            return low_pct + pct as u8;
+        }
+    }
+
+    0
+}
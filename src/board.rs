@@ -0,0 +1,123 @@
+//! Pins of the boards the firmware runs on. The custom PCB in pcbdesign/ is
+//! the default, the `board-discovery` feature selects the Murata discovery
+//! kit and `board-breadboard` the discovery kit with the sensor, buzzer and
+//! OLED wired up on a breadboard.
+//!
+//! All of them use the Murata module, so only the TCXO enable and the
+//! button differ.
+
+use stm32l0xx_hal::{
+    gpio::{gpioa::*, gpiob::*, gpioc::*, Floating, GpioExt, Input, Output, PullUp, PushPull},
+    pac,
+    rcc::Rcc,
+};
+
+#[cfg(all(feature = "board-discovery", feature = "board-breadboard"))]
+compile_error!("select at most one board feature");
+
+#[cfg(not(any(feature = "board-discovery", feature = "board-breadboard")))]
+pub type Tcxo = PB5<Output<PushPull>>;
+#[cfg(not(any(feature = "board-discovery", feature = "board-breadboard")))]
+pub type Button = PB2<Input<PullUp>>;
+
+#[cfg(feature = "board-discovery")]
+pub type Tcxo = PA8<Output<PushPull>>;
+// The user button B1 of the kit
+#[cfg(feature = "board-discovery")]
+pub type Button = PB2<Input<PullUp>>;
+
+#[cfg(feature = "board-breadboard")]
+pub type Tcxo = PA8<Output<PushPull>>;
+#[cfg(feature = "board-breadboard")]
+pub type Button = PA4<Input<PullUp>>;
+
+/// False if the button can't wake the MCU and is scanned all the time. PA4
+/// shares EXTI line 4 with the radio's DIO0 on PB4, which gets the line.
+#[cfg(not(feature = "board-breadboard"))]
+pub const BUTTON_WAKEUP: bool = true;
+#[cfg(feature = "board-breadboard")]
+pub const BUTTON_WAKEUP: bool = false;
+
+pub type AntennaPins = (
+    PA1<Output<PushPull>>,
+    PC2<Output<PushPull>>,
+    PC1<Output<PushPull>>,
+);
+
+/// The pins of the selected board, configured where the mode is the same
+/// for every user. The rest are handed over unconfigured to the drivers.
+pub struct Board {
+    pub button: Button,
+
+    pub radio_dio0: PB4<Input<PullUp>>,
+    pub radio_sck: PB3<Input<Floating>>,
+    pub radio_miso: PA6<Input<Floating>>,
+    pub radio_mosi: PA7<Input<Floating>>,
+    pub radio_nss: PA15<Output<PushPull>>,
+    pub radio_reset: PC0<Output<PushPull>>,
+    /// Rx, Tx RFO and Tx boost switches
+    pub antenna: AntennaPins,
+    pub tcxo: Tcxo,
+
+    pub serial_tx: PA9<Input<Floating>>,
+    pub serial_rx: PA10<Input<Floating>>,
+
+    pub buzzer: PA3<Input<Floating>>,
+    pub heater: PA5<Input<Floating>>,
+    pub sensor: PA2<Input<Floating>>,
+    /// Only connected with the `battery-divider` feature
+    pub battery: PA0<Input<Floating>>,
+
+    pub oled_cs: PB12<Output<PushPull>>,
+    pub oled_sck: PB13<Input<Floating>>,
+    pub oled_mosi: PB15<Input<Floating>>,
+    pub oled_dc: PB8<Input<Floating>>,
+    pub oled_reset: PB9<Input<Floating>>,
+}
+
+impl Board {
+    /// Splits the GPIO ports into the pins of the board
+    pub fn take(gpioa: pac::GPIOA, gpiob: pac::GPIOB, gpioc: pac::GPIOC, rcc: &mut Rcc) -> Board {
+        let gpioa = gpioa.split(rcc);
+        let gpiob = gpiob.split(rcc);
+        let gpioc = gpioc.split(rcc);
+
+        #[cfg(not(any(feature = "board-discovery", feature = "board-breadboard")))]
+        let (tcxo, button) = (gpiob.pb5.into_push_pull_output(), gpiob.pb2.into_pull_up_input());
+        #[cfg(feature = "board-discovery")]
+        let (tcxo, button) = (gpioa.pa8.into_push_pull_output(), gpiob.pb2.into_pull_up_input());
+        #[cfg(feature = "board-breadboard")]
+        let (tcxo, button) = (gpioa.pa8.into_push_pull_output(), gpioa.pa4.into_pull_up_input());
+
+        Board {
+            button: button,
+
+            radio_dio0: gpiob.pb4.into_pull_up_input(),
+            radio_sck: gpiob.pb3,
+            radio_miso: gpioa.pa6,
+            radio_mosi: gpioa.pa7,
+            radio_nss: gpioa.pa15.into_push_pull_output(),
+            radio_reset: gpioc.pc0.into_push_pull_output(),
+            antenna: (
+                gpioa.pa1.into_push_pull_output(),
+                gpioc.pc2.into_push_pull_output(),
+                gpioc.pc1.into_push_pull_output(),
+            ),
+            tcxo: tcxo,
+
+            serial_tx: gpioa.pa9,
+            serial_rx: gpioa.pa10,
+
+            buzzer: gpioa.pa3,
+            heater: gpioa.pa5,
+            sensor: gpioa.pa2,
+            battery: gpioa.pa0,
+
+            oled_cs: gpiob.pb12.into_push_pull_output(),
+            oled_sck: gpiob.pb13,
+            oled_mosi: gpiob.pb15,
+            oled_dc: gpiob.pb8,
+            oled_reset: gpiob.pb9,
+        }
+    }
+}
@@ -1,5 +1,3 @@
-//use cortex_m_semihosting::hprintln;
-
 use stm32l0xx_hal::{
     adc::Adc,
     gpio::{
@@ -9,21 +7,16 @@ use stm32l0xx_hal::{
     prelude::*,
 };
 
-#[derive(Debug)]
-pub enum BAC {
-    NONE,
-    LOW,
-    MEDIUM,
-    HIGH,
-    VERY_HIGH,
-    DEATH
-}
+use crate::log_messages as msg;
+use crate::logger::Module;
 
 pub struct Breathalyzer {
     pub heater: PA5<Output<PushPull>>,
     pub dat: PA2<Analog>,
     pub adc: Adc,
     pub curr_val: u16,
+    /// Reading behind the last result
+    pub last: u16,
     pub state: bool,
 }
 
@@ -34,6 +27,7 @@ impl Breathalyzer {
             dat: dat.into_analog(),
             adc: adc,
             curr_val: 0,
+            last: 0,
             state: false,
         }
     }
@@ -50,24 +44,13 @@ impl Breathalyzer {
         self.heater.set_high().unwrap();
     }
 
-    /// Calculates value from ADC
-    pub fn read(&mut self) -> BAC {
+    /// Reads the result of a measurement, see `protocol::severity` for
+    /// what it means against the baseline in `curr_val`
+    pub fn read(&mut self) -> u16 {
         let val: u16 = self.adc.read(&mut self.dat).unwrap();
-        //hprintln!("{:#} / {:#} = {:#}", val, self.curr_val, (val * 100) / self.curr_val).unwrap();
-
-        if ((val * 100) / self.curr_val) >= 93 {
-            return BAC::LOW;
-        } else if ((val * 100) / self.curr_val) >= 85 {
-            return BAC::MEDIUM;
-        } else if ((val * 100) / self.curr_val) >= 77 {
-            return BAC::HIGH;
-        } else if ((val * 100) / self.curr_val) >= 69 {
-            return BAC::VERY_HIGH;
-        } else if ((val * 100) / self.curr_val) >= 61 {
-            return BAC::DEATH;
-        } else {
-            return BAC::NONE;
-        }
+        self.last = val;
+        debug!(Module::Sensor, msg::SENSOR_READ, val, self.curr_val);
+        val
     }
 
     /// Reads the value from ADC
@@ -0,0 +1,206 @@
+use heapless::{consts::*, spsc::Queue};
+
+/// Number of buttons on the board
+pub const BUTTON_COUNT: usize = 1;
+
+/// Button scan period in ms, the timer driving `Buttons::scan` must use this
+pub const SCAN_PERIOD_MS: u32 = 10;
+
+// All times below are counted in scan ticks
+const DEBOUNCE_TICKS: u8 = 3;
+const LONG_TICKS: u16 = 80;
+const DOUBLE_TICKS: u16 = 30;
+const REPEAT_DELAY_TICKS: u16 = 150;
+const REPEAT_INTERVAL_TICKS: u16 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gesture {
+    /// Pressed and released once
+    Short,
+    /// Held down past the long press time
+    Long,
+    /// Two short presses within the double click window
+    Double,
+    /// Sent periodically while the button is held after a long press
+    Repeat,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ButtonEvent {
+    pub id: u8,
+    pub gesture: Gesture,
+}
+
+/// Debouncer and gesture detector for a single button
+pub struct Button {
+    pub id: u8,
+    pressed: bool,
+    counter: u8,
+    held: u16,
+    released: u16,
+    clicks: u8,
+    long_sent: bool,
+}
+
+impl Button {
+    pub fn new(id: u8) -> Button {
+        Button {
+            id: id,
+            pressed: false,
+            counter: 0,
+            held: 0,
+            released: 0,
+            clicks: 0,
+            long_sent: false,
+        }
+    }
+
+    /// Feeds one raw sample of the pin, should be called every scan tick
+    pub fn update(&mut self, pressed: bool) -> Option<Gesture> {
+        if pressed != self.pressed {
+            self.counter += 1;
+            if self.counter >= DEBOUNCE_TICKS {
+                self.counter = 0;
+                self.pressed = pressed;
+                return self.edge();
+            }
+        } else {
+            self.counter = 0;
+        }
+
+        self.tick()
+    }
+
+    /// True when the button is released and no gesture is pending
+    pub fn is_idle(&self) -> bool {
+        !self.pressed && self.counter == 0 && self.clicks == 0
+    }
+
+    // Called once the debounced state has changed
+    fn edge(&mut self) -> Option<Gesture> {
+        if self.pressed {
+            self.held = 0;
+            self.long_sent = false;
+            return None;
+        }
+
+        // Release after a long press or repeat is not a click
+        if self.long_sent {
+            self.clicks = 0;
+            return None;
+        }
+
+        self.clicks += 1;
+        if self.clicks >= 2 {
+            self.clicks = 0;
+            return Some(Gesture::Double);
+        }
+        self.released = 0;
+        None
+    }
+
+    fn tick(&mut self) -> Option<Gesture> {
+        if self.pressed {
+            self.held = self.held.saturating_add(1);
+
+            if !self.long_sent && self.held >= LONG_TICKS {
+                self.long_sent = true;
+                self.clicks = 0;
+                return Some(Gesture::Long);
+            }
+
+            if self.held >= REPEAT_DELAY_TICKS
+                && (self.held - REPEAT_DELAY_TICKS) % REPEAT_INTERVAL_TICKS == 0
+            {
+                return Some(Gesture::Repeat);
+            }
+        } else if self.clicks > 0 {
+            self.released += 1;
+            if self.released >= DOUBLE_TICKS {
+                self.clicks = 0;
+                return Some(Gesture::Short);
+            }
+        }
+
+        None
+    }
+}
+
+/// Queue of detected gestures, drops the oldest event instead of failing when full
+pub struct EventQueue {
+    queue: Queue<ButtonEvent, U8>,
+    pub dropped: u16,
+}
+
+impl EventQueue {
+    pub fn new() -> EventQueue {
+        EventQueue {
+            queue: Queue::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, event: ButtonEvent) {
+        if let Err(event) = self.queue.enqueue(event) {
+            self.queue.dequeue();
+            self.queue.enqueue(event).ok();
+            self.dropped = self.dropped.wrapping_add(1);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<ButtonEvent> {
+        self.queue.dequeue()
+    }
+}
+
+/// All buttons on the board together with their event queue.
+///
+/// The EXTI interrupt only calls `wake`, the actual sampling happens in
+/// `scan` on a timer tick. Timers are stopped in STOP mode so the EXTI edge
+/// is what wakes the MCU, after which scanning continues until every button
+/// is idle again.
+pub struct Buttons {
+    pub buttons: [Button; BUTTON_COUNT],
+    pub events: EventQueue,
+    pub active: bool,
+}
+
+impl Buttons {
+    pub fn new() -> Buttons {
+        Buttons {
+            buttons: [Button::new(0)],
+            events: EventQueue::new(),
+            active: false,
+        }
+    }
+
+    /// Called from the EXTI handler, starts scanning
+    pub fn wake(&mut self) {
+        self.active = true;
+    }
+
+    /// Samples all buttons, `pressed` is indexed by button id. Returns true
+    /// if new events were queued.
+    pub fn scan(&mut self, pressed: &[bool; BUTTON_COUNT]) -> bool {
+        let mut new_events = false;
+        let mut idle = true;
+
+        for (button, pressed) in self.buttons.iter_mut().zip(pressed.iter()) {
+            if let Some(gesture) = button.update(*pressed) {
+                self.events.push(ButtonEvent {
+                    id: button.id,
+                    gesture: gesture,
+                });
+                new_events = true;
+            }
+            idle &= button.is_idle();
+        }
+
+        self.active = !idle;
+        new_events
+    }
+
+    pub fn next_event(&mut self) -> Option<ButtonEvent> {
+        self.events.pop()
+    }
+}
@@ -0,0 +1,138 @@
+use heapless::{consts::*, Vec};
+use protocol::CalibrationPoint;
+
+use crate::breathalyzer::Breathalyzer;
+use crate::nvm::{Nvm, CALIBRATION};
+
+/// ADC samples averaged for a clean air reading
+pub const SAMPLES: u32 = 16;
+
+pub const MAX_POINTS: usize = 8;
+
+const MAGIC: u16 = 0xCA1C;
+// Magic, clean air reading, point count, points and checksum
+const STORED_SIZE: usize = 2 + 2 + 1 + 4 * MAX_POINTS + 2;
+
+/// Sensor calibration, stored in EEPROM.
+///
+/// The clean air reading is taken after warm up with nobody blowing into the
+/// sensor, and is used as the baseline until a higher one has been seen. The
+/// points, sorted by falling ratio, map the reading relative to the baseline
+/// to blood alcohol content and are imported from a reference instrument.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    pub clean_air: u16,
+    pub points: Vec<CalibrationPoint, U8>,
+}
+
+impl Calibration {
+    pub fn new() -> Calibration {
+        Calibration {
+            clean_air: 0,
+            points: Vec::new(),
+        }
+    }
+
+    /// Loads the stored calibration, an uncalibrated sensor reads 0
+    pub fn load(nvm: &Nvm) -> Calibration {
+        let mut bytes = [0; STORED_SIZE];
+        nvm.read(CALIBRATION, &mut bytes);
+
+        let magic = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let checksum = u16::from_le_bytes([bytes[STORED_SIZE - 2], bytes[STORED_SIZE - 1]]);
+        if magic != MAGIC
+            || checksum != crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2])
+        {
+            return Calibration::new();
+        }
+
+        let mut calibration = Calibration::new();
+        calibration.clean_air = u16::from_le_bytes([bytes[2], bytes[3]]);
+        for i in 0..(bytes[4] as usize).min(MAX_POINTS) {
+            let at = 5 + 4 * i;
+            let point = CalibrationPoint {
+                ratio: u16::from_le_bytes([bytes[at], bytes[at + 1]]),
+                bac: u16::from_le_bytes([bytes[at + 2], bytes[at + 3]]),
+            };
+            calibration.points.push(point).ok();
+        }
+        calibration
+    }
+
+    pub fn save(&self, nvm: &mut Nvm) -> Result<(), ()> {
+        let mut bytes = [0; STORED_SIZE];
+        bytes[..2].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.clean_air.to_le_bytes());
+        bytes[4] = self.points.len() as u8;
+        for (i, point) in self.points.iter().enumerate() {
+            let at = 5 + 4 * i;
+            bytes[at..at + 2].copy_from_slice(&point.ratio.to_le_bytes());
+            bytes[at + 2..at + 4].copy_from_slice(&point.bac.to_le_bytes());
+        }
+
+        let checksum = crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2]);
+        bytes[STORED_SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+        nvm.write(CALIBRATION, &bytes)
+    }
+
+    pub fn is_calibrated(&self) -> bool {
+        self.clean_air != 0
+    }
+
+    /// Takes a clean air reading, the heater has to be warmed up
+    pub fn calibrate(&mut self, breathalyzer: &mut Breathalyzer) -> u16 {
+        let mut sum = 0;
+        for _ in 0..SAMPLES {
+            sum += breathalyzer.read_curr() as u32;
+        }
+
+        self.clean_air = (sum / SAMPLES) as u16;
+        self.clean_air
+    }
+
+    /// Replaces the points, they are sorted by falling ratio
+    pub fn set_points(&mut self, points: &[CalibrationPoint]) -> Result<(), ()> {
+        if points.len() > MAX_POINTS {
+            return Err(());
+        }
+
+        self.points.clear();
+        for point in points {
+            self.points.push(*point).ok();
+        }
+        self.points.sort_unstable_by(|a, b| b.ratio.cmp(&a.ratio));
+        Ok(())
+    }
+
+    /// Blood alcohol content in 0.01 per mille for a reading, interpolated
+    /// between the points. `None` without points.
+    pub fn bac(&self, reading: u16, baseline: u16) -> Option<u16> {
+        if self.points.is_empty() || baseline == 0 {
+            return None;
+        }
+        let ratio = (reading as u32 * 1000 / baseline as u32) as i32;
+
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if ratio >= first.ratio as i32 {
+            return Some(first.bac);
+        }
+        if ratio <= last.ratio as i32 {
+            return Some(last.bac);
+        }
+
+        for pair in self.points.windows(2) {
+            let (high, low) = (pair[0], pair[1]);
+            if ratio <= high.ratio as i32 && ratio >= low.ratio as i32 {
+                let span = high.ratio as i32 - low.ratio as i32;
+                if span == 0 {
+                    return Some(low.bac);
+                }
+                let bac = high.bac as i32
+                    + (low.bac as i32 - high.bac as i32) * (high.ratio as i32 - ratio) / span;
+                return Some(bac as u16);
+            }
+        }
+        None
+    }
+}
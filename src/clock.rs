@@ -0,0 +1,168 @@
+use core::fmt;
+
+use stm32l0xx_hal::{pac, rtc::Instant};
+
+const MS_PER_HOUR: u32 = 3_600_000;
+
+/// Calendar time, kept as seconds since 2000-01-01 00:00:00 in records
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_instant(instant: &Instant) -> DateTime {
+        DateTime {
+            year: 2000 + instant.year() as u16,
+            month: instant.month(),
+            day: instant.day(),
+            hour: instant.hour(),
+            minute: instant.minute(),
+            second: instant.second(),
+        }
+    }
+
+    pub fn to_instant(&self) -> Instant {
+        Instant::new()
+            .set_year((self.year - 2000) as u8)
+            .set_month(self.month)
+            .set_day(self.day)
+            .set_hour(self.hour)
+            .set_minute(self.minute)
+            .set_second(self.second)
+    }
+
+    /// Parses `YYYY-MM-DD HH:MM:SS`, a `T` between date and time also works
+    pub fn parse(s: &str) -> Option<DateTime> {
+        let s = s.trim();
+        if s.len() != 19 {
+            return None;
+        }
+
+        let bytes = s.as_bytes();
+        if bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':' {
+            return None;
+        }
+        if bytes[10] != b' ' && bytes[10] != b'T' {
+            return None;
+        }
+
+        let field = |from: usize, to: usize| s.get(from..to)?.parse::<u16>().ok();
+        let time = DateTime {
+            year: field(0, 4)?,
+            month: field(5, 7)? as u8,
+            day: field(8, 10)? as u8,
+            hour: field(11, 13)? as u8,
+            minute: field(14, 16)? as u8,
+            second: field(17, 19)? as u8,
+        };
+
+        let valid = time.year >= 2000
+            && time.year < 2100
+            && time.month >= 1
+            && time.month <= 12
+            && time.day >= 1
+            && time.day <= days_in_month(time.year, time.month)
+            && time.hour < 24
+            && time.minute < 60
+            && time.second < 60;
+
+        if valid {
+            Some(time)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_seconds(&self) -> u32 {
+        let mut days = 0;
+        for year in 2000..self.year {
+            days += if is_leap(year) { 366 } else { 365 };
+        }
+        for month in 1..self.month {
+            days += days_in_month(self.year, month) as u32;
+        }
+        days += self.day as u32 - 1;
+
+        days * 86_400 + self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32
+    }
+
+    pub fn from_seconds(seconds: u32) -> DateTime {
+        let mut days = seconds / 86_400;
+        let rest = seconds % 86_400;
+
+        let mut year = 2000;
+        loop {
+            let length = if is_leap(year) { 366 } else { 365 };
+            if days < length {
+                break;
+            }
+            days -= length;
+            year += 1;
+        }
+
+        let mut month = 1;
+        while days >= days_in_month(year, month) as u32 {
+            days -= days_in_month(year, month) as u32;
+            month += 1;
+        }
+
+        DateTime {
+            year,
+            month,
+            day: days as u8 + 1,
+            hour: (rest / 3600) as u8,
+            minute: (rest / 60 % 60) as u8,
+            second: (rest % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Milliseconds into the current hour, from the RTC sub-seconds. Reads the
+/// registers directly so short intervals can be timed without the `RTC`
+/// resource, see `elapsed_ms`.
+pub fn millis_in_hour() -> u32 {
+    let rtc = unsafe { &*pac::RTC::ptr() };
+    // Reading SSR and TR locks the shadow registers until DR is read
+    let ss = rtc.ssr.read().bits() & 0xFFFF;
+    let tr = rtc.tr.read().bits();
+    rtc.dr.read();
+    let prediv_s = rtc.prer.read().bits() & 0x7FFF;
+
+    let bcd = |value: u32| (value >> 4 & 0x7) * 10 + (value & 0xF);
+    let seconds = bcd(tr >> 8) * 60 + bcd(tr);
+    seconds * 1000 + prediv_s.saturating_sub(ss) * 1000 / (prediv_s + 1)
+}
+
+/// Milliseconds since `start` from `millis_in_hour`, for intervals under an hour
+pub fn elapsed_ms(start: u32) -> u32 {
+    (millis_in_hour() + MS_PER_HOUR - start) % MS_PER_HOUR
+}
+
+fn is_leap(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
@@ -0,0 +1,248 @@
+//! Line based command console on USART1.
+//!
+//! Bytes are collected in the USART1 interrupt until a newline, the complete
+//! line is then parsed and run by the `console_command` task. There is no
+//! echo, turn on local echo in the terminal.
+//!
+//! The host tools send binary `protocol` frames on the same port instead.
+//! A frame starts and ends with a zero byte and is handled by the
+//! `console_request` task.
+
+use heapless::{consts::*, String, Vec};
+use stm32l0xx_hal::pac;
+
+use crate::clock::DateTime;
+use crate::sober::ELIMINATION_RATE;
+
+pub type Line = String<U64>;
+pub type Frame = Vec<u8, U128>;
+
+/// A complete line or frame
+pub enum Input {
+    Line(Line),
+    Frame(Frame),
+}
+
+/// Records shown by `history` without a count
+pub const HISTORY_DEFAULT: u32 = 10;
+
+pub const HELP: [(&str, &str); 13] = [
+    ("help", "show this text"),
+    ("sensor", "raw sensor reading and baseline"),
+    ("history [n] [profile]", "last n measurements, of one profile"),
+    ("get [name]", "show one or all settings"),
+    ("set <name> <value>", "change and store a setting"),
+    ("calibrate", "store a clean air reading"),
+    ("profiles", "list the user profiles"),
+    ("profile <id> <initials> <limit> [rate] [novice]", "store a profile, in 0.01 per mille (an hour)"),
+    ("profile <id> delete", "remove a profile"),
+    ("sober [profile]", "estimated time until below the limit"),
+    ("radio", "radio statistics"),
+    ("id", "unique id, radio identity and readout protection"),
+    ("clock [YYYY-MM-DD HH:MM:SS]", "show or set the clock"),
+    ("reboot", "restart the device"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command<'a> {
+    Help,
+    Sensor,
+    /// Count and profile
+    History(u32, Option<u8>),
+    Get(Option<&'a str>),
+    Set(&'a str, u16),
+    Calibrate,
+    Profiles,
+    /// Id, initials, limit, elimination rate and novice mode
+    SetProfile(u8, &'a str, u16, u16, bool),
+    DeleteProfile(u8),
+    /// The selected profile without one
+    Sober(Option<u8>),
+    Radio,
+    Id,
+    Clock(Option<DateTime>),
+    Reboot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    Unknown,
+    /// Bad arguments, holds the usage of the command
+    Usage(&'static str),
+}
+
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let line = line.trim();
+    let (name, args) = match line.find(' ') {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    let mut words = args.split_whitespace();
+
+    let command = match name {
+        "help" | "?" => Command::Help,
+        "sensor" => Command::Sensor,
+        "history" => {
+            let n = match words.next() {
+                None => HISTORY_DEFAULT,
+                Some(n) => n.parse().map_err(|_| usage("history"))?,
+            };
+            let profile = match words.next() {
+                None => None,
+                Some(id) => Some(id.parse().map_err(|_| usage("history"))?),
+            };
+            Command::History(n, profile)
+        }
+        "get" => Command::Get(words.next()),
+        "set" => {
+            let name = words.next().ok_or(usage("set"))?;
+            let value = words.next().and_then(|v| v.parse().ok()).ok_or(usage("set"))?;
+            Command::Set(name, value)
+        }
+        "calibrate" => Command::Calibrate,
+        "profiles" => Command::Profiles,
+        "profile" => {
+            let id = words.next().and_then(|id| id.parse().ok()).ok_or(usage("profile"))?;
+            match words.next().ok_or(usage("profile"))? {
+                "delete" => Command::DeleteProfile(id),
+                initials => {
+                    let limit = words.next().and_then(|v| v.parse().ok()).ok_or(usage("profile"))?;
+                    let mut rate = ELIMINATION_RATE;
+                    let mut novice = false;
+                    for word in &mut words {
+                        match word {
+                            "novice" => novice = true,
+                            _ => rate = word.parse().map_err(|_| usage("profile"))?,
+                        }
+                    }
+                    Command::SetProfile(id, initials, limit, rate, novice)
+                }
+            }
+        }
+        "sober" => match words.next() {
+            None => Command::Sober(None),
+            Some(id) => Command::Sober(Some(id.parse().map_err(|_| usage("sober"))?)),
+        },
+        "radio" => Command::Radio,
+        "id" => Command::Id,
+        "clock" if args.is_empty() => Command::Clock(None),
+        "clock" => Command::Clock(Some(DateTime::parse(args).ok_or(usage("clock"))?)),
+        "reboot" => Command::Reboot,
+        _ => return Err(ParseError::Unknown),
+    };
+
+    // Extra arguments are most likely a typo
+    if words.next().is_some() && name != "clock" {
+        return Err(usage(name));
+    }
+    Ok(command)
+}
+
+fn usage(name: &str) -> ParseError {
+    let usage = HELP
+        .iter()
+        .find(|(u, _)| u.split(' ').next() == Some(name))
+        .map(|(u, _)| *u)
+        .unwrap_or("");
+    ParseError::Usage(usage)
+}
+
+/// Collects received bytes into lines and frames
+pub struct InputBuffer {
+    line: Line,
+    frame: Frame,
+    in_frame: bool,
+    overflow: bool,
+}
+
+impl InputBuffer {
+    pub fn new() -> InputBuffer {
+        InputBuffer {
+            line: Line::new(),
+            frame: Frame::new(),
+            in_frame: false,
+            overflow: false,
+        }
+    }
+
+    /// Adds a byte, returns the line or frame when it is complete. Too long input is dropped.
+    pub fn push(&mut self, byte: u8) -> Option<Input> {
+        if self.in_frame {
+            return self.push_frame(byte);
+        }
+
+        match byte {
+            0 => {
+                // Any half typed line is thrown away
+                self.line.clear();
+                self.overflow = false;
+                self.in_frame = true;
+                None
+            }
+            b'\r' | b'\n' => {
+                let line = core::mem::replace(&mut self.line, Line::new());
+                let overflow = self.overflow;
+                self.overflow = false;
+
+                if overflow || line.is_empty() {
+                    None
+                } else {
+                    Some(Input::Line(line))
+                }
+            }
+            // Backspace and delete
+            0x08 | 0x7F => {
+                self.line.pop();
+                None
+            }
+            0x20..=0x7E => {
+                if self.line.push(byte as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn push_frame(&mut self, byte: u8) -> Option<Input> {
+        if byte != 0 {
+            if self.frame.push(byte).is_err() {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        // Repeated zeros between frames are skipped
+        if self.frame.is_empty() {
+            return None;
+        }
+
+        let frame = core::mem::replace(&mut self.frame, Frame::new());
+        let overflow = self.overflow;
+        self.overflow = false;
+        self.in_frame = false;
+
+        if overflow {
+            None
+        } else {
+            Some(Input::Frame(frame))
+        }
+    }
+}
+
+/// Lets a received byte wake the MCU from STOP mode. USART1 is clocked
+/// from HSI16, which it keeps running in STOP, and wakes on RXNE. WUS and
+/// the clock can only be changed while the USART is disabled.
+pub fn enable_wakeup() {
+    unsafe {
+        let rcc = &*pac::RCC::ptr();
+        let usart = &*pac::USART1::ptr();
+
+        usart.cr1.modify(|_, w| w.ue().clear_bit());
+        rcc.ccipr.modify(|_, w| w.usart1sel().bits(0b10));
+        usart.cr3.modify(|_, w| w.wus().bits(0b11).ucesm().set_bit());
+        usart.cr1.modify(|_, w| w.uesm().set_bit());
+        usart.cr1.modify(|_, w| w.ue().set_bit());
+    }
+}
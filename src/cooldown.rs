@@ -0,0 +1,108 @@
+//! Keeps readings apart so they mean something. After a measurement the
+//! sensor has to come back close to its baseline, and the `cooldown`
+//! setting has to pass, before the next one starts.
+//!
+//! Alcohol from the lungs keeps the sensor low for a while. A big drop that
+//! is gone within `FAST_RECOVERY_S` is more likely alcohol left in the
+//! mouth, which reads far too high until it has evaporated.
+
+use crate::diagnostics::{push_line, Lines};
+
+/// Back at the baseline when the reading is within this of it
+const RECOVERED_PERCENT: u32 = 97;
+
+/// Drops larger than this, in percent of the baseline, are judged
+const SPIKE_PERCENT: u32 = 10;
+
+/// Recovering this fast from a spike suggests mouth alcohol
+const FAST_RECOVERY_S: u16 = 10;
+
+/// A sensor that hasn't recovered by now is trusted again anyway
+const MAX_WAIT_S: u16 = 600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The last reading recovered suspiciously fast, holds the seconds
+    MouthAlcohol(u16),
+    /// Measurements can start again, holds the seconds waited
+    Ready(u16),
+}
+
+/// Shown when mouth alcohol is suspected
+pub fn warning() -> Lines {
+    let mut lines = Lines::new();
+    push_line(&mut lines, format_args!("Mouth alcohol?"));
+    push_line(&mut lines, format_args!("Result too high"));
+    push_line(&mut lines, format_args!("Rinse with water,"));
+    push_line(&mut lines, format_args!("wait 15 minutes and"));
+    push_line(&mut lines, format_args!("blow again"));
+    lines
+}
+
+pub struct Cooldown {
+    /// Seconds since the last measurement, `None` when ready
+    elapsed_s: Option<u16>,
+    min_interval_s: u16,
+    spike: bool,
+    recovered: bool,
+    /// The countdown is on the display and is refreshed every second
+    pub shown: bool,
+}
+
+impl Cooldown {
+    pub fn new() -> Cooldown {
+        Cooldown {
+            elapsed_s: None,
+            min_interval_s: 0,
+            spike: false,
+            recovered: false,
+            shown: false,
+        }
+    }
+
+    /// Starts over after a reading of `raw` against `baseline`
+    pub fn start(&mut self, raw: u16, baseline: u16, min_interval_s: u16) {
+        self.elapsed_s = Some(0);
+        self.min_interval_s = min_interval_s;
+        self.spike = (raw as u32) * 100 < baseline as u32 * (100 - SPIKE_PERCENT);
+        self.recovered = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.elapsed_s.is_some()
+    }
+
+    /// The last reading may still turn out to be mouth alcohol
+    pub fn judging(&self) -> bool {
+        self.spike && !self.recovered && self.elapsed_s.map_or(false, |elapsed| elapsed < FAST_RECOVERY_S)
+    }
+
+    /// Seconds left of the minimum interval, the sensor may need longer
+    pub fn remaining_s(&self) -> u16 {
+        self.elapsed_s
+            .map_or(0, |elapsed| self.min_interval_s.saturating_sub(elapsed))
+    }
+
+    /// Called every second with the sensor reading, `None` if the heater is
+    /// off and the sensor can't be judged
+    pub fn tick(&mut self, raw: Option<u16>, baseline: u16) -> Option<Event> {
+        let elapsed = self.elapsed_s? + 1;
+        self.elapsed_s = Some(elapsed);
+
+        if !self.recovered {
+            self.recovered = match raw {
+                Some(raw) => raw as u32 * 100 >= baseline as u32 * RECOVERED_PERCENT,
+                None => true,
+            };
+            if self.recovered && raw.is_some() && self.spike && elapsed <= FAST_RECOVERY_S {
+                return Some(Event::MouthAlcohol(elapsed));
+            }
+        }
+
+        if (self.recovered || elapsed >= MAX_WAIT_S) && elapsed >= self.min_interval_s {
+            self.elapsed_s = None;
+            return Some(Event::Ready(elapsed));
+        }
+        None
+    }
+}
@@ -0,0 +1,203 @@
+use core::fmt::{self, Write};
+
+use protocol::telemetry::CrashSite;
+
+#[cfg(not(debug_assertions))]
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(not(debug_assertions))]
+use stm32l0xx_hal::{delay::Delay, pac, prelude::*, rcc::Config, spi, spi::NoMiso};
+
+#[cfg(not(debug_assertions))]
+use crate::{
+    board::Board,
+    diagnostics::Lines,
+    oled::{Oled, PanelCommands},
+};
+
+pub const STACK_WORDS: usize = 16;
+pub const MESSAGE_LEN: usize = 96;
+pub const RECORD_SIZE: usize = core::mem::size_of::<CrashRecord>();
+
+// Changed with the layout, so a record left by older firmware is ignored
+const MAGIC: u32 = 0xC2A5_4ED1;
+
+/// Information about the last panic, kept in the `CRASH` RAM region of
+/// `memory.x` which is not touched by the startup code.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    pub magic: u32,
+    pub line: u32,
+    /// `CrashSite::hash_file` of the file, 0 without a location
+    pub file: u32,
+    pub sp: u32,
+    pub len: u32,
+    pub stack: [u32; STACK_WORDS],
+    pub message: [u8; MESSAGE_LEN],
+    pub checksum: u32,
+}
+
+extern "C" {
+    static mut _crash_record: CrashRecord;
+}
+
+impl CrashRecord {
+    /// The panic message including file and line
+    pub fn message(&self) -> &str {
+        let len = (self.len as usize).min(MESSAGE_LEN);
+        core::str::from_utf8(&self.message[..len]).unwrap_or("")
+    }
+
+    /// File and line for the telemetry, the message is too long for it
+    pub fn site(&self) -> CrashSite {
+        CrashSite {
+            file: self.file as u16,
+            line: self.line.min(u16::max_value() as u32) as u16,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, RECORD_SIZE) }
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Option<CrashRecord> {
+        let record = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const CrashRecord) };
+        if record.is_valid() {
+            Some(record)
+        } else {
+            None
+        }
+    }
+
+    pub fn report<W: Write>(&self, w: &mut W) -> fmt::Result {
+        writeln!(w, "Crash: {}", self.message())?;
+        write!(w, "sp {:08x}:", self.sp)?;
+        for word in self.stack.iter() {
+            write!(w, " {:08x}", word)?;
+        }
+        writeln!(w)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.compute_checksum()
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let bytes = self.as_bytes();
+        crc16::State::<crc16::XMODEM>::calculate(&bytes[..RECORD_SIZE - 4]) as u32
+    }
+}
+
+/// Takes the crash record left by a panic before the last reset, if any
+pub fn take() -> Option<CrashRecord> {
+    unsafe {
+        let record = core::ptr::read_volatile(&_crash_record);
+        core::ptr::write_volatile(&mut _crash_record.magic, 0);
+
+        if record.is_valid() {
+            Some(record)
+        } else {
+            None
+        }
+    }
+}
+
+// Writes as much as fits into the message buffer
+struct MessageWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for MessageWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if self.len >= self.buf.len() {
+                return Err(fmt::Error);
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(debug_assertions))]
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Release builds store the panic in the crash record, show an error on
+/// the display and reset. Debug builds use `panic_semihosting` instead.
+#[cfg(not(debug_assertions))]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    // A panic while handling a panic just resets
+    if PANICKING.load(Ordering::Relaxed) {
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+    PANICKING.store(true, Ordering::Relaxed);
+
+    let record = unsafe { &mut _crash_record };
+    record.magic = 0;
+
+    let mut writer = MessageWriter {
+        buf: &mut record.message,
+        len: 0,
+    };
+    write!(writer, "{}", info).ok();
+    record.len = writer.len as u32;
+    record.line = info.location().map(|l| l.line()).unwrap_or(0);
+    record.file = info.location().map(|l| CrashSite::hash_file(l.file()) as u32).unwrap_or(0);
+
+    // Stack snapshot, without reading past the top of the stack
+    let sp = cortex_m::register::msp::read();
+    let top = unsafe { &_crash_record as *const _ as u32 };
+    record.sp = sp;
+    for (i, word) in record.stack.iter_mut().enumerate() {
+        let addr = sp + 4 * i as u32;
+        *word = if addr < top {
+            unsafe { core::ptr::read_volatile(addr as *const u32) }
+        } else {
+            0
+        };
+    }
+
+    record.checksum = record.compute_checksum();
+    record.magic = MAGIC;
+
+    show_error();
+    cortex_m::asm::delay(32_000_000);
+
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+// Sets up the display from scratch, the `Oled` resource may be in any state
+#[cfg(not(debug_assertions))]
+fn show_error() {
+    let dp = unsafe { pac::Peripherals::steal() };
+    let cp = unsafe { cortex_m::Peripherals::steal() };
+
+    let mut rcc = dp.RCC.freeze(Config::hsi16());
+    let board = Board::take(dp.GPIOA, dp.GPIOB, dp.GPIOC, &mut rcc);
+
+    let mut cs = board.oled_cs;
+    cs.set_low().ok();
+
+    let spi = dp
+        .SPI2
+        .spi((board.oled_sck, NoMiso, board.oled_mosi), spi::MODE_0, 1_000_000.hz(), &mut rcc);
+    let delay = Delay::new(cp.SYST, rcc.clocks);
+
+    let commands = match cortex_m::singleton!(: PanelCommands = PanelCommands::new()) {
+        Some(commands) => commands,
+        None => return,
+    };
+    let mut oled = Oled::new(spi, board.oled_dc, board.oled_reset, delay, commands);
+    let mut lines = Lines::new();
+    crate::diagnostics::push_line(&mut lines, format_args!("Error - restarting"));
+    oled.show_lines(lines);
+}
@@ -0,0 +1,75 @@
+use core::fmt::{self, Write};
+use heapless::{consts::*, String, Vec};
+
+use crate::battery::Battery;
+use crate::power::Power;
+use crate::radio_stats::RadioStats;
+use crate::watchdog::ResetCause;
+
+/// One line of Font6x12 text across the display
+pub type Line = String<U21>;
+pub type Lines = Vec<Line, U5>;
+
+/// Diagnostics pages, cycled through with long presses
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Page {
+    System,
+    Radio,
+}
+
+impl Page {
+    /// The page after this one, `None` leaves the diagnostics
+    pub fn next(self) -> Option<Page> {
+        match self {
+            Page::System => Some(Page::Radio),
+            Page::Radio => None,
+        }
+    }
+}
+
+pub fn push_line(lines: &mut Lines, args: fmt::Arguments) {
+    let mut line = Line::new();
+    line.write_fmt(args).ok();
+    lines.push(line).ok();
+}
+
+pub fn system_page(
+    reset_cause: ResetCause,
+    battery: &Battery,
+    power: &Power,
+    log_len: u32,
+    dropped_buttons: u16,
+) -> Lines {
+    let mut lines = Lines::new();
+
+    push_line(&mut lines, format_args!("Reset: {}", reset_cause.name()));
+    push_line(
+        &mut lines,
+        format_args!("Bat {}mV {}%", battery.mv, battery.percent),
+    );
+    push_line(&mut lines, format_args!("Avg {}uA", power.average_ua()));
+    push_line(&mut lines, format_args!("Log {} events", log_len));
+    push_line(&mut lines, format_args!("Btn dropped {}", dropped_buttons));
+
+    lines
+}
+
+/// `budget_ms` is the airtime left in the duty cycle
+pub fn radio_page(stats: &RadioStats, uptime_s: u32, budget_ms: u32) -> Lines {
+    let mut lines = Lines::new();
+    let duty = stats.duty_permille(uptime_s);
+
+    push_line(&mut lines, format_args!("RSSI {} SNR {}", stats.rssi, stats.snr));
+    push_line(
+        &mut lines,
+        format_args!("TX {}/{} {}ms", stats.tx_done, stats.tx, stats.average_latency_ms()),
+    );
+    push_line(
+        &mut lines,
+        format_args!("RX {} bad {} crc {}", stats.rx, stats.rx_invalid, stats.crc_errors),
+    );
+    push_line(&mut lines, format_args!("Retry {} duty {}.{}%", stats.retries, duty / 10, duty % 10));
+    push_line(&mut lines, format_args!("Airtime left {}s", budget_ms / 1000));
+
+    lines
+}
@@ -0,0 +1,100 @@
+/// Seconds without activity before the display is dimmed
+pub const DIM_AFTER_S: u16 = 20;
+
+/// Seconds without activity before the display is turned off
+pub const OFF_AFTER_S: u16 = 60;
+
+/// Seconds the same content may stay on screen before it is shifted
+pub const SHIFT_AFTER_S: u16 = 30;
+
+/// Offsets cycled through to move static content around
+const SHIFT_PATTERN: [(i32, i32); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayState {
+    On,
+    Dimmed,
+    Off,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayAction {
+    Dim,
+    Off,
+    Shift,
+}
+
+/// Inactivity and burn-in bookkeeping for the display, driven by a 1 s tick
+pub struct DisplayPower {
+    pub state: DisplayState,
+    pub contrast: u8,
+    pub dim_after: u16,
+    pub off_after: u16,
+    idle: u16,
+    static_for: u16,
+    shift: usize,
+}
+
+impl DisplayPower {
+    pub fn new() -> DisplayPower {
+        DisplayPower {
+            state: DisplayState::Off,
+            contrast: 0xCF,
+            dim_after: DIM_AFTER_S,
+            off_after: OFF_AFTER_S,
+            idle: 0,
+            static_for: 0,
+            shift: 0,
+        }
+    }
+
+    /// Registers user activity or new content, returns true if the display was not fully on
+    pub fn activity(&mut self) -> bool {
+        let woken = self.state != DisplayState::On;
+        self.state = DisplayState::On;
+        self.idle = 0;
+        self.static_for = 0;
+        woken
+    }
+
+    /// Advances the timers one second
+    pub fn tick(&mut self) -> Option<DisplayAction> {
+        if self.state == DisplayState::Off {
+            return None;
+        }
+
+        self.idle = self.idle.saturating_add(1);
+        self.static_for = self.static_for.saturating_add(1);
+
+        if self.idle >= self.off_after {
+            self.state = DisplayState::Off;
+            return Some(DisplayAction::Off);
+        }
+
+        if self.state == DisplayState::On && self.idle >= self.dim_after {
+            self.state = DisplayState::Dimmed;
+            return Some(DisplayAction::Dim);
+        }
+
+        if self.static_for >= SHIFT_AFTER_S {
+            self.static_for = 0;
+            self.shift = (self.shift + 1) % SHIFT_PATTERN.len();
+            return Some(DisplayAction::Shift);
+        }
+
+        None
+    }
+
+    /// Contrast to use in the current state
+    pub fn current_contrast(&self) -> u8 {
+        match self.state {
+            DisplayState::Dimmed => self.contrast / 8,
+            _ => self.contrast,
+        }
+    }
+
+    /// Pixel offset applied to everything drawn
+    pub fn offset(&self) -> (i32, i32) {
+        SHIFT_PATTERN[self.shift]
+    }
+}
@@ -0,0 +1,134 @@
+//! EU868 time on air and the duty cycle limits of its sub-bands, shared by
+//! LongFi and the LoRaWAN stack.
+
+/// The limits hold over any hour, ETSI EN 300 220
+const WINDOW_MS: u64 = 3_600_000;
+/// The window moves on in steps of `WINDOW_MS / SLOTS`
+const SLOTS: usize = 12;
+const SLOT_MS: u64 = WINDOW_MS / SLOTS as u64;
+
+/// Sub-bands with their own duty cycle limit, 1/divisor
+pub const BANDS: [(u32, u32, u32); 5] = [
+    (863_000_000, 868_000_000, 100),
+    (868_000_000, 868_600_000, 100),
+    (868_700_000, 869_200_000, 1000),
+    (869_400_000, 869_650_000, 10),
+    (869_700_000, 870_000_000, 100),
+];
+
+pub fn band(frequency: u32) -> Option<usize> {
+    BANDS
+        .iter()
+        .position(|(min, max, _)| frequency >= *min && frequency < *max)
+}
+
+/// Time on air in ms of a packet with explicit header and CRC, CR 4/5 and
+/// an 8 symbol preamble
+pub fn airtime_ms(spreading_factor: u8, bandwidth_hz: u32, len: usize) -> u32 {
+    let sf = spreading_factor as i32;
+    let symbol_us = ((1u32 << sf) as u64 * 1_000_000 / bandwidth_hz as u64) as i32;
+    // Low data rate optimization is on for symbols of 16 ms and more
+    let low_rate = if symbol_us >= 16_000 { 1 } else { 0 };
+
+    let numerator = 8 * len as i32 - 4 * sf + 28 + 16;
+    let denominator = 4 * (sf - 2 * low_rate);
+    let payload_symbols = 8 + ((numerator + denominator - 1) / denominator).max(0) * 5;
+
+    // 8 + 4.25 symbols of preamble
+    let us = (payload_symbols * 4 + 49) * symbol_us / 4;
+    (us as u32 + 999) / 1000
+}
+
+/// Airtime spent in each band over the last hour, in `SLOTS` slots that
+/// drop out of the window one at a time, so no hour ever holds more than
+/// the limit. What was sent before a reset is unknown, so the device starts
+/// as if it had used the limit evenly in the hour before: only a slot's
+/// worth is free at first, more as the slots drop out.
+pub struct DutyCycle {
+    /// Per band, a ring of the airtime in ms of each slot
+    used_ms: [[u32; SLOTS]; 5],
+    /// Number of the current slot since boot
+    slot: u64,
+}
+
+impl DutyCycle {
+    pub fn new() -> DutyCycle {
+        let mut duty_cycle = DutyCycle {
+            used_ms: [[0; SLOTS]; 5],
+            slot: 0,
+        };
+        for (b, used) in duty_cycle.used_ms.iter_mut().enumerate() {
+            // All but the current slot
+            for slot in used.iter_mut().skip(1) {
+                *slot = limit_ms(b) / SLOTS as u32;
+            }
+        }
+        duty_cycle
+    }
+
+    // Clears the slots that started since the last call
+    fn advance(&mut self, now_ms: u64) {
+        let slot = now_ms / SLOT_MS;
+        if slot <= self.slot {
+            return;
+        }
+        let new = (slot - self.slot).min(SLOTS as u64);
+        for i in 1..=new {
+            let index = ((self.slot + i) % SLOTS as u64) as usize;
+            for used in self.used_ms.iter_mut() {
+                used[index] = 0;
+            }
+        }
+        self.slot = slot;
+    }
+
+    fn used(&self, b: usize) -> u32 {
+        self.used_ms[b].iter().sum()
+    }
+
+    /// Airtime in ms left in the band of `frequency`
+    pub fn remaining_ms(&mut self, frequency: u32, now_ms: u64) -> u32 {
+        self.advance(now_ms);
+        band(frequency)
+            .map(|b| limit_ms(b).saturating_sub(self.used(b)))
+            .unwrap_or(0)
+    }
+
+    /// How long to wait before `airtime_ms` can be sent on `frequency`, 0
+    /// if it can go now. Frequencies outside the bands and packets longer
+    /// than the limit are never allowed.
+    pub fn wait_ms(&mut self, frequency: u32, airtime_ms: u32, now_ms: u64) -> u64 {
+        self.advance(now_ms);
+        let b = match band(frequency) {
+            Some(b) => b,
+            None => return u64::max_value(),
+        };
+
+        let mut used = self.used(b);
+        // Drops the slots from the oldest on until there is room, the one
+        // dropped last leaves the window as slot `self.slot + age` starts
+        for age in 0..=SLOTS as u64 {
+            if used + airtime_ms <= limit_ms(b) {
+                return if age == 0 { 0 } else { (self.slot + age) * SLOT_MS - now_ms };
+            }
+            if age < SLOTS as u64 {
+                let index = ((self.slot + 1 + age) % SLOTS as u64) as usize;
+                used -= self.used_ms[b][index];
+            }
+        }
+        u64::max_value()
+    }
+
+    /// Accounts for a transmission
+    pub fn spend(&mut self, frequency: u32, airtime_ms: u32, now_ms: u64) {
+        self.advance(now_ms);
+        if let Some(b) = band(frequency) {
+            let index = (self.slot % SLOTS as u64) as usize;
+            self.used_ms[b][index] = self.used_ms[b][index].saturating_add(airtime_ms);
+        }
+    }
+}
+
+fn limit_ms(band: usize) -> u32 {
+    (WINDOW_MS / BANDS[band].2 as u64) as u32
+}
@@ -0,0 +1,111 @@
+use crate::nvm::{Nvm, EVENT_LOG, EVENT_LOG_HEAD, EVENT_LOG_SIZE};
+
+const ENTRY_SIZE: u32 = 8;
+pub const LOG_ENTRIES: u32 = EVENT_LOG_SIZE / ENTRY_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    /// Device booted, `arg` is the reset cause
+    Boot = 1,
+    /// The supervisor stopped feeding the watchdog, `arg` is the mask of stalled tasks
+    Watchdog = 2,
+    /// Shut down at critical battery, `arg` is the battery percentage
+    Shutdown = 3,
+    /// Reset after a panic, `data` is the line of the panic
+    Crash = 4,
+    /// A firmware update changed state, `arg` is the `update::Status` and
+    /// `data` the low half of the version
+    Update = 5,
+    /// A remote command started a measurement, `data` is the requester
+    RemoteTrigger = 6,
+    Unknown = 0xFF,
+}
+
+impl EventKind {
+    pub fn from_u8(kind: u8) -> EventKind {
+        match kind {
+            1 => EventKind::Boot,
+            2 => EventKind::Watchdog,
+            3 => EventKind::Shutdown,
+            4 => EventKind::Crash,
+            5 => EventKind::Update,
+            6 => EventKind::RemoteTrigger,
+            _ => EventKind::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    pub kind: EventKind,
+    pub arg: u8,
+    pub data: u16,
+    pub time: u32,
+}
+
+impl Event {
+    fn to_bytes(&self) -> [u8; ENTRY_SIZE as usize] {
+        let data = self.data.to_le_bytes();
+        let time = self.time.to_le_bytes();
+        [
+            self.kind as u8,
+            self.arg,
+            data[0],
+            data[1],
+            time[0],
+            time[1],
+            time[2],
+            time[3],
+        ]
+    }
+
+    fn from_bytes(bytes: &[u8; ENTRY_SIZE as usize]) -> Event {
+        Event {
+            kind: EventKind::from_u8(bytes[0]),
+            arg: bytes[1],
+            data: u16::from_le_bytes([bytes[2], bytes[3]]),
+            time: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+/// Ring buffer of events in EEPROM that survives resets.
+///
+/// The head word counts every event ever written, so the newest entry is at
+/// `(head - 1) % LOG_ENTRIES`.
+pub struct EventLog {
+    head: u32,
+}
+
+impl EventLog {
+    pub fn new(nvm: &Nvm) -> EventLog {
+        EventLog {
+            head: nvm.read_word(EVENT_LOG_HEAD),
+        }
+    }
+
+    pub fn push(&mut self, nvm: &mut Nvm, event: Event) {
+        let slot = self.head % LOG_ENTRIES;
+        nvm.write(EVENT_LOG + slot * ENTRY_SIZE, &event.to_bytes()).ok();
+
+        self.head = self.head.wrapping_add(1);
+        nvm.write_word(EVENT_LOG_HEAD, self.head).ok();
+    }
+
+    /// Number of events stored
+    pub fn len(&self) -> u32 {
+        self.head.min(LOG_ENTRIES)
+    }
+
+    /// Reads the n:th newest event, 0 being the latest
+    pub fn get(&self, nvm: &Nvm, n: u32) -> Option<Event> {
+        if n >= self.len() {
+            return None;
+        }
+
+        let slot = (self.head - 1 - n) % LOG_ENTRIES;
+        let mut bytes = [0; ENTRY_SIZE as usize];
+        nvm.read(EVENT_LOG + slot * ENTRY_SIZE, &mut bytes);
+        Some(Event::from_bytes(&bytes))
+    }
+}
@@ -0,0 +1,98 @@
+use crate::nvm::{Nvm, HISTORY, HISTORY_HEAD, HISTORY_SIZE};
+
+const ENTRY_SIZE: u32 = 10;
+pub const HISTORY_ENTRIES: u32 = HISTORY_SIZE / ENTRY_SIZE;
+
+/// The result was at or above the limit of the profile
+pub const FLAG_OVER_LIMIT: u8 = 0x01;
+/// The reading was most likely mouth alcohol, see `cooldown`
+pub const FLAG_MOUTH_ALCOHOL: u8 = 0x02;
+
+// The profile is stored in the upper half of the flags byte, so records
+// from before the profiles read as the guest's
+const FLAG_MASK: u8 = 0x0F;
+const PROFILE_SHIFT: u8 = 4;
+
+/// One finished measurement
+#[derive(Clone, Copy, Debug)]
+pub struct Record {
+    /// Seconds since 2000-01-01, see `clock::DateTime`
+    pub time: u32,
+    pub raw: u16,
+    pub baseline: u16,
+    /// Index of the band, see `protocol::severity`, 0 is none
+    pub category: u8,
+    /// `FLAG_*` bits
+    pub flags: u8,
+    /// Who blew, see `profiles`
+    pub profile: u8,
+}
+
+impl Record {
+    fn to_bytes(&self) -> [u8; ENTRY_SIZE as usize] {
+        let time = self.time.to_le_bytes();
+        let raw = self.raw.to_le_bytes();
+        let baseline = self.baseline.to_le_bytes();
+        [
+            time[0],
+            time[1],
+            time[2],
+            time[3],
+            raw[0],
+            raw[1],
+            baseline[0],
+            baseline[1],
+            self.category,
+            self.flags & FLAG_MASK | self.profile << PROFILE_SHIFT,
+        ]
+    }
+
+    fn from_bytes(bytes: &[u8; ENTRY_SIZE as usize]) -> Record {
+        Record {
+            time: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            raw: u16::from_le_bytes([bytes[4], bytes[5]]),
+            baseline: u16::from_le_bytes([bytes[6], bytes[7]]),
+            category: bytes[8],
+            flags: bytes[9] & FLAG_MASK,
+            profile: bytes[9] >> PROFILE_SHIFT,
+        }
+    }
+}
+
+/// Measurement history in EEPROM, a ring buffer like `EventLog`
+pub struct History {
+    head: u32,
+}
+
+impl History {
+    pub fn new(nvm: &Nvm) -> History {
+        History {
+            head: nvm.read_word(HISTORY_HEAD),
+        }
+    }
+
+    pub fn push(&mut self, nvm: &mut Nvm, record: Record) {
+        let slot = self.head % HISTORY_ENTRIES;
+        nvm.write(HISTORY + slot * ENTRY_SIZE, &record.to_bytes()).ok();
+
+        self.head = self.head.wrapping_add(1);
+        nvm.write_word(HISTORY_HEAD, self.head).ok();
+    }
+
+    /// Number of records stored
+    pub fn len(&self) -> u32 {
+        self.head.min(HISTORY_ENTRIES)
+    }
+
+    /// Reads the n:th newest record, 0 being the latest
+    pub fn get(&self, nvm: &Nvm, n: u32) -> Option<Record> {
+        if n >= self.len() {
+            return None;
+        }
+
+        let slot = (self.head - 1 - n) % HISTORY_ENTRIES;
+        let mut bytes = [0; ENTRY_SIZE as usize];
+        nvm.read(HISTORY + slot * ENTRY_SIZE, &mut bytes);
+        Some(Record::from_bytes(&bytes))
+    }
+}
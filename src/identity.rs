@@ -0,0 +1,91 @@
+//! Who the device is on the radio network. The device id is written at
+//! manufacturing with `breathctl identity provision`, units that haven't
+//! been provisioned derive one from the unique id of the MCU. LongFi device
+//! ids are only 16 bits, so those units take another 16 bits of the unique
+//! id into the lower half of the OUI as well.
+
+use crate::nvm::{Nvm, IDENTITY};
+
+/// Upper half of the organization id used until one is provisioned
+pub const DERIVED_OUI: u32 = 0xBEEF_0000;
+
+const MAGIC: u16 = 0x4944;
+// Magic, oui, device id and checksum
+const STORED_SIZE: usize = 2 + 4 + 2 + 2;
+
+/// The 96 bit unique id, RM0367 28.2
+const UID_ADDRESSES: [u32; 3] = [0x1FF8_0050, 0x1FF8_0054, 0x1FF8_0064];
+
+const FLASH_OPTR: u32 = 0x4002_201C;
+
+#[derive(Clone, Copy)]
+pub struct Identity {
+    pub oui: u32,
+    pub device_id: u16,
+    /// False if `device_id` is derived from the unique id
+    pub provisioned: bool,
+}
+
+impl Identity {
+    /// Loads the provisioned identity, or derives one from the unique id
+    pub fn load(nvm: &Nvm) -> Identity {
+        let mut bytes = [0; STORED_SIZE];
+        nvm.read(IDENTITY, &mut bytes);
+
+        let magic = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let checksum = u16::from_le_bytes([bytes[STORED_SIZE - 2], bytes[STORED_SIZE - 1]]);
+        if magic != MAGIC
+            || checksum != crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2])
+        {
+            return Identity::derive(&uid());
+        }
+
+        Identity {
+            oui: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            device_id: u16::from_le_bytes([bytes[6], bytes[7]]),
+            provisioned: true,
+        }
+    }
+
+    /// The identity of a unit that hasn't been provisioned, two different
+    /// checksums of the unique id so it takes 32 bits to collide
+    pub fn derive(uid: &[u8; 12]) -> Identity {
+        Identity {
+            oui: DERIVED_OUI | crc16::State::<crc16::ARC>::calculate(uid) as u32,
+            device_id: crc16::State::<crc16::XMODEM>::calculate(uid),
+            provisioned: false,
+        }
+    }
+
+    pub fn save(&self, nvm: &mut Nvm) -> Result<(), ()> {
+        let mut bytes = [0; STORED_SIZE];
+        bytes[..2].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[2..6].copy_from_slice(&self.oui.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.device_id.to_le_bytes());
+
+        let checksum = crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2]);
+        bytes[STORED_SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+        nvm.write(IDENTITY, &bytes)
+    }
+}
+
+/// The unique id of the MCU, lowest address first
+pub fn uid() -> [u8; 12] {
+    let mut uid = [0; 12];
+    for (chunk, addr) in uid.chunks_mut(4).zip(UID_ADDRESSES.iter()) {
+        let word = unsafe { core::ptr::read_volatile(*addr as *const u32) };
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    uid
+}
+
+/// The readout protection level from the option bytes. At level 0 the keys
+/// in the EEPROM can be read with a debugger.
+pub fn readout_protection() -> u8 {
+    let optr = unsafe { core::ptr::read_volatile(FLASH_OPTR as *const u32) };
+    match optr as u8 {
+        0xAA => 0,
+        0xCC => 2,
+        _ => 1,
+    }
+}
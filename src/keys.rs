@@ -0,0 +1,56 @@
+use crate::nvm::{Nvm, KEYS};
+
+const MAGIC: u16 = 0x4B59;
+// Magic, keys and checksum
+const STORED_SIZE: usize = 2 + 8 + 8 + 16 + 2;
+
+/// Network identity and root key, provisioned over the console
+#[derive(Clone, Copy)]
+pub struct Keys {
+    pub dev_eui: [u8; 8],
+    pub app_eui: [u8; 8],
+    pub app_key: [u8; 16],
+}
+
+impl Keys {
+    /// Loads the provisioned keys, `None` if the device has not been provisioned
+    pub fn load(nvm: &Nvm) -> Option<Keys> {
+        let mut bytes = [0; STORED_SIZE];
+        nvm.read(KEYS, &mut bytes);
+
+        let magic = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let checksum = u16::from_le_bytes([bytes[STORED_SIZE - 2], bytes[STORED_SIZE - 1]]);
+        if magic != MAGIC
+            || checksum != crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2])
+        {
+            return None;
+        }
+
+        let mut keys = Keys {
+            dev_eui: [0; 8],
+            app_eui: [0; 8],
+            app_key: [0; 16],
+        };
+        keys.dev_eui.copy_from_slice(&bytes[2..10]);
+        keys.app_eui.copy_from_slice(&bytes[10..18]);
+        keys.app_key.copy_from_slice(&bytes[18..34]);
+        Some(keys)
+    }
+
+    pub fn save(&self, nvm: &mut Nvm) -> Result<(), ()> {
+        let mut bytes = [0; STORED_SIZE];
+        bytes[..2].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[2..10].copy_from_slice(&self.dev_eui);
+        bytes[10..18].copy_from_slice(&self.app_eui);
+        bytes[18..34].copy_from_slice(&self.app_key);
+
+        let checksum = crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2]);
+        bytes[STORED_SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+        nvm.write(KEYS, &bytes)
+    }
+}
+
+/// Whether keys and identity may be provisioned, see `protocol::may_provision`
+pub fn may_provision(stored: Option<&Keys>, current_key: Option<&[u8; 16]>) -> bool {
+    protocol::may_provision(stored.map(|keys| &keys.app_key), current_key)
+}
@@ -0,0 +1,107 @@
+// Log message table, shared with the host decoder in `host/`. Only ever
+// append to it, the id is what ends up in the log stream.
+
+pub const LEVELS: [&str; 5] = ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+pub const MODULES: [&str; 8] = [
+    "main", "radio", "sensor", "power", "button", "display", "watchdog", "battery",
+];
+
+pub const BOOT: u16 = 1;
+pub const CRASH: u16 = 2;
+pub const WATCHDOG_STARVED: u16 = 3;
+pub const BUTTON_IRQ: u16 = 4;
+pub const RADIO_IRQ: u16 = 5;
+pub const TX_START: u16 = 6;
+pub const TX_DONE: u16 = 7;
+pub const RX_PACKET: u16 = 8;
+pub const REMOTE_TRIGGER: u16 = 9;
+pub const MEASURE_START: u16 = 10;
+pub const MEASURE_RESULT: u16 = 11;
+pub const WARM_UP_DONE: u16 = 12;
+pub const HEATER_OFF: u16 = 13;
+pub const RADIO_SLEEP: u16 = 14;
+pub const BATTERY: u16 = 15;
+pub const BATTERY_LOW: u16 = 16;
+pub const SHUTDOWN: u16 = 17;
+pub const BUTTONS_DROPPED: u16 = 18;
+pub const SENSOR_READ: u16 = 19;
+pub const JOINED: u16 = 20;
+pub const JOIN_FAILED: u16 = 21;
+pub const DOWNLINK: u16 = 22;
+pub const UPLINK_DROPPED: u16 = 23;
+pub const COMMAND: u16 = 24;
+pub const COMMAND_REJECTED: u16 = 25;
+pub const UPDATE_STATUS: u16 = 26;
+pub const UPDATE_RECEIVED: u16 = 27;
+pub const UPDATE_CONFIRMED: u16 = 28;
+pub const FRAGMENT_INVALID: u16 = 29;
+pub const UPLINK_HELD: u16 = 30;
+pub const REMOTE_TRIGGER_REJECTED: u16 = 31;
+pub const UNSIGNED_TRIGGER: u16 = 32;
+pub const READOUT_UNPROTECTED: u16 = 33;
+pub const MOUTH_ALCOHOL: u16 = 34;
+pub const COOLDOWN_DONE: u16 = 35;
+pub const ALERT_RAISED: u16 = 36;
+pub const ALERT_RETRY: u16 = 37;
+pub const ALERT_ACKNOWLEDGED: u16 = 38;
+pub const ALERT_DUPLICATE: u16 = 39;
+pub const MEASURE_DROPPED: u16 = 40;
+pub const RADIO_EVENT_DROPPED: u16 = 41;
+pub const SENSOR_VALUE: u16 = 42;
+pub const UPLINK_ENCODE_FAILED: u16 = 43;
+pub const HELD_UPLINK_DROPPED: u16 = 44;
+pub const PROVISION_REFUSED: u16 = 45;
+pub const PROVISION_UNPROTECTED: u16 = 46;
+pub const ALERT_GAVE_UP: u16 = 47;
+
+/// Format strings by id, `{}` is replaced by the arguments in order
+pub const MESSAGES: &[(u16, &str)] = &[
+    (BOOT, "boot, reset cause {}"),
+    (CRASH, "crashed before reset at line {}"),
+    (WATCHDOG_STARVED, "watchdog starved, stalled tasks {}"),
+    (BUTTON_IRQ, "button edge"),
+    (RADIO_IRQ, "radio DIO0"),
+    (TX_START, "tx {} bytes"),
+    (TX_DONE, "tx done"),
+    (RX_PACKET, "rx {} bytes"),
+    (REMOTE_TRIGGER, "remote trigger from {}, counter {}"),
+    (MEASURE_START, "measuring, baseline {}"),
+    (MEASURE_RESULT, "result in band {}"),
+    (WARM_UP_DONE, "warm up done"),
+    (HEATER_OFF, "heater off after {} s idle"),
+    (RADIO_SLEEP, "radio asleep"),
+    (BATTERY, "battery {} mV {}%"),
+    (BATTERY_LOW, "battery low, {} mV"),
+    (SHUTDOWN, "shutting down, battery {} mV"),
+    (BUTTONS_DROPPED, "{} button events dropped"),
+    (SENSOR_READ, "raw {} baseline {}"),
+    (JOINED, "joined as {}"),
+    (JOIN_FAILED, "join failed"),
+    (DOWNLINK, "downlink port {}, {} bytes, rssi -{} dBm"),
+    (UPLINK_DROPPED, "uplink dropped, error {}"),
+    (COMMAND, "command {}, counter {}"),
+    (COMMAND_REJECTED, "command rejected, reason {} counter {}"),
+    (UPDATE_STATUS, "update to version {}, status {}"),
+    (UPDATE_RECEIVED, "update received, {} fragments"),
+    (UPDATE_CONFIRMED, "update to version {} confirmed"),
+    (FRAGMENT_INVALID, "invalid update fragment, {} bytes"),
+    (UPLINK_HELD, "uplink held for the duty cycle, {} s"),
+    (REMOTE_TRIGGER_REJECTED, "remote trigger from {} rejected, reason {}"),
+    (UNSIGNED_TRIGGER, "unsigned remote trigger ignored"),
+    (READOUT_UNPROTECTED, "keys provisioned without readout protection"),
+    (MOUTH_ALCOHOL, "reading recovered in {} s, mouth alcohol suspected"),
+    (COOLDOWN_DONE, "cooldown done after {} s"),
+    (ALERT_RAISED, "alert {} raised, band {}"),
+    (ALERT_RETRY, "alert {} not acknowledged, attempt {}"),
+    (ALERT_ACKNOWLEDGED, "alert {} acknowledged after {} attempts"),
+    (ALERT_DUPLICATE, "alert for band {} suppressed, already sent"),
+    (MEASURE_DROPPED, "measurement already queued, end of blowing dropped"),
+    (RADIO_EVENT_DROPPED, "radio interrupt dropped, event queue full"),
+    (SENSOR_VALUE, "sensor value {}"),
+    (UPLINK_ENCODE_FAILED, "uplink does not fit the buffer, dropped"),
+    (HELD_UPLINK_DROPPED, "held uplinks full, dropped one of rank {}"),
+    (PROVISION_REFUSED, "provisioning refused, keys are stored"),
+    (PROVISION_UNPROTECTED, "keys refused at readout protection level 0"),
+    (ALERT_GAVE_UP, "alert {} given up after {} attempts"),
+];
@@ -0,0 +1,161 @@
+//! Binary logging over RTT.
+//!
+//! Instead of formatting text on the device every log call writes a small
+//! frame: level and module, the message id from `log_messages.rs`, a
+//! timestamp and the arguments as varints. Frames are COBS encoded and end
+//! with a zero byte. The RTT channel never blocks, frames that don't fit are
+//! dropped. Decode the stream with `host/src/bin/logdecode.rs`.
+
+use rtt_target::{rtt_init, UpChannel};
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Module {
+    Main = 0,
+    Radio = 1,
+    Sensor = 2,
+    Power = 3,
+    Button = 4,
+    Display = 5,
+    Watchdog = 6,
+    Battery = 7,
+}
+
+/// Calls above this level are compiled out, selected with the `log-level-*` features
+#[cfg(feature = "log-level-trace")]
+pub const MAX_LEVEL: Level = Level::Trace;
+#[cfg(all(feature = "log-level-debug", not(feature = "log-level-trace")))]
+pub const MAX_LEVEL: Level = Level::Debug;
+#[cfg(all(
+    feature = "log-level-warn",
+    not(any(feature = "log-level-debug", feature = "log-level-trace"))
+))]
+pub const MAX_LEVEL: Level = Level::Warn;
+#[cfg(all(
+    feature = "log-level-error",
+    not(any(
+        feature = "log-level-warn",
+        feature = "log-level-debug",
+        feature = "log-level-trace"
+    ))
+))]
+pub const MAX_LEVEL: Level = Level::Error;
+#[cfg(not(any(
+    feature = "log-level-error",
+    feature = "log-level-warn",
+    feature = "log-level-debug",
+    feature = "log-level-trace"
+)))]
+pub const MAX_LEVEL: Level = Level::Info;
+
+const MAX_ARGS: usize = 4;
+// Header, id, timestamp and arguments as varints of at most 5 bytes each
+const MAX_FRAME: usize = 3 + 5 + MAX_ARGS * 5;
+const MAX_ENCODED: usize = MAX_FRAME + MAX_FRAME / 254 + 2;
+
+static mut CHANNEL: Option<UpChannel> = None;
+static mut TIME: u32 = 0;
+
+pub fn init() {
+    let channels = rtt_init! {
+        up: {
+            0: {
+                size: 1024
+                mode: NoBlockSkip
+                name: "log"
+            }
+        }
+    };
+
+    unsafe {
+        CHANNEL = Some(channels.up.0);
+    }
+}
+
+/// Sets the timestamp in seconds put in following frames
+pub fn set_time(seconds: u32) {
+    unsafe {
+        TIME = seconds;
+    }
+}
+
+/// Writes one frame, use the level macros instead of calling this directly
+pub fn write(level: Level, module: Module, id: u16, args: &[u32]) {
+    let mut frame = [0u8; MAX_FRAME];
+    frame[0] = (level as u8) << 4 | module as u8;
+    frame[1] = id as u8;
+    frame[2] = (id >> 8) as u8;
+
+    let mut len = 3;
+    len += varint(unsafe { TIME }, &mut frame[len..]);
+    for arg in args.iter().take(MAX_ARGS) {
+        len += varint(*arg, &mut frame[len..]);
+    }
+
+    let mut encoded = [0u8; MAX_ENCODED];
+    let n = cobs::encode(&frame[..len], &mut encoded);
+    encoded[n] = 0;
+
+    cortex_m::interrupt::free(|_| unsafe {
+        if let Some(channel) = &mut CHANNEL {
+            channel.write(&encoded[..n + 1]);
+        }
+    });
+}
+
+// LEB128, returns the number of bytes written
+fn varint(mut value: u32, buf: &mut [u8]) -> usize {
+    let mut i = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[i] = byte;
+            return i + 1;
+        }
+        buf[i] = byte | 0x80;
+        i += 1;
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $module:expr, $id:expr $(, $arg:expr)*) => {
+        if ($level as u8) <= ($crate::logger::MAX_LEVEL as u8) {
+            $crate::logger::write($level, $module, $id, &[$($arg as u32),*]);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($t:tt)*) => { log!($crate::logger::Level::Error, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($t:tt)*) => { log!($crate::logger::Level::Warn, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($t:tt)*) => { log!($crate::logger::Level::Info, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($t:tt)*) => { log!($crate::logger::Level::Debug, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($t:tt)*) => { log!($crate::logger::Level::Trace, $($t)*) };
+}
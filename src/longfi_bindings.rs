@@ -79,8 +79,8 @@ pub extern "C" fn set_antenna_pins(mode: AntPinsMode, _power: u8) {
     }
 }
 
-static mut EN_TCXO: Option<stm32l0xx_hal::gpio::gpiob::PB5<Output<PushPull>>> = None;
-pub fn set_tcxo_pins(pin: stm32l0xx_hal::gpio::gpiob::PB5<Output<PushPull>>) {
+static mut EN_TCXO: Option<crate::board::Tcxo> = None;
+pub fn set_tcxo_pins(pin: crate::board::Tcxo) {
     unsafe {
         EN_TCXO = Some(pin);
     }
@@ -127,6 +127,85 @@ pub extern "C" fn spi_in_out(s: *mut Spi, out_data: u8) -> u8 {
     in_data
 }
 
+type RadioSpi = hal::spi::Spi<
+    SPI1,
+    (
+        stm32l0xx_hal::gpio::gpiob::PB3<Input<Floating>>,
+        PA6<Input<Floating>>,
+        PA7<Input<Floating>>,
+    ),
+>;
+
+static mut RADIO_SPI: Option<RadioSpi> = None;
+
+pub fn set_spi(spi: RadioSpi) {
+    unsafe {
+        RADIO_SPI = Some(spi);
+    }
+}
+
+const REG_OP_MODE: u8 = 0x01;
+const OP_MODE_LORA_SLEEP: u8 = 0x80;
+
+/// Writes a SX1276 register directly, bypassing the LongFi driver
+pub fn write_register(addr: u8, value: u8) {
+    unsafe {
+        if let Some(spi) = &mut RADIO_SPI {
+            spi_nss(false);
+            spi.send(addr | 0x80).unwrap();
+            block!(spi.read()).unwrap();
+            spi.send(value).unwrap();
+            block!(spi.read()).unwrap();
+            spi_nss(true);
+        }
+    }
+}
+
+/// Reads a SX1276 register directly, bypassing the LongFi driver
+pub fn read_register(addr: u8) -> u8 {
+    let mut value = [0];
+    read_burst(addr, &mut value);
+    value[0]
+}
+
+/// Writes consecutive registers, or the FIFO at address 0
+pub fn write_burst(addr: u8, data: &[u8]) {
+    unsafe {
+        if let Some(spi) = &mut RADIO_SPI {
+            spi_nss(false);
+            spi.send(addr | 0x80).unwrap();
+            block!(spi.read()).unwrap();
+            for byte in data {
+                spi.send(*byte).unwrap();
+                block!(spi.read()).unwrap();
+            }
+            spi_nss(true);
+        }
+    }
+}
+
+/// Reads consecutive registers, or the FIFO at address 0
+pub fn read_burst(addr: u8, data: &mut [u8]) {
+    unsafe {
+        if let Some(spi) = &mut RADIO_SPI {
+            spi_nss(false);
+            spi.send(addr & 0x7F).unwrap();
+            block!(spi.read()).unwrap();
+            for byte in data.iter_mut() {
+                spi.send(0).unwrap();
+                *byte = block!(spi.read()).unwrap();
+            }
+            spi_nss(true);
+        }
+    }
+}
+
+/// Puts the radio into sleep, the next send or receive wakes it up again
+pub fn radio_sleep() {
+    write_register(REG_OP_MODE, OP_MODE_LORA_SLEEP);
+    set_antenna_pins(AntPinsMode::AntModeSleep, 0);
+}
+
 static mut SPI_NSS: Option<stm32l0xx_hal::gpio::gpioa::PA15<Output<PushPull>>> = None;
 
 pub fn set_spi_nss(pin: stm32l0xx_hal::gpio::gpioa::PA15<Output<PushPull>>) {
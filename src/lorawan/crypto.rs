@@ -0,0 +1,87 @@
+//! LoRaWAN 1.0.x frame encryption, message integrity codes and key derivation
+
+use aes::{Aes128, BlockCipher, NewBlockCipher};
+use cmac::{Cmac, Mac, NewMac};
+
+pub type Key = [u8; 16];
+
+/// Direction byte of the A and B0 blocks
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up = 0,
+    Down = 1,
+}
+
+fn aes_encrypt(key: &Key, block: &mut [u8; 16]) {
+    let cipher = Aes128::new(key.into());
+    cipher.encrypt_block(block.into());
+}
+
+fn cmac(key: &Key, parts: &[&[u8]]) -> [u8; 4] {
+    let mut mac = Cmac::<Aes128>::new_varkey(key).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    let code = mac.finalize().into_bytes();
+    [code[0], code[1], code[2], code[3]]
+}
+
+// The A and B0 blocks only differ in the first and the last byte
+fn block(first: u8, dir: Direction, dev_addr: u32, fcnt: u32, last: u8) -> [u8; 16] {
+    let mut b = [0; 16];
+    b[0] = first;
+    b[5] = dir as u8;
+    b[6..10].copy_from_slice(&dev_addr.to_le_bytes());
+    b[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    b[15] = last;
+    b
+}
+
+/// Encrypts or decrypts a FRMPayload in place, the operation is its own inverse
+pub fn crypt_payload(key: &Key, dir: Direction, dev_addr: u32, fcnt: u32, payload: &mut [u8]) {
+    for (i, chunk) in payload.chunks_mut(16).enumerate() {
+        let mut s = block(0x01, dir, dev_addr, fcnt, i as u8 + 1);
+        aes_encrypt(key, &mut s);
+        for (byte, k) in chunk.iter_mut().zip(s.iter()) {
+            *byte ^= k;
+        }
+    }
+}
+
+/// MIC of a data frame, `msg` is everything from the MHDR up to the MIC
+pub fn data_mic(key: &Key, dir: Direction, dev_addr: u32, fcnt: u32, msg: &[u8]) -> [u8; 4] {
+    let b0 = block(0x49, dir, dev_addr, fcnt, msg.len() as u8);
+    cmac(key, &[&b0, msg])
+}
+
+/// MIC of a join request or a decrypted join accept
+pub fn join_mic(app_key: &Key, msg: &[u8]) -> [u8; 4] {
+    cmac(app_key, &[msg])
+}
+
+/// Decrypts a join accept after the MHDR, the network encrypts it with AES decrypt
+pub fn decrypt_join_accept(app_key: &Key, payload: &mut [u8]) {
+    for chunk in payload.chunks_mut(16) {
+        let mut block = [0; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+        aes_encrypt(app_key, &mut block);
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+/// Derives the network (`0x01`) or application (`0x02`) session key
+pub fn session_key(
+    app_key: &Key,
+    kind: u8,
+    app_nonce: &[u8; 3],
+    net_id: &[u8; 3],
+    dev_nonce: u16,
+) -> Key {
+    let mut key = [0; 16];
+    key[0] = kind;
+    key[1..4].copy_from_slice(app_nonce);
+    key[4..7].copy_from_slice(net_id);
+    key[7..9].copy_from_slice(&dev_nonce.to_le_bytes());
+    aes_encrypt(app_key, &mut key);
+    key
+}
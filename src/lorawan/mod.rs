@@ -0,0 +1,737 @@
+//! LoRaWAN 1.0.x Class A end device on the SX1276, the `lorawan` feature
+//! uses it instead of LongFi.
+//!
+//! The device joins over the air with the provisioned `Keys`. Every uplink
+//! is followed by two receive windows, timed by the caller: when
+//! `take_timer` is true a timer is (re)started to call `tick` every
+//! `TICK_MS`, and `handle_timer` once `tick` returns true. Waits for the
+//! duty cycle can be much longer, the caller checks `wait_over` now and then
+//! instead and calls `handle_timer` as well.
+
+mod crypto;
+mod radio;
+pub mod region;
+
+use heapless::{consts::*, Vec};
+
+use crate::keys::Keys;
+use crypto::{Direction, Key};
+use region::Region;
+
+pub use radio::Packet;
+
+/// Port of the telemetry uplinks
+pub const TELEMETRY_PORT: u8 = 1;
+/// Unsigned measurement triggers of older firmware came on this port, they
+/// are logged and ignored
+pub const REMOTE_TRIGGER_PORT: u8 = 6;
+
+/// Period of the timer driving `tick`
+pub const TICK_MS: u32 = 100;
+
+const JOIN_REQUEST_LEN: usize = 23;
+
+/// Uplinks without any downlink before asking the network for one
+const ADR_ACK_LIMIT: u16 = 64;
+/// Uplinks after asking before lowering the data rate
+const ADR_ACK_DELAY: u16 = 32;
+
+/// How long the second window stays open
+const RX2_WINDOW_MS: u32 = 1000;
+/// Extra time when a packet is coming in as a window ends
+const RX_EXTEND_MS: u32 = 200;
+
+const MTYPE_JOIN_REQUEST: u8 = 0x00;
+const MTYPE_JOIN_ACCEPT: u8 = 0x20;
+const MTYPE_UNCONFIRMED_UP: u8 = 0x40;
+const MTYPE_UNCONFIRMED_DOWN: u8 = 0x60;
+const MTYPE_CONFIRMED_DOWN: u8 = 0xA0;
+
+const FCTRL_ADR: u8 = 0x80;
+const FCTRL_ADR_ACK_REQ: u8 = 0x40;
+const FCTRL_ACK: u8 = 0x20;
+
+// MAC command identifiers
+const LINK_CHECK: u8 = 0x02;
+const LINK_ADR: u8 = 0x03;
+const DUTY_CYCLE: u8 = 0x04;
+const RX_PARAM_SETUP: u8 = 0x05;
+const DEV_STATUS: u8 = 0x06;
+const NEW_CHANNEL: u8 = 0x07;
+const RX_TIMING_SETUP: u8 = 0x08;
+
+pub type Payload = Vec<u8, U51>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// No keys have been provisioned
+    NoKeys,
+    /// Longer than the current data rate allows
+    TooLong,
+}
+
+/// What happened, returned by the event handlers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    None,
+    /// An uplink or join request is out, the receive windows follow
+    TxDone,
+    Joined,
+    /// No join accept in either window, the next send tries again
+    JoinFailed,
+    /// A valid frame, see `downlink` for its application data
+    Downlink,
+    /// A frame with a bad MIC, address or counter
+    Invalid,
+    /// A packet with a bad payload CRC
+    CrcError,
+    /// Both windows closed without a downlink
+    Idle,
+}
+
+/// Interrupts for the radio task
+#[derive(Clone, Copy, Debug)]
+pub enum Irq {
+    Dio0,
+    /// `tick` returned true
+    Timer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Window {
+    None,
+    Tx,
+    /// Waiting for the first window
+    Rx1Wait,
+    Rx1,
+    Rx2,
+}
+
+struct Session {
+    dev_addr: u32,
+    nwk_skey: Key,
+    app_skey: Key,
+    fcnt_up: u32,
+    /// Lowest frame counter accepted on the next downlink
+    fcnt_down: u32,
+}
+
+pub struct LoRaWan {
+    keys: Option<Keys>,
+    session: Option<Session>,
+    pub region: Region,
+    pub data_rate: u8,
+    pub tx_power: u8,
+    rx1_dr_offset: u8,
+    rx2_data_rate: u8,
+    rx2_frequency: u32,
+    rx_delay_ms: u32,
+    adr_ack_cnt: u16,
+    ack_pending: bool,
+    /// Answers to MAC commands, sent in the next uplink
+    mac_answers: Vec<u8, U15>,
+    join_attempts: u8,
+    dev_nonce: u16,
+    joining: bool,
+    tx_frequency: u32,
+    window: Window,
+    /// Waits for the join or the duty cycle
+    pending: Option<(u8, Payload)>,
+    /// Requested, armed by `take_timer`
+    timer_ms: Option<u32>,
+    countdown_ms: Option<u32>,
+    /// When the duty cycle allows the waiting join or uplink
+    ready_at_ms: Option<u64>,
+    /// Battery level for DevStatusAns, 1-254 or 255 when unknown
+    pub battery: u8,
+    rx_buf: [u8; 256],
+    downlink: Option<(u8, usize, usize)>,
+    pub last_packet: Option<Packet>,
+}
+
+impl LoRaWan {
+    pub fn new(keys: Option<Keys>) -> LoRaWan {
+        radio::init();
+
+        LoRaWan {
+            keys,
+            session: None,
+            region: Region::new(),
+            data_rate: region::MAX_DATA_RATE,
+            tx_power: 0,
+            rx1_dr_offset: 0,
+            rx2_data_rate: region::RX2_DATA_RATE,
+            rx2_frequency: region::RX2_FREQUENCY,
+            rx_delay_ms: region::RECEIVE_DELAY1_MS,
+            adr_ack_cnt: 0,
+            ack_pending: false,
+            mac_answers: Vec::new(),
+            join_attempts: 0,
+            dev_nonce: 0,
+            joining: false,
+            tx_frequency: 0,
+            window: Window::None,
+            pending: None,
+            timer_ms: None,
+            countdown_ms: None,
+            ready_at_ms: None,
+            battery: 255,
+            rx_buf: [0; 256],
+            downlink: None,
+            last_packet: None,
+        }
+    }
+
+    /// New keys end the session, the next send joins again
+    pub fn set_keys(&mut self, keys: Keys) {
+        self.keys = Some(keys);
+        self.session = None;
+    }
+
+    /// Address assigned by the network, `None` until joined
+    pub fn dev_addr(&self) -> Option<u32> {
+        self.session.as_ref().map(|session| session.dev_addr)
+    }
+
+    /// True while transmitting or listening
+    pub fn is_busy(&self) -> bool {
+        self.window != Window::None
+    }
+
+    /// True until DIO0 signals the end of the transmission
+    pub fn is_transmitting(&self) -> bool {
+        self.window == Window::Tx
+    }
+
+    /// Nothing is going on or waiting, the timer is not needed
+    pub fn is_idle(&self) -> bool {
+        !self.is_busy() && !self.joining && self.pending.is_none()
+    }
+
+    /// Joins right away instead of with the first uplink
+    pub fn start(&mut self, now_ms: u64) {
+        if self.keys.is_some() && self.session.is_none() && self.is_idle() {
+            self.join(now_ms);
+        }
+    }
+
+    /// True if the timer has to be restarted, after handling any event
+    pub fn take_timer(&mut self) -> bool {
+        match self.timer_ms.take() {
+            Some(ms) => {
+                self.countdown_ms = Some(ms);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called every `TICK_MS` while the timer runs, true when it is up
+    pub fn tick(&mut self, elapsed_ms: u32) -> bool {
+        match self.countdown_ms {
+            Some(ms) if ms > elapsed_ms => {
+                self.countdown_ms = Some(ms - elapsed_ms);
+                false
+            }
+            Some(_) => {
+                self.countdown_ms = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// True once the duty cycle allows the join or uplink that waits for
+    /// it, `handle_timer` sends it then
+    pub fn wait_over(&mut self, now_ms: u64) -> bool {
+        match self.ready_at_ms {
+            Some(at) if now_ms >= at && !self.is_busy() => {
+                self.ready_at_ms = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Port and application data of the last downlink
+    pub fn downlink(&self) -> Option<(u8, &[u8])> {
+        self.downlink
+            .map(|(port, start, end)| (port, &self.rx_buf[start..end]))
+    }
+
+    /// Queues an uplink, joining first if needed. Only the newest message
+    /// waits for the receive windows, the join or the duty cycle.
+    pub fn send(&mut self, port: u8, data: &[u8], now_ms: u64) -> Result<(), Error> {
+        if self.keys.is_none() {
+            return Err(Error::NoKeys);
+        }
+        let mut payload = Payload::new();
+        payload.extend_from_slice(data).map_err(|_| Error::TooLong)?;
+        self.pending = Some((port, payload));
+
+        // Otherwise it goes out when the windows close or the duty cycle is over
+        if !self.is_busy() && !self.joining {
+            self.flush(now_ms);
+        }
+        Ok(())
+    }
+
+    // Sends the join request or the pending uplink, or waits for the duty cycle
+    fn flush(&mut self, now_ms: u64) {
+        if self.session.is_none() {
+            self.join(now_ms);
+            return;
+        }
+        if self.pending.is_none() {
+            return;
+        }
+
+        // The frame is at most the header, the MAC answers and the MIC longer
+        let len = self.pending.as_ref().map(|(_, payload)| payload.len()).unwrap_or(0);
+        let airtime = region::airtime_ms(self.data_rate, len + self.mac_answers.len() + 13);
+        let frequency = match self.region.pick_channel(airtime, now_ms) {
+            Ok(frequency) => frequency,
+            Err(wait_ms) => {
+                self.ready_at_ms = Some(now_ms.saturating_add(wait_ms));
+                return;
+            }
+        };
+
+        let (port, payload) = self.pending.take().unwrap();
+        let mut frame = [0; 64];
+        let len = self.build_uplink(port, &payload, &mut frame);
+        self.transmit(frequency, &frame[..len], now_ms);
+    }
+
+    fn join(&mut self, now_ms: u64) {
+        let keys = match self.keys {
+            Some(keys) => keys,
+            None => return,
+        };
+
+        // Start fast and fall back to longer range on every failure
+        let data_rate = region::MAX_DATA_RATE - self.join_attempts % (region::MAX_DATA_RATE + 1);
+
+        // Join requests only go out on the default channels
+        let airtime = region::airtime_ms(data_rate, JOIN_REQUEST_LEN);
+        let frequency = match self.region.pick_channel(airtime, now_ms) {
+            Ok(frequency) if region::DEFAULT_CHANNELS.contains(&frequency) => frequency,
+            Ok(_) => region::DEFAULT_CHANNELS[self.join_attempts as usize % 3],
+            Err(wait_ms) => {
+                self.joining = true;
+                self.ready_at_ms = Some(now_ms.saturating_add(wait_ms));
+                return;
+            }
+        };
+
+        self.data_rate = data_rate;
+        self.join_attempts = self.join_attempts.wrapping_add(1);
+
+        // The receiver has to run for the RSSI to be random
+        radio::receive(frequency, region::spreading_factor(self.data_rate));
+        self.dev_nonce = radio::random_u32() as u16;
+
+        let mut frame = [0; JOIN_REQUEST_LEN];
+        frame[0] = MTYPE_JOIN_REQUEST;
+        // The EUIs are provisioned most significant byte first
+        for i in 0..8 {
+            frame[1 + i] = keys.app_eui[7 - i];
+            frame[9 + i] = keys.dev_eui[7 - i];
+        }
+        frame[17..19].copy_from_slice(&self.dev_nonce.to_le_bytes());
+        let mic = crypto::join_mic(&keys.app_key, &frame[..19]);
+        frame[19..].copy_from_slice(&mic);
+
+        self.joining = true;
+        self.transmit(frequency, &frame, now_ms);
+    }
+
+    fn transmit(&mut self, frequency: u32, frame: &[u8], now_ms: u64) {
+        let airtime = region::airtime_ms(self.data_rate, frame.len());
+        self.region.transmitted(frequency, airtime, now_ms);
+        self.tx_frequency = frequency;
+        self.window = Window::Tx;
+
+        radio::transmit(
+            frequency,
+            region::spreading_factor(self.data_rate),
+            region::tx_power_dbm(self.tx_power),
+            frame,
+        );
+    }
+
+    fn build_uplink(&mut self, port: u8, data: &[u8], frame: &mut [u8; 64]) -> usize {
+        let session = self.session.as_mut().unwrap();
+
+        self.adr_ack_cnt = self.adr_ack_cnt.saturating_add(1);
+        if self.adr_ack_cnt >= ADR_ACK_LIMIT + ADR_ACK_DELAY {
+            // No answer from the network, trade speed for range
+            if self.tx_power > 0 {
+                self.tx_power = 0;
+            } else if self.data_rate > 0 {
+                self.data_rate -= 1;
+            } else {
+                self.region.mask = (1 << region::DEFAULT_CHANNELS.len()) - 1;
+            }
+            self.adr_ack_cnt = ADR_ACK_LIMIT;
+        }
+
+        let mut fctrl = FCTRL_ADR | self.mac_answers.len() as u8;
+        if self.adr_ack_cnt >= ADR_ACK_LIMIT {
+            fctrl |= FCTRL_ADR_ACK_REQ;
+        }
+        if self.ack_pending {
+            fctrl |= FCTRL_ACK;
+            self.ack_pending = false;
+        }
+
+        frame[0] = MTYPE_UNCONFIRMED_UP;
+        frame[1..5].copy_from_slice(&session.dev_addr.to_le_bytes());
+        frame[5] = fctrl;
+        frame[6..8].copy_from_slice(&(session.fcnt_up as u16).to_le_bytes());
+        let mut len = 8;
+
+        frame[len..len + self.mac_answers.len()].copy_from_slice(&self.mac_answers);
+        len += self.mac_answers.len();
+        self.mac_answers.clear();
+
+        // Whatever doesn't fit at this data rate is cut, the MIC needs 4 bytes
+        let room = region::MAX_PAYLOAD[self.data_rate as usize].min(frame.len() - len - 5);
+        let data = &data[..data.len().min(room)];
+        frame[len] = port;
+        len += 1;
+        frame[len..len + data.len()].copy_from_slice(data);
+        crypto::crypt_payload(
+            &session.app_skey,
+            Direction::Up,
+            session.dev_addr,
+            session.fcnt_up,
+            &mut frame[len..len + data.len()],
+        );
+        len += data.len();
+
+        let mic = crypto::data_mic(
+            &session.nwk_skey,
+            Direction::Up,
+            session.dev_addr,
+            session.fcnt_up,
+            &frame[..len],
+        );
+        frame[len..len + 4].copy_from_slice(&mic);
+        session.fcnt_up += 1;
+
+        len + 4
+    }
+
+    /// Handles DIO0, the end of a transmission or a received packet
+    pub fn handle_dio0(&mut self, now_ms: u64) -> Event {
+        match radio::irq() {
+            radio::Irq::TxDone if self.window == Window::Tx => {
+                radio::standby();
+                self.window = Window::Rx1Wait;
+                self.timer_ms = Some(if self.joining {
+                    region::JOIN_ACCEPT_DELAY1_MS
+                } else {
+                    self.rx_delay_ms
+                });
+                Event::TxDone
+            }
+            radio::Irq::RxDone if self.window == Window::Rx1 || self.window == Window::Rx2 => {
+                let packet = radio::read_packet(&mut self.rx_buf);
+                let len = packet.len;
+                self.last_packet = Some(packet);
+
+                let event = if self.joining {
+                    self.join_accept(len)
+                } else {
+                    self.data_down(len)
+                };
+
+                if event == Event::Invalid {
+                    // Keep listening for the rest of the window
+                    self.open_window(self.window);
+                } else {
+                    self.close(now_ms);
+                }
+                event
+            }
+            radio::Irq::CrcError => Event::CrcError,
+            _ => Event::None,
+        }
+    }
+
+    /// Handles the end of the time requested with `take_timer`
+    pub fn handle_timer(&mut self, now_ms: u64) -> Event {
+        match self.window {
+            Window::Tx => Event::None,
+            Window::Rx1Wait => {
+                self.open_window(Window::Rx1);
+                self.timer_ms = Some(region::RX2_OFFSET_MS);
+                Event::None
+            }
+            Window::Rx1 | Window::Rx2 if radio::receiving() => {
+                self.timer_ms = Some(RX_EXTEND_MS);
+                Event::None
+            }
+            Window::Rx1 => {
+                self.open_window(Window::Rx2);
+                self.timer_ms = Some(RX2_WINDOW_MS);
+                Event::None
+            }
+            Window::Rx2 => {
+                let joining = self.joining;
+                self.close(now_ms);
+                if joining {
+                    Event::JoinFailed
+                } else {
+                    Event::Idle
+                }
+            }
+            Window::None => {
+                // The duty cycle is over
+                self.flush(now_ms);
+                Event::None
+            }
+        }
+    }
+
+    fn open_window(&mut self, window: Window) {
+        self.window = window;
+        let (frequency, data_rate) = match window {
+            Window::Rx1 => (
+                self.tx_frequency,
+                region::rx1_data_rate(self.data_rate, self.rx1_dr_offset),
+            ),
+            _ => (self.rx2_frequency, self.rx2_data_rate),
+        };
+        radio::receive(frequency, region::spreading_factor(data_rate));
+    }
+
+    // Ends the transaction, a failed join or a waiting uplink goes out next
+    fn close(&mut self, now_ms: u64) {
+        radio::sleep();
+        self.window = Window::None;
+        self.timer_ms = None;
+        self.countdown_ms = None;
+        self.ready_at_ms = None;
+
+        if self.joining && self.session.is_none() {
+            // Tried again on the next send, a waiting message is dropped
+            self.joining = false;
+            self.pending = None;
+            return;
+        }
+        self.joining = false;
+        if self.pending.is_some() {
+            self.flush(now_ms);
+        }
+    }
+
+    fn join_accept(&mut self, len: usize) -> Event {
+        let keys = match self.keys {
+            Some(keys) => keys,
+            None => return Event::Invalid,
+        };
+        if (len != 17 && len != 33) || self.rx_buf[0] != MTYPE_JOIN_ACCEPT {
+            return Event::Invalid;
+        }
+
+        let frame = &mut self.rx_buf[..len];
+        crypto::decrypt_join_accept(&keys.app_key, &mut frame[1..]);
+        if crypto::join_mic(&keys.app_key, &frame[..len - 4]) != frame[len - 4..] {
+            return Event::Invalid;
+        }
+
+        let mut app_nonce = [0; 3];
+        let mut net_id = [0; 3];
+        app_nonce.copy_from_slice(&frame[1..4]);
+        net_id.copy_from_slice(&frame[4..7]);
+        let dev_addr = u32::from_le_bytes([frame[7], frame[8], frame[9], frame[10]]);
+        let dl_settings = frame[11];
+        let rx_delay = frame[12] & 0x0F;
+
+        self.session = Some(Session {
+            dev_addr,
+            nwk_skey: crypto::session_key(&keys.app_key, 0x01, &app_nonce, &net_id, self.dev_nonce),
+            app_skey: crypto::session_key(&keys.app_key, 0x02, &app_nonce, &net_id, self.dev_nonce),
+            fcnt_up: 0,
+            fcnt_down: 0,
+        });
+
+        self.rx1_dr_offset = (dl_settings >> 4) & 0x07;
+        self.rx2_data_rate = dl_settings & 0x0F;
+        self.rx_delay_ms = rx_delay.max(1) as u32 * 1000;
+        self.region = Region::new();
+        if len == 33 {
+            let mut cf_list = [0; 15];
+            cf_list.copy_from_slice(&self.rx_buf[13..28]);
+            self.region.add_cf_list(&cf_list);
+        }
+        self.adr_ack_cnt = 0;
+        self.join_attempts = 0;
+        self.mac_answers.clear();
+
+        Event::Joined
+    }
+
+    fn data_down(&mut self, len: usize) -> Event {
+        self.downlink = None;
+        // MHDR, DevAddr, FCtrl, FCnt and MIC
+        if len < 12 {
+            return Event::Invalid;
+        }
+
+        let mhdr = self.rx_buf[0];
+        if mhdr != MTYPE_UNCONFIRMED_DOWN && mhdr != MTYPE_CONFIRMED_DOWN {
+            return Event::Invalid;
+        }
+
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return Event::Invalid,
+        };
+
+        let frame = &mut self.rx_buf[..len];
+        let dev_addr = u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]);
+        if dev_addr != session.dev_addr {
+            return Event::Invalid;
+        }
+
+        let fctrl = frame[5];
+        let fopts_len = (fctrl & 0x0F) as usize;
+        if 8 + fopts_len + 4 > len {
+            return Event::Invalid;
+        }
+
+        // Only the low 16 bits are sent, assume the counter didn't jump more than that
+        let fcnt16 = u16::from_le_bytes([frame[6], frame[7]]) as u32;
+        let mut fcnt = session.fcnt_down & 0xFFFF_0000 | fcnt16;
+        if fcnt < session.fcnt_down {
+            fcnt += 0x1_0000;
+        }
+
+        let mic = crypto::data_mic(&session.nwk_skey, Direction::Down, dev_addr, fcnt, &frame[..len - 4]);
+        if mic != frame[len - 4..] {
+            return Event::Invalid;
+        }
+        session.fcnt_down = fcnt + 1;
+        self.adr_ack_cnt = 0;
+        if mhdr == MTYPE_CONFIRMED_DOWN {
+            self.ack_pending = true;
+        }
+
+        let mut commands: Vec<u8, U64> = Vec::new();
+        commands.extend_from_slice(&frame[8..8 + fopts_len]).ok();
+
+        let payload_start = 8 + fopts_len;
+        let payload_end = len - 4;
+        if payload_start < payload_end {
+            let port = frame[payload_start];
+            let key = if port == 0 { session.nwk_skey } else { session.app_skey };
+            crypto::crypt_payload(
+                &key,
+                Direction::Down,
+                dev_addr,
+                fcnt,
+                &mut frame[payload_start + 1..payload_end],
+            );
+
+            if port == 0 {
+                commands.extend_from_slice(&frame[payload_start + 1..payload_end]).ok();
+            } else {
+                self.downlink = Some((port, payload_start + 1, payload_end));
+            }
+        }
+
+        self.mac_commands(&commands);
+        Event::Downlink
+    }
+
+    // Applies the network's MAC commands and queues the answers
+    fn mac_commands(&mut self, mut commands: &[u8]) {
+        while let Some((&cid, args)) = commands.split_first() {
+            let size = match cid {
+                LINK_CHECK => 2,
+                LINK_ADR => 4,
+                DUTY_CYCLE => 1,
+                RX_PARAM_SETUP => 4,
+                DEV_STATUS => 0,
+                NEW_CHANNEL => 5,
+                RX_TIMING_SETUP => 1,
+                // The rest can't be parsed without knowing its size
+                _ => return,
+            };
+            if args.len() < size {
+                return;
+            }
+            let args = &args[..size];
+
+            match cid {
+                LINK_ADR => {
+                    let data_rate = args[0] >> 4;
+                    let tx_power = args[0] & 0x0F;
+                    let mut mask = u16::from_le_bytes([args[1], args[2]]);
+                    if (args[3] >> 4) & 0x07 == 6 {
+                        mask = u16::max_value() >> (16 - self.region.channels.len());
+                    }
+
+                    let power_ok = tx_power <= region::MAX_TX_POWER || tx_power == 0x0F;
+                    let rate_ok = data_rate <= region::MAX_DATA_RATE || data_rate == 0x0F;
+                    let mask_ok = self.region.mask_valid(mask);
+                    if power_ok && rate_ok && mask_ok {
+                        if tx_power != 0x0F {
+                            self.tx_power = tx_power;
+                        }
+                        if data_rate != 0x0F {
+                            self.data_rate = data_rate;
+                        }
+                        self.region.mask = mask;
+                    }
+                    self.answer(&[LINK_ADR, (power_ok as u8) << 2 | (rate_ok as u8) << 1 | mask_ok as u8]);
+                }
+                DUTY_CYCLE => {
+                    self.region.max_duty_cycle = args[0] & 0x0F;
+                    self.answer(&[DUTY_CYCLE]);
+                }
+                RX_PARAM_SETUP => {
+                    let frequency = u32::from_le_bytes([args[1], args[2], args[3], 0]) * 100;
+                    let offset_ok = (args[0] >> 4) & 0x07 <= region::MAX_RX1_DR_OFFSET;
+                    let rate_ok = args[0] & 0x0F <= region::MAX_DATA_RATE;
+                    let frequency_ok = frequency >= 863_000_000 && frequency < 870_000_000;
+                    if offset_ok && rate_ok && frequency_ok {
+                        self.rx1_dr_offset = (args[0] >> 4) & 0x07;
+                        self.rx2_data_rate = args[0] & 0x0F;
+                        self.rx2_frequency = frequency;
+                    }
+                    self.answer(&[
+                        RX_PARAM_SETUP,
+                        (frequency_ok as u8) | (rate_ok as u8) << 1 | (offset_ok as u8) << 2,
+                    ]);
+                }
+                DEV_STATUS => {
+                    // The margin is a signed 6 bit value
+                    let snr = self.last_packet.as_ref().map(|p| p.snr).unwrap_or(0);
+                    let margin = snr.max(-32).min(31) as u8 & 0x3F;
+                    self.answer(&[DEV_STATUS, self.battery, margin]);
+                }
+                NEW_CHANNEL => {
+                    let frequency = u32::from_le_bytes([args[1], args[2], args[3], 0]) * 100;
+                    let ok = self.region.set_channel(args[0] as usize, frequency);
+                    self.answer(&[NEW_CHANNEL, if ok { 0x03 } else { 0 }]);
+                }
+                RX_TIMING_SETUP => {
+                    self.rx_delay_ms = (args[0] & 0x0F).max(1) as u32 * 1000;
+                    self.answer(&[RX_TIMING_SETUP]);
+                }
+                _ => {}
+            }
+
+            commands = &commands[1 + size..];
+        }
+    }
+
+    fn answer(&mut self, answer: &[u8]) {
+        // FOpts holds 15 bytes, answers that don't fit are asked for again
+        self.mac_answers.extend_from_slice(answer).ok();
+    }
+}
@@ -0,0 +1,183 @@
+//! Minimal SX1276 LoRa modem driver on the registers in `longfi_bindings`
+
+use longfi_device::AntPinsMode;
+
+use crate::longfi_bindings::{
+    radio_sleep, read_burst, read_register, set_antenna_pins, set_tcxo, write_burst,
+    write_register,
+};
+
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_FIFO_TX_BASE: u8 = 0x0E;
+const REG_FIFO_RX_BASE: u8 = 0x0F;
+const REG_FIFO_RX_CURRENT: u8 = 0x10;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_RX_NB_BYTES: u8 = 0x13;
+const REG_MODEM_STAT: u8 = 0x18;
+const REG_PKT_SNR: u8 = 0x19;
+const REG_PKT_RSSI: u8 = 0x1A;
+const REG_MODEM_CONFIG1: u8 = 0x1D;
+const REG_MODEM_CONFIG2: u8 = 0x1E;
+const REG_PREAMBLE_MSB: u8 = 0x20;
+const REG_PREAMBLE_LSB: u8 = 0x21;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG3: u8 = 0x26;
+const REG_RSSI_WIDEBAND: u8 = 0x2C;
+const REG_INVERT_IQ: u8 = 0x33;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_INVERT_IQ2: u8 = 0x3B;
+const REG_DIO_MAPPING1: u8 = 0x40;
+const REG_TCXO: u8 = 0x4B;
+
+const MODE_LORA: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_CONTINUOUS: u8 = 0x05;
+
+const IRQ_RX_DONE: u8 = 0x40;
+const IRQ_CRC_ERROR: u8 = 0x20;
+const IRQ_TX_DONE: u8 = 0x08;
+
+/// Public network sync word
+const SYNC_WORD: u8 = 0x34;
+
+#[derive(Clone, Copy)]
+pub struct Packet {
+    pub len: usize,
+    pub rssi: i16,
+    /// Signal to noise ratio in dB
+    pub snr: i8,
+}
+
+fn set_mode(mode: u8) {
+    write_register(REG_OP_MODE, MODE_LORA | mode);
+}
+
+/// Puts the modem in LoRa mode, the mode can only change while asleep
+pub fn init() {
+    set_tcxo(true);
+    set_mode(MODE_SLEEP);
+    write_register(REG_TCXO, 0x19);
+    write_register(REG_SYNC_WORD, SYNC_WORD);
+    write_register(REG_PREAMBLE_MSB, 0);
+    write_register(REG_PREAMBLE_LSB, 8);
+    write_register(REG_FIFO_TX_BASE, 0);
+    write_register(REG_FIFO_RX_BASE, 0);
+    set_mode(MODE_STANDBY);
+}
+
+fn configure(frequency: u32, spreading_factor: u8, crc: bool) {
+    let frf = ((frequency as u64) << 19) / 32_000_000;
+    write_burst(REG_FRF_MSB, &[(frf >> 16) as u8, (frf >> 8) as u8, frf as u8]);
+
+    // 125 kHz, coding rate 4/5, explicit header
+    write_register(REG_MODEM_CONFIG1, 0x72);
+    write_register(
+        REG_MODEM_CONFIG2,
+        spreading_factor << 4 | if crc { 0x04 } else { 0 },
+    );
+    // Low data rate optimization above 16 ms symbols, automatic gain
+    let low_rate = if spreading_factor >= 11 { 0x08 } else { 0 };
+    write_register(REG_MODEM_CONFIG3, low_rate | 0x04);
+}
+
+/// Starts sending `data`, DIO0 rises when done
+pub fn transmit(frequency: u32, spreading_factor: u8, power_dbm: i8, data: &[u8]) {
+    set_mode(MODE_STANDBY);
+    configure(frequency, spreading_factor, true);
+
+    // PA_BOOST, output power is 2 + the low nibble
+    let power = (power_dbm.max(2).min(17) - 2) as u8;
+    write_register(REG_PA_CONFIG, 0x80 | 0x70 | power);
+
+    write_register(REG_INVERT_IQ, 0x27);
+    write_register(REG_INVERT_IQ2, 0x1D);
+
+    write_register(REG_FIFO_ADDR_PTR, 0);
+    write_burst(REG_FIFO, data);
+    write_register(REG_PAYLOAD_LENGTH, data.len() as u8);
+
+    write_register(REG_DIO_MAPPING1, 0x40);
+    write_register(REG_IRQ_FLAGS, 0xFF);
+    set_antenna_pins(AntPinsMode::AntModeTx, 0);
+    set_mode(MODE_TX);
+}
+
+/// Listens for a downlink, DIO0 rises when a packet is received
+pub fn receive(frequency: u32, spreading_factor: u8) {
+    set_mode(MODE_STANDBY);
+    // Downlinks have inverted IQ and no payload CRC
+    configure(frequency, spreading_factor, false);
+    write_register(REG_INVERT_IQ, 0x67);
+    write_register(REG_INVERT_IQ2, 0x19);
+
+    write_register(REG_DIO_MAPPING1, 0x00);
+    write_register(REG_IRQ_FLAGS, 0xFF);
+    set_antenna_pins(AntPinsMode::AntModeRx, 0);
+    set_mode(MODE_RX_CONTINUOUS);
+}
+
+pub fn standby() {
+    set_mode(MODE_STANDBY);
+    set_antenna_pins(AntPinsMode::AntModeSleep, 0);
+}
+
+/// Lowest power, the next transmit or receive wakes the modem up
+pub fn sleep() {
+    radio_sleep();
+}
+
+/// What DIO0 meant, the flags are cleared
+pub enum Irq {
+    TxDone,
+    RxDone,
+    CrcError,
+    None,
+}
+
+pub fn irq() -> Irq {
+    let flags = read_register(REG_IRQ_FLAGS);
+    write_register(REG_IRQ_FLAGS, 0xFF);
+
+    if flags & IRQ_TX_DONE != 0 {
+        Irq::TxDone
+    } else if flags & IRQ_CRC_ERROR != 0 {
+        Irq::CrcError
+    } else if flags & IRQ_RX_DONE != 0 {
+        Irq::RxDone
+    } else {
+        Irq::None
+    }
+}
+
+/// Copies the received packet into `buf`
+pub fn read_packet(buf: &mut [u8]) -> Packet {
+    let len = (read_register(REG_RX_NB_BYTES) as usize).min(buf.len());
+    write_register(REG_FIFO_ADDR_PTR, read_register(REG_FIFO_RX_CURRENT));
+    read_burst(REG_FIFO, &mut buf[..len]);
+
+    Packet {
+        len,
+        rssi: read_register(REG_PKT_RSSI) as i16 - 157,
+        snr: read_register(REG_PKT_SNR) as i8 / 4,
+    }
+}
+
+/// True while a preamble or header has been detected
+pub fn receiving() -> bool {
+    read_register(REG_MODEM_STAT) & 0x0B != 0
+}
+
+/// Random bits from the wideband RSSI, the receiver must be running
+pub fn random_u32() -> u32 {
+    let mut value = 0;
+    for _ in 0..32 {
+        value = value << 1 | (read_register(REG_RSSI_WIDEBAND) & 1) as u32;
+    }
+    value
+}
@@ -0,0 +1,155 @@
+//! EU868 channel plan and data rates, the duty cycle bands are in
+//! duty_cycle.rs
+
+use heapless::{consts::*, Vec};
+
+use crate::duty_cycle::{self, band, DutyCycle};
+
+pub const MAX_CHANNELS: usize = 16;
+
+/// Join and data channels every device has
+pub const DEFAULT_CHANNELS: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+
+pub const RX2_FREQUENCY: u32 = 869_525_000;
+pub const RX2_DATA_RATE: u8 = 0;
+
+pub const RECEIVE_DELAY1_MS: u32 = 1000;
+pub const JOIN_ACCEPT_DELAY1_MS: u32 = 5000;
+/// The second window always opens one second after the first
+pub const RX2_OFFSET_MS: u32 = 1000;
+
+pub const MAX_DATA_RATE: u8 = 5;
+/// Highest RX1DROffset, the first window is at most five data rates lower
+pub const MAX_RX1_DR_OFFSET: u8 = 5;
+/// Highest power index, every step is 2 dB below the 16 dBm EIRP
+pub const MAX_TX_POWER: u8 = 7;
+
+/// Application payload limit per data rate, without a repeater
+pub const MAX_PAYLOAD: [usize; 6] = [51, 51, 51, 115, 222, 222];
+
+/// Spreading factor of a data rate, all of DR0-5 are 125 kHz
+pub fn spreading_factor(data_rate: u8) -> u8 {
+    12 - data_rate.min(MAX_DATA_RATE)
+}
+
+pub fn tx_power_dbm(index: u8) -> i8 {
+    16 - 2 * index.min(MAX_TX_POWER) as i8
+}
+
+/// Data rate of the first receive window
+pub fn rx1_data_rate(data_rate: u8, offset: u8) -> u8 {
+    data_rate.saturating_sub(offset)
+}
+
+/// Time on air in ms of a packet at a data rate, all of them are 125 kHz
+pub fn airtime_ms(data_rate: u8, len: usize) -> u32 {
+    duty_cycle::airtime_ms(spreading_factor(data_rate), 125_000, len)
+}
+
+/// Channels and the airtime left in each duty cycle band
+pub struct Region {
+    pub channels: Vec<u32, U16>,
+    /// Bit per channel, set by the network with LinkADRReq
+    pub mask: u16,
+    pub duty: DutyCycle,
+    /// Aggregated duty cycle set by DutyCycleReq, 1/2^n
+    pub max_duty_cycle: u8,
+    // Off time of the aggregated duty cycle
+    ready_at_ms: u64,
+    next_channel: usize,
+}
+
+impl Region {
+    pub fn new() -> Region {
+        let mut channels = Vec::new();
+        channels.extend_from_slice(&DEFAULT_CHANNELS).ok();
+
+        Region {
+            channels,
+            mask: 0b111,
+            duty: DutyCycle::new(),
+            max_duty_cycle: 0,
+            ready_at_ms: 0,
+            next_channel: 0,
+        }
+    }
+
+    /// Adds the channels of a join accept CFList, frequencies in 100 Hz
+    pub fn add_cf_list(&mut self, cf_list: &[u8]) {
+        for chunk in cf_list.chunks(3).take(5) {
+            let frequency = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], 0]) * 100;
+            if frequency != 0 && self.channels.push(frequency).is_ok() {
+                self.mask |= 1 << (self.channels.len() - 1);
+            }
+        }
+    }
+
+    /// Sets a channel from NewChannelReq, false if the frequency is not allowed
+    pub fn set_channel(&mut self, index: usize, frequency: u32) -> bool {
+        if index < DEFAULT_CHANNELS.len() || index >= MAX_CHANNELS {
+            return false;
+        }
+        if frequency != 0 && band(frequency).is_none() {
+            return false;
+        }
+
+        while self.channels.len() <= index {
+            if self.channels.push(0).is_err() {
+                return false;
+            }
+        }
+        self.channels[index] = frequency;
+        if frequency == 0 {
+            self.mask &= !(1 << index);
+        } else {
+            self.mask |= 1 << index;
+        }
+        true
+    }
+
+    /// True if the mask leaves at least one existing channel enabled
+    pub fn mask_valid(&self, mask: u16) -> bool {
+        self.channels
+            .iter()
+            .enumerate()
+            .any(|(i, frequency)| *frequency != 0 && mask & (1 << i) != 0)
+    }
+
+    /// Next enabled channel whose band has `airtime_ms` left, round robin.
+    /// Otherwise the time until one has.
+    pub fn pick_channel(&mut self, airtime_ms: u32, now_ms: u64) -> Result<u32, u64> {
+        if self.ready_at_ms > now_ms {
+            return Err(self.ready_at_ms - now_ms);
+        }
+
+        let count = self.channels.len();
+        let mut wait_ms = u64::max_value();
+
+        for i in 0..count {
+            let index = (self.next_channel + i) % count;
+            let frequency = self.channels[index];
+            if frequency == 0 || self.mask & (1 << index) == 0 {
+                continue;
+            }
+
+            let wait = self.duty.wait_ms(frequency, airtime_ms, now_ms);
+            if wait == 0 {
+                self.next_channel = index + 1;
+                return Ok(frequency);
+            }
+            wait_ms = wait_ms.min(wait);
+        }
+
+        Err(wait_ms)
+    }
+
+    /// Accounts for a transmission, keeping the band and the aggregated
+    /// duty cycle within their limits
+    pub fn transmitted(&mut self, frequency: u32, airtime_ms: u32, now_ms: u64) {
+        self.duty.spend(frequency, airtime_ms, now_ms);
+
+        if self.max_duty_cycle > 0 {
+            self.ready_at_ms = now_ms + ((airtime_ms as u64) << self.max_duty_cycle);
+        }
+    }
+}
@@ -2,31 +2,103 @@
 #![no_main]
 #![no_std]
 
+#[macro_use]
+mod logger;
+mod log_messages;
+
+mod alerts;
+mod battery;
+mod board;
 mod breathalyzer;
+mod button;
 mod buzzer;
+mod calibration;
+mod clock;
+mod console;
+mod cooldown;
+mod crash;
+mod diagnostics;
+mod display_power;
+mod duty_cycle;
+mod event_log;
+mod history;
+mod identity;
+mod keys;
 mod longfi_bindings;
+#[cfg(feature = "lorawan")]
+mod lorawan;
+mod nvm;
 mod oled;
+mod ota;
+mod power;
+mod profiles;
+mod radio_stats;
+mod remote;
+mod settings;
+mod sober;
+mod temperature;
+mod watchdog;
 
 use longfi_bindings::AntennaSwitches;
+#[cfg(not(feature = "lorawan"))]
 use longfi_device::{self, ClientEvent, LongFi, RfConfig, RfEvent};
-use communicator::{Message, Channel};
+#[cfg(not(feature = "lorawan"))]
+use communicator::Message;
 use heapless::consts::*;
+use core::fmt::Write;
+use ufmt::uwrite;
 use core::str::from_utf8;
 
-use crate::breathalyzer::{Breathalyzer, BAC};
+use crate::alerts::Alerts;
+use crate::battery::{Battery, Level};
+use crate::board::{self, Board};
+use crate::breathalyzer::Breathalyzer;
+use crate::button::{Buttons, Gesture, SCAN_PERIOD_MS};
 use crate::buzzer::Buzzer;
-use crate::oled::Oled;
+use crate::calibration::Calibration;
+use crate::clock::DateTime;
+use crate::console::{Command, InputBuffer, ParseError};
+use crate::cooldown::Cooldown;
+use crate::crash::CrashRecord;
+use crate::diagnostics::Page;
+use crate::display_power::DisplayState;
+use crate::duty_cycle::DutyCycle;
+use crate::event_log::{Event, EventKind, EventLog};
+use crate::history::{History, Record, FLAG_MOUTH_ALCOHOL, FLAG_OVER_LIMIT};
+use crate::identity::Identity;
+use crate::keys::Keys;
+use crate::log_messages as msg;
+use crate::logger::Module;
+use crate::nvm::{Nvm, CRASH_RECORD};
+use crate::oled::{Oled, PanelCommands};
+use crate::ota::{Ota, CONFIRM_AFTER_S};
+use crate::power::{Busy, Power, RX_WINDOW_S};
+use crate::profiles::{Profile, Profiles, GUEST};
+use crate::radio_stats::RadioStats;
+use crate::remote::Remote;
+use crate::settings::{SettingError, Settings};
+use crate::temperature::TempSensor;
+use crate::watchdog::{ResetCause, Supervisor, Task, Watchdog, CHECK_PERIOD_S};
+use protocol::{ErrorCode, HistoryRecord, Info, Request, Response, Setting, HISTORY_CHUNK};
+use protocol::alert::{Alert, Tick, ALERT_PORT, MAX_ALERT};
+use protocol::command::{
+    Command as RemoteCommand, Rejection, Reply, SelfTestResult, COMMAND_MARKER, REPLY_RECORDS,
+};
+use protocol::update::{Status as UpdateStatus, FRAGMENT_MARKER};
+use protocol::settings::SETTINGS_INFO;
+use protocol::severity::{Band, Severity};
+use protocol::telemetry::{fault, reset, Telemetry, BAC_UNKNOWN, TELEMETRY_VERSION};
+#[cfg(not(feature = "cayenne-lpp"))]
+use protocol::telemetry::MAX_TELEMETRY;
 
+use rtfm::Mutex;
 use stm32l0xx_hal as hal;
 
-//#[cfg(not(debug_assertions))]
-//use panic_halt as _;
+// Release builds use the panic handler in crash.rs
 
 // Debug imports
 #[cfg(debug_assertions)]
 extern crate panic_semihosting;
-#[cfg(debug_assertions)]
-use cortex_m_semihosting::hprintln;
 
 
 use stm32l0xx_hal::{
@@ -36,12 +108,225 @@ use stm32l0xx_hal::{
     gpio::*,
     pac,
     prelude::*,
-    rcc::Config,
+    pwr::PWR,
+    rcc::{self, Config},
+    rtc::{self, Instant, RTC},
+    serial,
     spi::{self, Mode, NoMiso, Phase, Polarity},
     syscfg, 
     timer,
 };
 
+// LongFi by default, LoRaWAN with the `lorawan` feature. DIO0 and, for
+// LoRaWAN, the receive window timer are handled by `radio_event`.
+#[cfg(not(feature = "lorawan"))]
+type Radio = LongFi;
+#[cfg(not(feature = "lorawan"))]
+type RadioIrq = RfEvent;
+#[cfg(not(feature = "lorawan"))]
+const RADIO_DIO0: RadioIrq = RfEvent::DIO0;
+
+#[cfg(feature = "lorawan")]
+type Radio = lorawan::LoRaWan;
+#[cfg(feature = "lorawan")]
+type RadioIrq = lorawan::Irq;
+#[cfg(feature = "lorawan")]
+const RADIO_DIO0: RadioIrq = lorawan::Irq::Dio0;
+
+/// Seconds from accepting an update until the reset that installs it
+const REBOOT_DELAY_S: u32 = 12;
+
+/// Seconds the empty battery message stays up, counted in STOP mode
+const SHUTDOWN_MESSAGE_S: u32 = 8;
+
+/// Every this many uplinks carry the radio link statistics
+const LINK_STATS_EVERY: u16 = 16;
+
+/// The band the airtime budget is shown for, the one of the LoRaWAN
+/// default channels. LongFi picks its channels itself and is assumed to
+/// stay in it.
+const UPLINK_FREQUENCY: u32 = 868_100_000;
+
+// LongFi's radio settings, for its time on air
+#[cfg(not(feature = "lorawan"))]
+const LONGFI_SPREADING_FACTOR: u8 = 10;
+#[cfg(not(feature = "lorawan"))]
+const LONGFI_BANDWIDTH_HZ: u32 = 125_000;
+
+/// A finished measurement held back until the cooldown has ruled out mouth
+/// alcohol, which would make the alert wrong
+pub struct PendingResult {
+    record: Record,
+    severity: Severity,
+    bac: Option<u16>,
+    telemetry: Telemetry,
+}
+
+/// What `send_radio_message` sends
+pub enum Uplink {
+    Telemetry(Telemetry),
+    /// A signed reply to a remote command
+    Reply(remote::Frame),
+    /// Sent until acknowledged, see `alerts`
+    Alert(Alert),
+}
+
+#[cfg(not(feature = "lorawan"))]
+impl Uplink {
+    // Replies are only sent once, alerts are retried and telemetry is only
+    // worth sending while it is the newest
+    fn rank(&self) -> u8 {
+        match self {
+            Uplink::Telemetry(_) => 0,
+            Uplink::Alert(_) => 1,
+            Uplink::Reply(_) => 2,
+        }
+    }
+}
+
+/// Uplinks held back by the duty cycle
+#[cfg(not(feature = "lorawan"))]
+type HeldUplinks = heapless::Vec<Uplink, U4>;
+
+// Holds back an uplink. Newer telemetry replaces the held one, and with no
+// room left the least important held uplink gives way to a more important
+// one, so telemetry never pushes out a reply or an alert.
+#[cfg(not(feature = "lorawan"))]
+fn hold(held: &mut HeldUplinks, uplink: Uplink) {
+    let rank = uplink.rank();
+    if rank == 0 {
+        if let Some(i) = held.iter().position(|held| held.rank() == 0) {
+            held[i] = uplink;
+            return;
+        }
+    }
+
+    let uplink = match held.push(uplink) {
+        Ok(()) => return,
+        Err(uplink) => uplink,
+    };
+    let least = (0..held.len()).min_by_key(|i| held[*i].rank()).unwrap_or(0);
+    if held[least].rank() < rank {
+        warn!(Module::Radio, msg::HELD_UPLINK_DROPPED, held[least].rank());
+        held[least] = uplink;
+    } else {
+        warn!(Module::Radio, msg::HELD_UPLINK_DROPPED, rank);
+    }
+}
+
+// Telemetry faults of the sensor, calibration and battery state
+fn faults(raw: u16, baseline: u16, calibration: &Calibration, battery: &Battery) -> u8 {
+    let mut faults = 0;
+    if !calibration.is_calibrated() {
+        faults |= fault::UNCALIBRATED;
+    }
+    if raw > baseline {
+        faults |= fault::SENSOR_RANGE;
+    }
+    if battery.level != Level::Ok {
+        faults |= fault::BATTERY_LOW;
+    }
+    faults
+}
+
+fn history_record(record: Record) -> HistoryRecord {
+    HistoryRecord {
+        time: record.time,
+        raw: record.raw,
+        baseline: record.baseline,
+        category: record.category,
+        flags: record.flags,
+        profile: record.profile,
+    }
+}
+
+// Shown when a profile is selected
+fn profile_message(profiles: &Profiles) -> heapless::String<U16> {
+    let mut text = heapless::String::new();
+    match profiles.get(profiles.selected) {
+        None => text.push_str("Guest").ok(),
+        Some(profile) if profile.novice => write!(text, "{} novice", profile.initials()).ok(),
+        Some(profile) => {
+            write!(text, "{} limit {}.{:02}", profile.initials(), profile.limit / 100, profile.limit % 100).ok()
+        }
+    };
+    text
+}
+
+// `ufmt` output to a line of text on the display
+struct Text(heapless::String<U16>);
+
+impl ufmt::uWrite for Text {
+    type Error = ();
+
+    fn write_str(&mut self, s: &str) -> Result<(), ()> {
+        self.0.push_str(s)
+    }
+}
+
+// The icon and name of a band, as the result is shown
+fn band_text(band: &Band) -> heapless::String<U16> {
+    let mut text = Text(heapless::String::new());
+    uwrite!(text, "{}", band).ok();
+    text.0
+}
+
+// The countdown shown while the next measurement has to wait
+fn cooldown_message(cooldown: &Cooldown) -> heapless::String<U16> {
+    let mut text = heapless::String::new();
+    match cooldown.remaining_s() {
+        0 => text.push_str("Sensor settling").ok(),
+        s => write!(text, "Wait {} s", s).ok(),
+    };
+    text
+}
+
+fn reply(result: Result<(), Rejection>) -> Reply {
+    match result {
+        Ok(()) => Reply::Done,
+        Err(rejection) => Reply::Rejected(rejection),
+    }
+}
+
+// Changes and stores a setting for a remote command
+fn save_setting(
+    settings: &mut impl Mutex<T = Settings>,
+    nvm: &mut impl Mutex<T = Nvm>,
+    name: &str,
+    value: u16,
+) -> Reply {
+    match settings.lock(|settings| settings.set(name, value).map(|_| *settings)) {
+        Ok(settings) => match nvm.lock(|nvm| settings.save(nvm)) {
+            Ok(()) => Reply::Done,
+            Err(()) => Reply::Rejected(Rejection::Storage),
+        },
+        Err(_) => Reply::Rejected(Rejection::OutOfRange),
+    }
+}
+
+// Stores or with `None` removes a profile for a console command
+fn store_profile<W: Write>(
+    tx: &mut W,
+    profiles: &mut impl Mutex<T = Profiles>,
+    nvm: &mut impl Mutex<T = Nvm>,
+    id: u8,
+    profile: Option<Profile>,
+) {
+    let result = profiles.lock(|profiles| {
+        profiles
+            .set(id, profile)
+            .map(|_| nvm.lock(|nvm| profiles.save(nvm)))
+    });
+
+    match result {
+        Err(()) => writeln!(tx, "profile ids are 1 to {}", profiles::MAX_PROFILES),
+        Ok(Err(())) => writeln!(tx, "profile {} not stored", id),
+        Ok(Ok(())) if profile.is_some() => writeln!(tx, "profile {} stored", id),
+        Ok(Ok(())) => writeln!(tx, "profile {} deleted", id),
+    }
+    .ok();
+}
+
 #[rtfm::app(device = stm32l0xx_hal::pac, peripherals = true)]
 const APP: () = {
     struct Resources {
@@ -57,44 +342,189 @@ const APP: () = {
         TIME_COUNTER: u16,
         #[init(true)]
         WARM_UP: bool,
+        #[init(0)]
+        HEATER_IDLE: u16,
+        #[init(RX_WINDOW_S)]
+        RADIO_LISTEN: u16,
+        #[init(0)]
+        BATTERY_CHECK: u16,
+        #[init(false)]
+        RESET_REPORTED: bool,
+        #[init(None)]
+        DIAG_PAGE: Option<Page>,
+        #[init(0)]
+        UPTIME: u32,
+        #[init(0)]
+        TELEMETRY_SEQ: u16,
+        #[init(0)]
+        REPORT_COUNTER: u32,
+        #[init(0)]
+        REBOOT_IN: u32,
+        #[init(0)]
+        DISPLAY_OFF_IN: u32,
+        /// Who asked for the measurement about to start, see `Command::Measure`
+        #[init(None)]
+        REQUESTER: Option<u16>,
+        /// The last result, until it is stored and sent
+        #[init(None)]
+        RESULT: Option<PendingResult>,
+        /// Half seconds of beeping left after a result, on at even counts
+        #[init(0)]
+        BEEPS: u8,
 
         EXT: pac::EXTI,
-        BUTTON: gpiob::PB2<Input<PullUp>>,
+        BUTTON: board::Button,
+        BUTTONS: Buttons,
+        TIMER_BUTTON: timer::Timer<pac::TIM6>,
         TIMER_BREATH: timer::Timer<pac::TIM2>,
         TIMER_PWM: timer::Timer<pac::TIM3>,
         TIMER_SEC: timer::Timer<pac::TIM21>,
         TIMER_WARM_UP: timer::Timer<pac::TIM22>,
+        TIMER_RX: timer::Timer<pac::TIM7>,
         BREATHALYZER: Breathalyzer,
         BUZZER: Buzzer,
-        LONGFI: LongFi,
+        RADIO: Radio,
         RADIO_EXTI: gpiob::PB4<Input<PullUp>>,
         OLED: Oled,
+        BATTERY: Battery,
+        POWER: Power,
+        PWR: PWR,
+        SCB: cortex_m::peripheral::SCB,
+        RCC: rcc::Rcc,
+        RTC: RTC,
+        NVM: Nvm,
+        EVENT_LOG: EventLog,
+        RESET_CAUSE: ResetCause,
+        WATCHDOG: Watchdog,
+        SUPERVISOR: Supervisor,
+        CRASH: Option<CrashRecord>,
+        SETTINGS: Settings,
+        PROFILES: Profiles,
+        COOLDOWN: Cooldown,
+        ALERTS: Alerts,
+        CALIBRATION: Calibration,
+        HISTORY: History,
+        RADIO_STATS: RadioStats,
+        #[cfg(not(feature = "lorawan"))]
+        DUTY_CYCLE: DutyCycle,
+        /// Uplinks that didn't fit in the duty cycle, LoRaWAN holds them
+        /// back itself
+        #[cfg(not(feature = "lorawan"))]
+        HELD_UPLINKS: HeldUplinks,
+        CONSOLE_INPUT: InputBuffer,
+        KEYS: Option<Keys>,
+        IDENTITY: Identity,
+        REMOTE: Remote,
+        OTA: Ota,
+        TEMP_SENSOR: TempSensor,
+        SERIAL_TX: serial::Tx<pac::USART1>,
+        SERIAL_RX: serial::Rx<pac::USART1>,
     }
 
     #[init(resources = [BUFFER])]
     fn init(cx: init::Context) -> init::LateResources {
+        // Has to be read before the RCC is configured
+        let reset_cause = ResetCause::read(&cx.device.RCC);
+
+        logger::init();
+        info!(Module::Main, msg::BOOT, reset_cause as u8);
+
         // Configure the clock.
         let mut rcc = cx.device.RCC.freeze(Config::hsi16());
         let mut syscfg = syscfg::SYSCFG::new(cx.device.SYSCFG, &mut rcc);
+        let mut pwr = PWR::new(cx.device.PWR, &mut rcc);
+
+        // Record the boot in the persistent log
+        let mut nvm = Nvm::new(cx.device.FLASH);
+        let mut event_log = EventLog::new(&nvm);
+        event_log.push(&mut nvm, Event {
+            kind: EventKind::Boot,
+            arg: reset_cause as u8,
+            data: 0,
+            time: 0,
+        });
+
+        let settings = Settings::load(&nvm);
+        let profiles = Profiles::load(&nvm);
+        let calibration = Calibration::load(&nvm);
+        let history = History::new(&nvm);
+        let keys = Keys::load(&nvm);
+        let identity = Identity::load(&nvm);
+        if keys.is_some() && identity::readout_protection() == 0 {
+            warn!(Module::Main, msg::READOUT_UNPROTECTED);
+        }
+        let remote = Remote::load(&nvm, keys.as_ref());
+
+        // Report what the bootloader did with an update
+        let ota = Ota::load(&nvm);
+        let update = ota.state.status;
+        if update == UpdateStatus::Testing
+            || update == UpdateStatus::RolledBack
+            || update == UpdateStatus::Rejected
+        {
+            info!(Module::Main, msg::UPDATE_STATUS, ota.state.manifest.version, update as u8);
+            event_log.push(&mut nvm, Event {
+                kind: EventKind::Update,
+                arg: update as u8,
+                data: ota.state.manifest.version as u16,
+                time: 0,
+            });
+        }
+
+        // Keep the record of a panic before the reset
+        let crash = crash::take();
+        if let Some(record) = &crash {
+            error!(Module::Main, msg::CRASH, record.line);
+            nvm.write(CRASH_RECORD, record.as_bytes()).ok();
+            event_log.push(&mut nvm, Event {
+                kind: EventKind::Crash,
+                arg: 0,
+                data: record.line as u16,
+                time: 0,
+            });
+        }
+
+        // The RTC wakes the MCU periodically so the supervisor can feed the watchdog
+        let mut rtc = RTC::new(cx.device.RTC, &mut rcc, &mut pwr, Instant::new());
+        rtc.enable_interrupts(rtc::Interrupts {
+            wakeup_timer: true,
+            ..rtc::Interrupts::default()
+        });
+        rtc.wakeup_timer().start(CHECK_PERIOD_S);
 
         // Configure ADC
         let mut adc = adc::Adc::new(cx.device.ADC, &mut rcc);
 
-        // Acquire the GPIOB peripheral. This also enables the clock for GPIOB in
-        // the RCC register.
-        let gpioa = cx.device.GPIOA.split(&mut rcc);
-        let gpiob = cx.device.GPIOB.split(&mut rcc);
-        let gpioc = cx.device.GPIOC.split(&mut rcc);
+        // Pins of the board selected with the board features
+        let board = Board::take(cx.device.GPIOA, cx.device.GPIOB, cx.device.GPIOC, &mut rcc);
+
+        // Serial port on USART1, used for the console and to report crashes
+        let mut serial = cx.device
+            .USART1
+            .usart(board.serial_tx, board.serial_rx, serial::Config::default().baudrate(115_200.bps()), &mut rcc)
+            .unwrap();
+        serial.listen(serial::Event::Rxne);
+        console::enable_wakeup();
+        let (mut serial_tx, serial_rx) = serial.split();
+
+        if let Some(record) = &crash {
+            record.report(&mut serial_tx).ok();
+        }
 
-        // Configure inputs
-        let button = gpiob.pb2.into_pull_up_input();
-        let radio_int = gpiob.pb4.into_pull_up_input();
+        let button = board.button;
+        let radio_int = board.radio_dio0;
 
-        // Configure timers
+        // Configure timers, only the ones needed right now are listened to
         let mut tim2 = timer::Timer::tim2(cx.device.TIM2, 1000.ms(), &mut rcc);
-        let mut tim3 = timer::Timer::tim3(cx.device.TIM3, 1000.hz(), &mut rcc);
+        let tim3 = timer::Timer::tim3(cx.device.TIM3, 1000.hz(), &mut rcc);
+        // The one second tick while awake, until a shutdown
         let mut tim21 = timer::Timer::tim21(cx.device.TIM21, 1000.ms(), &mut rcc);
+        tim21.listen();
         let mut tim22 = timer::Timer::tim22(cx.device.TIM22, 1000.ms(), &mut rcc);
+        // Only listened to while a button is active
+        // Ticks every 100 ms while LoRaWAN waits for a receive window, unused by LongFi
+        let tim7 = timer::Timer::tim7(cx.device.TIM7, 100.ms(), &mut rcc);
+        let mut tim6 = timer::Timer::tim6(cx.device.TIM6, SCAN_PERIOD_MS.ms(), &mut rcc);
 
         // External interrupt
         let exti = cx.device.EXTI;
@@ -107,43 +537,39 @@ const APP: () = {
             TriggerEdge::Rising,
         );
 
-        exti.listen(
-            &mut syscfg,
-            button.port(),
-            button.pin_number(),
-            TriggerEdge::Falling,
-        );
+        if board::BUTTON_WAKEUP {
+            exti.listen(
+                &mut syscfg,
+                button.port(),
+                button.pin_number(),
+                TriggerEdge::Falling,
+            );
+        } else {
+            tim6.listen();
+        }
 
-        tim2.listen();
-        tim3.listen();
-        tim21.listen();
-        tim22.listen();
+        // RTC wakeup is EXTI line 20, needed to leave STOP mode
+        exti.imr.modify(|r, w| unsafe { w.bits(r.bits() | 1 << 20) });
+        exti.rtsr.modify(|r, w| unsafe { w.bits(r.bits() | 1 << 20) });
 
         // Initialize radio.
-        let radio_sck = gpiob.pb3;
-        let radio_miso = gpioa.pa6;
-        let radio_mosi = gpioa.pa7;
-        let radio_nss = gpioa.pa15.into_push_pull_output();
-        longfi_bindings::set_spi_nss(radio_nss);
+        longfi_bindings::set_spi_nss(board.radio_nss);
 
-        let mut spi1 = cx.device
+        let spi1 = cx.device
             .SPI1
-            .spi((radio_sck, radio_miso, radio_mosi), spi::MODE_0, 1_000_000.hz(), &mut rcc);
-
-        let radio_reset = gpioc.pc0.into_push_pull_output();
-        longfi_bindings::set_radio_reset(radio_reset);
-    
-        let ant_sw = AntennaSwitches::new(
-            gpioa.pa1.into_push_pull_output(),
-            gpioc.pc2.into_push_pull_output(),
-            gpioc.pc1.into_push_pull_output(),
-        );
+            .spi((board.radio_sck, board.radio_miso, board.radio_mosi), spi::MODE_0, 1_000_000.hz(), &mut rcc);
+        longfi_bindings::set_spi(spi1);
+
+        longfi_bindings::set_radio_reset(board.radio_reset);
+
+        let (ant_rx, ant_tx_rfo, ant_tx_boost) = board.antenna;
+        let ant_sw = AntennaSwitches::new(ant_rx, ant_tx_rfo, ant_tx_boost);
 
         longfi_bindings::set_antenna_switch(ant_sw);
 
-        let en_tcxo = gpiob.pb5.into_push_pull_output();
-        longfi_bindings::set_tcxo_pins(en_tcxo);
+        longfi_bindings::set_tcxo_pins(board.tcxo);
 
+        #[cfg(not(feature = "lorawan"))]
         static mut BINDINGS: longfi_device::BoardBindings = longfi_device::BoardBindings {
             reset: Some(longfi_bindings::radio_reset),
             spi_in_out: Some(longfi_bindings::spi_in_out),
@@ -154,23 +580,35 @@ const APP: () = {
             set_board_tcxo: Some(longfi_bindings::set_tcxo),
         };
 
-        let rf_config = RfConfig {
-            oui: 0xBEEF_FEED,
-            device_id: 0xABCD,
-        };
+        #[cfg(not(feature = "lorawan"))]
+        let radio = {
+            let rf_config = RfConfig {
+                oui: identity.oui,
+                device_id: identity.device_id,
+            };
 
-        let mut longfi_radio = unsafe { LongFi::new(&mut BINDINGS, rf_config).unwrap() };
+            let mut longfi_radio = unsafe { LongFi::new(&mut BINDINGS, rf_config).unwrap() };
 
-        longfi_radio.set_buffer(cx.resources.BUFFER);
+            longfi_radio.set_buffer(cx.resources.BUFFER);
 
-        longfi_radio.receive();
+            longfi_radio.receive();
+            longfi_radio
+        };
+
+        // Joins right away, the first measurement is sent once joined
+        #[cfg(feature = "lorawan")]
+        let radio = {
+            let mut lorawan = lorawan::LoRaWan::new(keys);
+            lorawan.start(0);
+            lorawan
+        };
 
         // Initialize OLED
-        let mut cs = gpiob.pb12.into_push_pull_output();
+        let mut cs = board.oled_cs;
         cs.set_low().unwrap(); // not sure if needed, did not try without it
 
-        let sck = gpiob.pb13;
-        let mosi = gpiob.pb15;
+        let sck = board.oled_sck;
+        let mosi = board.oled_mosi;
         let mut delay = Delay::new(cx.core.SYST, rcc.clocks);
 
         // Initialise the SPI peripheral.
@@ -180,132 +618,806 @@ const APP: () = {
                 .spi((sck, NoMiso, mosi), spi::MODE_0, 1_000_000.hz(), &mut rcc);
 
         // Initialize modules
-        let mut buzzer = Buzzer::new(gpioa.pa3);
-        let mut breathalyzer = Breathalyzer::new(gpioa.pa5, gpioa.pa2, adc);
+        let mut buzzer = Buzzer::new(board.buzzer);
+        let mut breathalyzer = Breathalyzer::new(board.heater, board.sensor, adc);
+        breathalyzer.curr_val = calibration.clean_air;
         breathalyzer.on();
-        let mut oled = Oled::new(spi, gpiob.pb8, gpiob.pb9, delay);
+        // Polled and warmed up from the start, `warm_up` stops its timer once ready
+        tim2.listen();
+        tim22.listen();
+        let commands = cortex_m::singleton!(: PanelCommands = PanelCommands::new()).unwrap();
+        let mut oled = Oled::new(spi, board.oled_dc, board.oled_reset, delay, commands);
+        oled.power.dim_after = settings.dim_after_s;
+        oled.power.off_after = settings.off_after_s;
+
+        #[cfg(feature = "battery-divider")]
+        let mut battery = Battery::new(board.battery);
+        #[cfg(not(feature = "battery-divider"))]
+        let mut battery = Battery::new();
+        battery.update(&mut breathalyzer.adc);
+        oled.set_battery(battery.percent);
 
         let test: f32 = 0.5;
 
+        let mut power = Power::new(settings.budget_ua());
+        power.set_busy(Busy::WarmUp, true);
+        power.set_busy(Busy::Button, !board::BUTTON_WAKEUP);
+        #[cfg(feature = "lorawan")]
+        power.set_busy(Busy::Radio, radio.is_busy());
+
+        // Started last so the slow init above can't trigger it
+        let watchdog = Watchdog::start(cx.device.IWDG);
+
         // Return the initialised resources.
         init::LateResources {
             EXT: exti,
             BUTTON: button,
+            BUTTONS: Buttons::new(),
+            TIMER_BUTTON: tim6,
             TIMER_BREATH: tim2,
             TIMER_PWM: tim3,
             TIMER_SEC: tim21,
             TIMER_WARM_UP: tim22,
+            TIMER_RX: tim7,
             BREATHALYZER: breathalyzer,
             BUZZER: buzzer,
-            LONGFI: longfi_radio,
+            RADIO: radio,
             RADIO_EXTI: radio_int,
             OLED: oled,
+            BATTERY: battery,
+            POWER: power,
+            PWR: pwr,
+            SCB: cx.core.SCB,
+            RCC: rcc,
+            RTC: rtc,
+            NVM: nvm,
+            EVENT_LOG: event_log,
+            RESET_CAUSE: reset_cause,
+            WATCHDOG: watchdog,
+            SUPERVISOR: Supervisor::new(),
+            CRASH: crash,
+            SETTINGS: settings,
+            PROFILES: profiles,
+            COOLDOWN: Cooldown::new(),
+            ALERTS: Alerts::load(&nvm),
+            CALIBRATION: calibration,
+            HISTORY: history,
+            RADIO_STATS: RadioStats::new(),
+            #[cfg(not(feature = "lorawan"))]
+            DUTY_CYCLE: DutyCycle::new(),
+            #[cfg(not(feature = "lorawan"))]
+            HELD_UPLINKS: HeldUplinks::new(),
+            CONSOLE_INPUT: InputBuffer::new(),
+            KEYS: keys,
+            IDENTITY: identity,
+            REMOTE: remote,
+            OTA: ota,
+            TEMP_SENSOR: TempSensor::new(),
+            SERIAL_TX: serial_tx,
+            SERIAL_RX: serial_rx,
+        }
+    }
+
+    // Sleeps whenever there is nothing to do, in STOP mode if no timer is needed
+    #[idle(resources = [POWER, PWR, SCB, RCC, SUPERVISOR])]
+    fn idle(mut cx: idle::Context) -> ! {
+        loop {
+            cortex_m::interrupt::free(|_| {
+                let stop = cx.resources.POWER.lock(|power| !power.is_busy() || power.shutdown);
+
+                cx.resources.SUPERVISOR.lock(|supervisor| {
+                    supervisor.check_in(Task::Idle);
+                    if stop {
+                        supervisor.sleeping();
+                    }
+                });
+
+                power::sleep(cx.resources.PWR, cx.resources.SCB, cx.resources.RCC, stop);
+            });
+        }
+    }
+
+    // Periodic RTC wakeup, feeds the watchdog as long as every task makes progress
+    #[task(binds = RTC, priority = 3, spawn = [rtc_tick], resources = [RTC, WATCHDOG, SUPERVISOR, NVM, EVENT_LOG])]
+    fn rtc_wakeup(cx: rtc_wakeup::Context) {
+        cx.resources.RTC.wakeup_timer().wait().ok();
+        unsafe {
+            (*pac::EXTI::ptr()).pr.write(|w| w.bits(1 << 20));
+        }
+
+        let supervisor = cx.resources.SUPERVISOR;
+        if supervisor.check() {
+            cx.resources.WATCHDOG.feed();
+        } else {
+            // Log once, then let the watchdog reset us
+            if supervisor.missed != 0 && !supervisor.reported {
+                supervisor.reported = true;
+                error!(Module::Watchdog, msg::WATCHDOG_STARVED, supervisor.missed);
+                cx.resources.EVENT_LOG.push(cx.resources.NVM, Event {
+                    kind: EventKind::Watchdog,
+                    arg: supervisor.missed,
+                    data: 0,
+                    time: 0,
+                });
+            }
+        }
+
+        cx.spawn.rtc_tick().ok();
+    }
+
+    // Uptime and power accounting, runs every RTC wakeup. Also confirms
+    // updated firmware, resets into the bootloader to install one and
+    // retries an uplink held back by the duty cycle.
+    #[task(priority = 2, spawn = [send_radio_message, radio_event], resources = [UPTIME, POWER, OTA, NVM, EVENT_LOG, REBOOT_IN, HELD_UPLINKS, ALERTS, BREATHALYZER, TIMER_BREATH, MEASURING, WARM_UP, HEATER_IDLE, SETTINGS, OLED, DISPLAY_OFF_IN, RADIO])]
+    fn rtc_tick(mut cx: rtc_tick::Context) {
+        *cx.resources.UPTIME += CHECK_PERIOD_S;
+        logger::set_time(*cx.resources.UPTIME);
+        cx.resources.POWER.period_elapsed(CHECK_PERIOD_S);
+
+        // The heater has to warm up again after this. Counted here as the
+        // 1 s tick stops in STOP mode, while the heater keeps drawing.
+        if cx.resources.BREATHALYZER.state && !*cx.resources.MEASURING && !*cx.resources.WARM_UP {
+            let idle = cx.resources.HEATER_IDLE.saturating_add(CHECK_PERIOD_S as u16);
+            *cx.resources.HEATER_IDLE = idle;
+            let heater_off_after = cx.resources.SETTINGS.heater_off_after_s;
+            if idle >= heater_off_after {
+                *cx.resources.HEATER_IDLE = 0;
+                info!(Module::Power, msg::HEATER_OFF, heater_off_after);
+                cx.resources.BREATHALYZER.off();
+                cx.resources.TIMER_BREATH.unlisten();
+                *cx.resources.WARM_UP = true;
+            }
+        }
+
+        // Nothing wakes the radio any more after a shutdown
+        if !cx.resources.POWER.shutdown {
+            // Alerts go out again until the server acknowledges them, counted
+            // here as the 1 s tick stops in STOP mode
+            let alerts = cx.resources.ALERTS;
+            match cx.resources.NVM.lock(|nvm| alerts.tick(nvm, CHECK_PERIOD_S as u16)) {
+                Some(Tick::Retry(alert)) => {
+                    warn!(Module::Radio, msg::ALERT_RETRY, alert.id, alert.attempt);
+                    cx.spawn.send_radio_message(Uplink::Alert(alert)).ok();
+                }
+                Some(Tick::GaveUp(alert)) => {
+                    warn!(Module::Radio, msg::ALERT_GAVE_UP, alert.id, alert.attempt as u16 + 1);
+                }
+                None => {}
+            }
+
+            // Held back again if the duty cycle still doesn't allow it
+            #[cfg(not(feature = "lorawan"))]
+            {
+                // The most important first, one per wakeup
+                let held = cx.resources.HELD_UPLINKS;
+                if let Some(i) = (0..held.len()).max_by_key(|i| held[*i].rank()) {
+                    let uplink = held.swap_remove(i);
+                    if let Err(uplink) = cx.spawn.send_radio_message(uplink) {
+                        held.push(uplink).ok();
+                    }
+                }
+            }
+
+            // A join or uplink waiting for the duty cycle goes out like after
+            // the receive windows
+            #[cfg(feature = "lorawan")]
+            {
+                let now_ms = *cx.resources.UPTIME as u64 * 1000;
+                if cx.resources.RADIO.wait_over(now_ms) {
+                    cx.spawn.radio_event(lorawan::Irq::Timer).ok();
+                }
+            }
+        }
+
+        // Running this long with the watchdog fed means the update works
+        if *cx.resources.UPTIME >= CONFIRM_AFTER_S {
+            let ota = cx.resources.OTA;
+            let event_log = cx.resources.EVENT_LOG;
+            let uptime = *cx.resources.UPTIME;
+            cx.resources.NVM.lock(|nvm| {
+                if ota.confirm(nvm) {
+                    let version = ota.state.manifest.version;
+                    info!(Module::Main, msg::UPDATE_CONFIRMED, version);
+                    event_log.lock(|log| log.push(nvm, Event {
+                        kind: EventKind::Update,
+                        arg: ota.state.status as u8,
+                        data: version as u16,
+                        time: uptime,
+                    }));
+                }
+            });
+        }
+
+        // The empty battery message has been up long enough
+        if *cx.resources.DISPLAY_OFF_IN > 0 {
+            *cx.resources.DISPLAY_OFF_IN = cx.resources.DISPLAY_OFF_IN.saturating_sub(CHECK_PERIOD_S);
+            if *cx.resources.DISPLAY_OFF_IN == 0 {
+                cx.resources.OLED.off();
+            }
+        }
+
+        // Gives the reply to the update command a chance to go out first
+        if *cx.resources.REBOOT_IN > 0 {
+            *cx.resources.REBOOT_IN = cx.resources.REBOOT_IN.saturating_sub(CHECK_PERIOD_S);
+            if *cx.resources.REBOOT_IN == 0 && cx.resources.OTA.is_pending() {
+                cortex_m::peripheral::SCB::sys_reset();
+            }
         }
     }
 
-    // External interrupt for the button
-    #[task(binds = EXTI2_3, priority = 2, spawn = [button_event], resources = [EXT, BUTTON, WARM_UP])]
+    // External interrupt for the button, starts the debounce scan
+    #[task(binds = EXTI2_3, priority = 2, resources = [EXT, BUTTON, BUTTONS, TIMER_BUTTON, POWER])]
     fn exti2_3(cx: exti2_3::Context) {
-        //hprintln!("exti2_3").unwrap();
+        trace!(Module::Button, msg::BUTTON_IRQ);
         cx.resources.EXT.clear_irq(cx.resources.BUTTON.pin_number());
 
-        if !*cx.resources.WARM_UP {
-            cx.spawn.button_event().unwrap();
+        if !cx.resources.BUTTONS.active {
+            cx.resources.POWER.set_busy(Busy::Button, true);
+            cx.resources.BUTTONS.wake();
+            cx.resources.TIMER_BUTTON.listen();
+        }
+    }
+
+    // Samples the buttons until they are all released and idle
+    #[task(binds = TIM6_DAC, priority = 2, spawn = [button_event], resources = [BUTTON, BUTTONS, TIMER_BUTTON, POWER])]
+    fn button_scan(cx: button_scan::Context) {
+        cx.resources.TIMER_BUTTON.clear_irq();
+
+        let pressed = [cx.resources.BUTTON.is_low().unwrap()];
+
+        if cx.resources.BUTTONS.scan(&pressed) {
+            // Already pending means the queue gets drained anyway
+            cx.spawn.button_event().ok();
+        }
+
+        if !cx.resources.BUTTONS.active && board::BUTTON_WAKEUP {
+            cx.resources.TIMER_BUTTON.unlisten();
+            cx.resources.POWER.set_busy(Busy::Button, false);
         }
     }
 
     // External interrupt for the radio
-    #[task(binds = EXTI4_15, priority = 2, spawn = [radio_event], resources = [EXT, RADIO_EXTI, WARM_UP])]
+    #[task(binds = EXTI4_15, priority = 2, spawn = [radio_event], resources = [EXT, RADIO_EXTI])]
     fn exti4_15(cx: exti4_15::Context) {
-        //hprintln!("exti4_15").unwrap();
+        trace!(Module::Radio, msg::RADIO_IRQ);
         cx.resources.EXT.clear_irq(cx.resources.RADIO_EXTI.pin_number());
 
-        if !*cx.resources.WARM_UP {
-            cx.spawn.radio_event(RfEvent::DIO0).unwrap();
+        if cx.spawn.radio_event(RADIO_DIO0).is_err() {
+            warn!(Module::Radio, msg::RADIO_EVENT_DROPPED);
         }
     }
 
-    #[task(capacity = 4, priority = 2, spawn = [button_event], resources = [BUFFER, LONGFI])]
-    fn radio_event(cx: radio_event::Context, event: RfEvent) {
-        let mut longfi_radio = cx.resources.LONGFI;
-        let client_event = longfi_radio.handle_event(event);
+    #[task(capacity = 4, priority = 2, spawn = [remote_command, update_fragment], resources = [BUFFER, RADIO, POWER, RADIO_LISTEN, SUPERVISOR, SETTINGS, RADIO_STATS, TIMER_RX, UPTIME])]
+    fn radio_event(mut cx: radio_event::Context, event: RadioIrq) {
+        #[cfg(not(feature = "lorawan"))]
+        {
+            let mut longfi_radio = cx.resources.RADIO;
+            let client_event = longfi_radio.handle_event(event);
 
-        match client_event {
-            ClientEvent::ClientEvent_TxDone => {
-                longfi_radio.receive();
-            },
-            ClientEvent::ClientEvent_Rx => {
-                let rx_packet = longfi_radio.get_rx();
+            match client_event {
+                ClientEvent::ClientEvent_TxDone => {
+                    cx.resources.RADIO_STATS.tx_finished();
+                    debug!(Module::Radio, msg::TX_DONE);
+                    cx.resources.POWER.set_busy(Busy::Radio, false);
+                    cx.resources.SUPERVISOR.lock(|supervisor| {
+                        supervisor.check_in(Task::Radio);
+                        supervisor.expect_radio(false);
+                    });
+                    // Listen for downlinks for a while before the radio sleeps
+                    *cx.resources.RADIO_LISTEN = cx.resources.SETTINGS.rx_window_s;
+                    longfi_radio.receive();
+                },
+                ClientEvent::ClientEvent_Rx => {
+                    let rx_packet = longfi_radio.get_rx();
+                    debug!(Module::Radio, msg::RX_PACKET, rx_packet.len);
+                    cx.resources.RADIO_STATS.received(rx_packet.rssi, rx_packet.snr);
 
-                {
-                    let buf = unsafe {
-                        core::slice::from_raw_parts(rx_packet.buf, rx_packet.len as usize)
-                    };
+                    {
+                        let buf = unsafe {
+                            core::slice::from_raw_parts(rx_packet.buf, rx_packet.len as usize)
+                        };
+
+                        // Commands and update fragments are told apart from
+                        // messages by their marker
+                        let marker = buf.first().cloned();
+                        if marker == Some(COMMAND_MARKER) || marker == Some(FRAGMENT_MARKER) {
+                            let mut frame = remote::Frame::new();
+                            if frame.extend_from_slice(buf).is_err() {
+                                cx.resources.RADIO_STATS.rx_invalid += 1;
+                            } else if marker == Some(COMMAND_MARKER) {
+                                cx.spawn.remote_command(frame).ok();
+                            } else {
+                                cx.spawn.update_fragment(frame).ok();
+                            }
+                            longfi_radio.set_buffer(cx.resources.BUFFER);
+                            longfi_radio.receive();
+                            return;
+                        }
+
+                        let message = Message::deserialize(buf);
 
-                    let message = Message::deserialize(buf);
+                        if message.is_none() {
+                            cx.resources.RADIO_STATS.rx_invalid += 1;
+                        }
+
+                        if let Some(message) = message {
+                            // Anyone can send these, measurements have to be
+                            // requested with a signed command instead
+                            if message.id == 6 {
+                                warn!(Module::Radio, msg::UNSIGNED_TRIGGER);
+                            }
+                        }
+                    }
+
+                    longfi_radio.set_buffer(cx.resources.BUFFER);
+                    longfi_radio.receive();
+                }
+                ClientEvent::ClientEvent_None => {}
+            }
+        }
+
+        #[cfg(feature = "lorawan")]
+        {
+            let lorawan = cx.resources.RADIO;
+            let now_ms = *cx.resources.UPTIME as u64 * 1000;
+            let result = match event {
+                lorawan::Irq::Dio0 => lorawan.handle_dio0(now_ms),
+                lorawan::Irq::Timer => lorawan.handle_timer(now_ms),
+            };
+
+            match result {
+                lorawan::Event::TxDone => {
+                    cx.resources.RADIO_STATS.tx_finished();
+                    debug!(Module::Radio, msg::TX_DONE);
+                }
+                lorawan::Event::Joined => {
+                    info!(Module::Radio, msg::JOINED, lorawan.dev_addr().unwrap_or(0));
+                }
+                lorawan::Event::JoinFailed => {
+                    warn!(Module::Radio, msg::JOIN_FAILED);
+                    cx.resources.RADIO_STATS.retries += 1;
+                }
+                lorawan::Event::Downlink => {
+                    if let Some(packet) = lorawan.last_packet {
+                        cx.resources.RADIO_STATS.received(packet.rssi, packet.snr);
+                    }
+                    if let Some((port, data)) = lorawan.downlink() {
+                        let rssi = lorawan.last_packet.map(|packet| -packet.rssi).unwrap_or(0);
+                        debug!(Module::Radio, msg::DOWNLINK, port, data.len(), rssi);
 
-                    if let Some(message) = message {
-                        // Let's assume we only have permission to use ID 6:
-                        if message.id == 6 {
-                            cx.spawn.button_event().unwrap();
+                        if port == lorawan::REMOTE_TRIGGER_PORT {
+                            warn!(Module::Radio, msg::UNSIGNED_TRIGGER);
+                        }
+                        if port == protocol::command::COMMAND_PORT || port == protocol::update::FRAGMENT_PORT {
+                            let mut frame = remote::Frame::new();
+                            if frame.extend_from_slice(data).is_err() {
+                                cx.resources.RADIO_STATS.rx_invalid += 1;
+                            } else if port == protocol::command::COMMAND_PORT {
+                                cx.spawn.remote_command(frame).ok();
+                            } else {
+                                cx.spawn.update_fragment(frame).ok();
+                            }
                         }
                     }
                 }
+                lorawan::Event::Invalid => cx.resources.RADIO_STATS.rx_invalid += 1,
+                lorawan::Event::CrcError => cx.resources.RADIO_STATS.crc_errors += 1,
+                lorawan::Event::Idle | lorawan::Event::None => {}
+            }
+
+            // Uplinks held back for the join or the duty cycle go out from here
+            let stats = cx.resources.RADIO_STATS;
+            if lorawan.is_transmitting() && !stats.is_transmitting() {
+                stats.tx_started();
+            }
 
-                longfi_radio.set_buffer(cx.resources.BUFFER);
-                longfi_radio.receive();
+            let timer = cx.resources.TIMER_RX;
+            if lorawan.take_timer() {
+                timer.start(lorawan::TICK_MS.ms());
+                timer.clear_irq();
+                timer.listen();
+            } else if !lorawan.is_busy() {
+                timer.unlisten();
             }
-            ClientEvent::ClientEvent_None => {}
+
+            // Waits for the duty cycle are checked on the RTC and don't
+            // keep the MCU out of STOP
+            cx.resources.POWER.set_busy(Busy::Radio, lorawan.is_busy());
+            cx.resources.SUPERVISOR.lock(|supervisor| {
+                supervisor.check_in(Task::Radio);
+                supervisor.expect_radio(lorawan.is_transmitting());
+            });
         }
     }
 
-    #[task(priority = 2, resources = [LONGFI])]
-    fn send_radio_message(cx: send_radio_message::Context, bac: BAC) {
-        let bac_convert = match bac {
-            BAC::NONE => 0,
-            BAC::LOW => 1,
-            BAC::MEDIUM => 2,
-            BAC::HIGH => 3,
-            BAC::VERY_HIGH => 4,
-            BAC::DEATH => 5
-        };
+    // Counts down the LoRaWAN receive windows
+    #[task(binds = TIM7, priority = 2, spawn = [radio_event], resources = [TIMER_RX, RADIO])]
+    fn rx_timer(cx: rx_timer::Context) {
+        cx.resources.TIMER_RX.clear_irq();
+
+        #[cfg(feature = "lorawan")]
+        {
+            if cx.resources.RADIO.tick(lorawan::TICK_MS) {
+                cx.resources.TIMER_RX.unlisten();
+                cx.spawn.radio_event(lorawan::Irq::Timer).ok();
+            }
+        }
+    }
+
+    // Sends telemetry, see the schema in the protocol crate, a command reply
+    // or an alert
+    #[task(capacity = 3, priority = 2, resources = [RADIO, POWER, SUPERVISOR, RADIO_STATS, TELEMETRY_SEQ, RESET_CAUSE, RESET_REPORTED, CRASH, TIMER_RX, UPTIME, DUTY_CYCLE, HELD_UPLINKS])]
+    fn send_radio_message(mut cx: send_radio_message::Context, uplink: Uplink) {
+        // Checked against the longest telemetry so nothing below has to be
+        // undone, only the actual airtime is spent
+        #[cfg(not(feature = "lorawan"))]
+        {
+            let len = match &uplink {
+                Uplink::Telemetry(_) => protocol::telemetry::MAX_TELEMETRY,
+                Uplink::Reply(frame) => frame.len(),
+                Uplink::Alert(_) => MAX_ALERT,
+            };
+            let airtime = duty_cycle::airtime_ms(LONGFI_SPREADING_FACTOR, LONGFI_BANDWIDTH_HZ, len);
+            let now_ms = *cx.resources.UPTIME as u64 * 1000;
+            let wait_ms = cx.resources.DUTY_CYCLE.wait_ms(UPLINK_FREQUENCY, airtime, now_ms);
+            if wait_ms > 0 {
+                warn!(Module::Radio, msg::UPLINK_HELD, (wait_ms / 1000) as u32);
+                hold(cx.resources.HELD_UPLINKS, uplink);
+                return;
+            }
+        }
+
+        #[cfg(not(feature = "cayenne-lpp"))]
+        let mut buf = [0; MAX_TELEMETRY];
+        #[cfg(feature = "cayenne-lpp")]
+        let lpp;
+        #[cfg(feature = "lorawan")]
+        let mut battery_percent = None;
+        let mut alert_buf = [0; MAX_ALERT];
 
-        // Wrap the message in a way such that ThingsBoard can handle it
-        let message = Message {
-            id: 6,
-            data: bac_convert,
-            channel: Channel::One
+        let payload: Result<&[u8], ()> = match &uplink {
+            Uplink::Telemetry(telemetry) => {
+                let mut telemetry = *telemetry;
+
+                // The cause of the last reset goes with the first uplink after boot
+                if !*cx.resources.RESET_REPORTED {
+                    *cx.resources.RESET_REPORTED = true;
+
+                    let cause = *cx.resources.RESET_CAUSE;
+                    if cause == ResetCause::Watchdog {
+                        telemetry.faults |= fault::WATCHDOG_RESET;
+                    }
+                    telemetry.reset = Some(cause as u8);
+                    if let Some(record) = cx.resources.CRASH.take() {
+                        telemetry.faults |= fault::CRASHED;
+                        telemetry.reset = Some(reset::CRASH);
+                        telemetry.crash = Some(record.site());
+                    }
+                }
+
+                telemetry.seq = *cx.resources.TELEMETRY_SEQ;
+                *cx.resources.TELEMETRY_SEQ = telemetry.seq.wrapping_add(1);
+
+                if telemetry.seq % LINK_STATS_EVERY == 0 {
+                    telemetry.link = Some(cx.resources.RADIO_STATS.link(*cx.resources.UPTIME));
+                }
+
+                #[cfg(feature = "lorawan")]
+                {
+                    battery_percent = Some(telemetry.battery_percent);
+                }
+
+                #[cfg(not(feature = "cayenne-lpp"))]
+                let payload = telemetry.encode(&mut buf).map(|payload| &*payload).map_err(|_| ());
+                #[cfg(feature = "cayenne-lpp")]
+                let payload = match protocol::cayenne::encode(&telemetry) {
+                    Ok(encoded) => {
+                        lpp = encoded;
+                        Ok(&lpp[..])
+                    }
+                    Err(()) => Err(()),
+                };
+                payload
+            }
+            Uplink::Reply(frame) => Ok(&frame[..]),
+            Uplink::Alert(alert) => alert.encode(&mut alert_buf).map_err(|_| ()),
+        };
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(_) => {
+                warn!(Module::Radio, msg::UPLINK_ENCODE_FAILED);
+                return;
+            }
         };
-    
-        let binary = Message::serialize(&message).unwrap();
-        cx.resources.LONGFI.send(&binary);
+
+        debug!(Module::Radio, msg::TX_START, payload.len());
+
+        #[cfg(not(feature = "lorawan"))]
+        {
+            let airtime = duty_cycle::airtime_ms(LONGFI_SPREADING_FACTOR, LONGFI_BANDWIDTH_HZ, payload.len());
+            let now_ms = *cx.resources.UPTIME as u64 * 1000;
+            cx.resources.DUTY_CYCLE.spend(UPLINK_FREQUENCY, airtime, now_ms);
+
+            cx.resources.RADIO_STATS.tx_started();
+            cx.resources.POWER.set_busy(Busy::Radio, true);
+            cx.resources.SUPERVISOR.lock(|supervisor| supervisor.expect_radio(true));
+            cx.resources.RADIO.send(payload);
+        }
+
+        // Waits for the join, the receive windows or the duty cycle if needed
+        #[cfg(feature = "lorawan")]
+        {
+            let lorawan = cx.resources.RADIO;
+            if let Some(percent) = battery_percent {
+                lorawan.battery = (percent as u16 * 254 / 100).max(1) as u8;
+            }
+
+            let port = match &uplink {
+                Uplink::Telemetry(_) => lorawan::TELEMETRY_PORT,
+                Uplink::Reply(_) => protocol::command::COMMAND_PORT,
+                Uplink::Alert(_) => ALERT_PORT,
+            };
+            let now_ms = *cx.resources.UPTIME as u64 * 1000;
+            if let Err(error) = lorawan.send(port, payload, now_ms) {
+                warn!(Module::Radio, msg::UPLINK_DROPPED, error);
+                return;
+            }
+
+            if lorawan.is_transmitting() && !cx.resources.RADIO_STATS.is_transmitting() {
+                cx.resources.RADIO_STATS.tx_started();
+            }
+
+            let timer = cx.resources.TIMER_RX;
+            if lorawan.take_timer() {
+                timer.start(lorawan::TICK_MS.ms());
+                timer.clear_irq();
+                timer.listen();
+            }
+            cx.resources.POWER.set_busy(Busy::Radio, lorawan.is_busy());
+            cx.resources.SUPERVISOR.lock(|supervisor| {
+                supervisor.expect_radio(lorawan.is_transmitting())
+            });
+        }
+    }
+
+    // Handles the queued button gestures
+    #[task(priority = 2, spawn = [measure], resources = [BUTTONS, OLED, WARM_UP, BREATHALYZER, TIMER_BREATH, TIMER_WARM_UP, POWER, HEATER_IDLE, DIAG_PAGE, BATTERY, RESET_CAUSE, EVENT_LOG, RADIO_STATS, UPTIME, RADIO, DUTY_CYCLE, PROFILES, MEASURING, NVM, HISTORY, CALIBRATION, RTC, SETTINGS, COOLDOWN])]
+    fn button_event(mut cx: button_event::Context) {
+        while let Some(event) = cx.resources.BUTTONS.next_event() {
+            let woken = cx.resources.OLED.wake();
+            cx.resources.POWER.set_busy(Busy::Display, true);
+            *cx.resources.HEATER_IDLE = 0;
+
+            // The heater was turned off while idle, warm it up again
+            if !cx.resources.BREATHALYZER.state {
+                cx.resources.BREATHALYZER.on();
+                cx.resources.TIMER_BREATH.listen();
+                cx.resources.TIMER_WARM_UP.listen();
+                cx.resources.POWER.set_busy(Busy::WarmUp, true);
+                cx.resources.OLED.on("Warming up");
+                continue;
+            }
+
+            // The press that wakes the display is not passed on
+            if woken {
+                continue;
+            }
+
+            // Another screen replaces the countdown, a short press brings it back
+            cx.resources.COOLDOWN.shown = false;
+
+            match event.gesture {
+                // Long presses step through the diagnostics pages
+                Gesture::Long => {
+                    let page = match *cx.resources.DIAG_PAGE {
+                        None => Some(Page::System),
+                        Some(page) => page.next(),
+                    };
+                    *cx.resources.DIAG_PAGE = page;
+
+                    match page {
+                        Some(Page::System) => {
+                            let log_len = cx.resources.EVENT_LOG.lock(|log| log.len());
+                            let lines = diagnostics::system_page(
+                                *cx.resources.RESET_CAUSE,
+                                cx.resources.BATTERY,
+                                cx.resources.POWER,
+                                log_len,
+                                cx.resources.BUTTONS.events.dropped,
+                            );
+                            cx.resources.OLED.show_lines(lines);
+                        }
+                        Some(Page::Radio) => {
+                            let now_ms = *cx.resources.UPTIME as u64 * 1000;
+                            #[cfg(not(feature = "lorawan"))]
+                            let budget_ms = cx.resources.DUTY_CYCLE.remaining_ms(UPLINK_FREQUENCY, now_ms);
+                            #[cfg(feature = "lorawan")]
+                            let budget_ms = cx.resources.RADIO.region.duty.remaining_ms(UPLINK_FREQUENCY, now_ms);
+
+                            let lines = diagnostics::radio_page(cx.resources.RADIO_STATS, *cx.resources.UPTIME, budget_ms);
+                            cx.resources.OLED.show_lines(lines);
+                        }
+                        None => cx.resources.OLED.on("Ready"),
+                    }
+                }
+                Gesture::Short if cx.resources.DIAG_PAGE.is_some() => {
+                    *cx.resources.DIAG_PAGE = None;
+                    cx.resources.OLED.on("Ready");
+                }
+                Gesture::Short if !*cx.resources.WARM_UP => {
+                    cx.spawn.measure().ok();
+                }
+                // Double presses pick who is about to blow
+                Gesture::Double if cx.resources.DIAG_PAGE.is_none() && !*cx.resources.MEASURING => {
+                    let profiles = &mut *cx.resources.PROFILES;
+                    let id = profiles.select_next();
+                    cx.resources.NVM.lock(|nvm| profiles.save(nvm)).ok();
+
+                    // What is left of the estimate from their last reading
+                    let limit = profiles.limit(cx.resources.SETTINGS.legal_limit);
+                    let rate = profiles.rate();
+                    let now = cx.resources.RTC.lock(|rtc| DateTime::from_instant(&rtc.now()).to_seconds());
+                    let history = &*cx.resources.HISTORY;
+                    let calibration = &*cx.resources.CALIBRATION;
+                    let minutes = cx.resources.NVM.lock(|nvm| {
+                        sober::from_history(history, nvm, calibration, id, limit, rate, now)
+                    });
+
+                    match minutes {
+                        Some(minutes) if minutes > 0 => {
+                            let lines = sober::lines(&profile_message(profiles), None, minutes);
+                            cx.resources.OLED.show_lines(lines);
+                        }
+                        _ => cx.resources.OLED.on(&profile_message(profiles)),
+                    }
+                }
+                _ => {}
+            }
+        }
     }
 
-    // Handles the button press
-    #[task(priority = 2, spawn = [send_radio_message], resources = [BUTTON, BUZZER, BREATHALYZER, OLED, MEASURING, TIMER_SEC])]
-    fn button_event(cx: button_event::Context) {
+    // Starts a measurement, or shows the result of a finished one
+    #[task(priority = 2, spawn = [shutdown], resources = [BUZZER, BREATHALYZER, OLED, MEASURING, TIMER_PWM, POWER, HEATER_IDLE, BATTERY, RTC, CALIBRATION, UPTIME, TEMP_SENSOR, SETTINGS, REQUESTER, PROFILES, COOLDOWN, BEEPS, RESULT])]
+    fn measure(mut cx: measure::Context) {
+        *cx.resources.HEATER_IDLE = 0;
+        // Taken even if the measurement doesn't start, so it isn't shown for
+        // the next press of the button
+        let requester = cx.resources.REQUESTER.take();
+
+        // The buzzer being on means a measurement, so the beeps of the last
+        // result have to stop first
+        if *cx.resources.BEEPS > 0 {
+            *cx.resources.BEEPS = 0;
+            cx.resources.BUZZER.disable();
+            cx.resources.TIMER_PWM.unlisten();
+            cx.resources.POWER.set_busy(Busy::Buzzer, false);
+        }
+
+        // Disabled remotely, a running measurement is still finished
+        if !*cx.resources.MEASURING && !cx.resources.BUZZER.enabled && !cx.resources.SETTINGS.enabled {
+            cx.resources.OLED.on("Disabled");
+            return;
+        }
+
+        // The sensor needs time to recover from the last reading
+        if !*cx.resources.MEASURING && !cx.resources.BUZZER.enabled && cx.resources.COOLDOWN.is_active() {
+            cx.resources.COOLDOWN.shown = true;
+            cx.resources.OLED.on(&cooldown_message(cx.resources.COOLDOWN));
+            return;
+        }
+
+        // Only checked before starting, a running measurement is finished
+        if !*cx.resources.MEASURING && !cx.resources.BUZZER.enabled {
+            let level = cx.resources.BATTERY.update(&mut cx.resources.BREATHALYZER.adc);
+            cx.resources.OLED.set_battery(cx.resources.BATTERY.percent);
+
+            match level {
+                Level::Ok => {}
+                Level::Low => {
+                    cx.resources.OLED.on("Low battery");
+                    return;
+                }
+                Level::Critical => {
+                    cx.spawn.shutdown().ok();
+                    return;
+                }
+            }
+        }
+
         if !*cx.resources.MEASURING {
             if cx.resources.BUZZER.enabled {
                 cx.resources.BUZZER.disable();
-                let mut value: BAC = cx.resources.BREATHALYZER.read();
-
-                let val = match value {
-                    BAC::NONE => "NONE",
-                    BAC::LOW => "LOW",
-                    BAC::MEDIUM => "MEDIUM",
-                    BAC::HIGH => "HIGH",
-                    BAC::VERY_HIGH => "VERY HIGH",
-                    BAC::DEATH => "DEATH"
+                cx.resources.TIMER_PWM.unlisten();
+                cx.resources.POWER.set_busy(Busy::Buzzer, false);
+                cx.resources.POWER.set_busy(Busy::Measuring, false);
+                let raw = cx.resources.BREATHALYZER.read();
+                let baseline = cx.resources.BREATHALYZER.curr_val;
+                let severity = Severity::of(raw, baseline, &cx.resources.SETTINGS.band_starts);
+                info!(Module::Sensor, msg::MEASURE_RESULT, severity.category());
+
+                let calibration = cx.resources.CALIBRATION;
+                let battery = cx.resources.BATTERY;
+                let bac = calibration.bac(raw, baseline);
+                let profile = cx.resources.PROFILES.selected;
+                let limit = cx.resources.PROFILES.limit(cx.resources.SETTINGS.legal_limit);
+                let over_limit = bac.map_or(false, |bac| bac >= limit);
+
+                let band = *severity.band(&cx.resources.SETTINGS.bands);
+                let text = if over_limit {
+                    let mut text = heapless::String::<U16>::new();
+                    text.push_str("OVER LIMIT").ok();
+                    text
+                } else {
+                    band_text(&band)
+                };
+                match bac {
+                    // When driving is allowed again
+                    Some(bac) if over_limit => {
+                        let minutes = sober::minutes_to_limit(bac, limit, cx.resources.PROFILES.rate());
+                        cx.resources.OLED.show_lines(sober::lines(&text, Some(bac), minutes));
+                    }
+                    _ => cx.resources.OLED.on(&text),
+                }
+                *cx.resources.BEEPS = band.beeps * 2;
+
+                let time = cx.resources.RTC.lock(|rtc| DateTime::from_instant(&rtc.now()).to_seconds());
+                let record = Record {
+                    time: time,
+                    raw: raw,
+                    baseline: baseline,
+                    category: severity.category(),
+                    flags: if over_limit { FLAG_OVER_LIMIT } else { 0 },
+                    profile: profile,
                 };
-                cx.resources.OLED.on(val);
+                cx.resources.COOLDOWN.start(raw, baseline, cx.resources.SETTINGS.cooldown_s);
 
-                // Send radio message, place this wherever it is needed
-                cx.spawn.send_radio_message(value).unwrap();
+                let telemetry = Telemetry {
+                    version: TELEMETRY_VERSION,
+                    seq: 0,
+                    uptime: *cx.resources.UPTIME,
+                    bac: bac.unwrap_or(BAC_UNKNOWN),
+                    category: severity.category(),
+                    raw: raw,
+                    baseline: baseline,
+                    temperature: cx.resources.TEMP_SENSOR.read(&mut cx.resources.BREATHALYZER.adc, battery.vdd_mv),
+                    battery_mv: battery.mv,
+                    battery_percent: battery.percent,
+                    faults: faults(raw, baseline, calibration, battery),
+                    link: None,
+                    profile: profile,
+                    reset: None,
+                    crash: None,
+                };
+                // Stored and sent by housekeeping once the cooldown has
+                // judged the reading
+                *cx.resources.RESULT = Some(PendingResult {
+                    record: record,
+                    severity: severity,
+                    bac: bac,
+                    telemetry: telemetry,
+                });
             } else {
-                cx.resources.OLED.on("Reading");
+                match requester {
+                    Some(requester) => {
+                        let mut text = heapless::String::<U16>::new();
+                        write!(text, "Remote #{}", requester).ok();
+                        cx.resources.OLED.on(&text);
+                    }
+                    None => match cx.resources.PROFILES.get(cx.resources.PROFILES.selected) {
+                        Some(profile) => {
+                            let mut text = heapless::String::<U16>::new();
+                            write!(text, "Reading {}", profile.initials()).ok();
+                            cx.resources.OLED.on(&text);
+                        }
+                        None => cx.resources.OLED.on("Reading"),
+                    },
+                }
+                debug!(Module::Sensor, msg::MEASURE_START, cx.resources.BREATHALYZER.curr_val);
                 // constant beep
                 cx.resources.BUZZER.enable();
-                cx.resources.TIMER_SEC.listen();
+                cx.resources.TIMER_PWM.listen();
+                cx.resources.POWER.set_busy(Busy::Buzzer, true);
+                cx.resources.POWER.set_busy(Busy::Measuring, true);
                 *cx.resources.MEASURING = true;
             }
         }
@@ -329,7 +1441,7 @@ const APP: () = {
         cx.resources.BUZZER.toggle_pwm();
     }
 
-    #[task(binds = TIM21, spawn = [button_event], resources = [BUZZER, TIME_COUNTER, TIMER_SEC, MEASURING])]
+    #[task(binds = TIM21, spawn = [measure, housekeeping], resources = [BUZZER, TIME_COUNTER, TIMER_SEC, MEASURING, SETTINGS])]
     fn stop_measuring(mut cx: stop_measuring::Context) {
         cx.resources.TIMER_SEC.lock(|TIMER_SEC| TIMER_SEC.clear_irq());
         let mut measuring: bool = cx.resources.MEASURING.lock(|MEASURING| return *MEASURING);
@@ -337,7 +1449,8 @@ const APP: () = {
         let mut complete: bool = false;
 
         if measuring {
-            if counter >= 3 {
+            let blow_s = cx.resources.SETTINGS.lock(|settings| settings.blow_s);
+            if counter >= blow_s {
                 cx.resources.MEASURING.lock(|MEASURING| {
                     *MEASURING = false;
                 });
@@ -350,12 +1463,874 @@ const APP: () = {
 
         if complete {
             *cx.resources.TIME_COUNTER = 0;
-            cx.spawn.button_event().unwrap();
+            // The queued one reads the result just the same
+            if cx.spawn.measure().is_err() {
+                warn!(Module::Sensor, msg::MEASURE_DROPPED);
+            }
+        }
+
+        cx.spawn.housekeeping().ok();
+    }
+
+    // Once a second while awake: display power, radio sleep and power accounting
+    #[task(priority = 2, spawn = [shutdown, status_report, send_radio_message], resources = [OLED, POWER, BREATHALYZER, RADIO_LISTEN, BATTERY, BATTERY_CHECK, SUPERVISOR, SETTINGS, REPORT_COUNTER, COOLDOWN, BUZZER, TIMER_PWM, BEEPS, ALERTS, RESULT, NVM, HISTORY])]
+    fn housekeeping(mut cx: housekeeping::Context) {
+        cx.resources.SUPERVISOR.lock(|supervisor| supervisor.check_in(Task::Housekeeping));
+
+        let power = cx.resources.POWER;
+        power.awake_second();
+
+        cx.resources.OLED.tick();
+        power.set_busy(Busy::Display, cx.resources.OLED.power.state != DisplayState::Off);
+
+        // The beeps of the severity band of the last result
+        if *cx.resources.BEEPS > 0 {
+            *cx.resources.BEEPS -= 1;
+            if *cx.resources.BEEPS % 2 == 1 {
+                cx.resources.BUZZER.enable();
+                cx.resources.TIMER_PWM.listen();
+            } else {
+                cx.resources.BUZZER.disable();
+                cx.resources.TIMER_PWM.unlisten();
+            }
+            power.set_busy(Busy::Buzzer, *cx.resources.BEEPS > 0);
+        }
+
+        // Measurements wait for the sensor to recover from the last one
+        let mut mouth_alcohol = false;
+        if cx.resources.COOLDOWN.is_active() {
+            let breathalyzer = &mut *cx.resources.BREATHALYZER;
+            let raw = if breathalyzer.state { Some(breathalyzer.read_curr()) } else { None };
+            let cooldown = &mut *cx.resources.COOLDOWN;
+
+            match cooldown.tick(raw, breathalyzer.curr_val) {
+                Some(cooldown::Event::MouthAlcohol(seconds)) => {
+                    warn!(Module::Sensor, msg::MOUTH_ALCOHOL, seconds);
+                    mouth_alcohol = true;
+                    cooldown.shown = false;
+                    cx.resources.OLED.show_lines(cooldown::warning());
+                }
+                Some(cooldown::Event::Ready(seconds)) => {
+                    debug!(Module::Sensor, msg::COOLDOWN_DONE, seconds);
+                    if cooldown.shown {
+                        cooldown.shown = false;
+                        cx.resources.OLED.on("Ready");
+                    }
+                }
+                None if cooldown.shown => cx.resources.OLED.on(&cooldown_message(cooldown)),
+                None => {}
+            }
+        }
+
+        // The last result once it can't be mouth alcohol any more, or is
+        if !cx.resources.COOLDOWN.judging() {
+            if let Some(mut result) = cx.resources.RESULT.take() {
+                if mouth_alcohol {
+                    result.record.flags |= FLAG_MOUTH_ALCOHOL;
+                    result.telemetry.faults |= fault::MOUTH_ALCOHOL;
+                }
+                let history = cx.resources.HISTORY;
+                cx.resources.NVM.lock(|nvm| history.push(nvm, result.record));
+
+                // Sent ahead of the telemetry, and again until acknowledged.
+                // Mouth alcohol reads far too high to alert anyone about.
+                let category = result.severity.category();
+                if result.severity.band(&cx.resources.SETTINGS.bands).alert && !mouth_alcohol {
+                    let bac = result.bac.unwrap_or(BAC_UNKNOWN);
+                    let record = result.record;
+                    let alerts = cx.resources.ALERTS;
+                    let raised = cx
+                        .resources
+                        .NVM
+                        .lock(|nvm| alerts.raise(nvm, result.severity, bac, record.profile, record.time));
+                    match raised {
+                        Some(alert) => {
+                            warn!(Module::Radio, msg::ALERT_RAISED, alert.id, alert.category);
+                            cx.spawn.send_radio_message(Uplink::Alert(alert)).ok();
+                        }
+                        None => info!(Module::Radio, msg::ALERT_DUPLICATE, category),
+                    }
+                }
+                cx.spawn.send_radio_message(Uplink::Telemetry(result.telemetry)).ok();
+            }
+        }
+
+        // LoRaWAN puts the radio to sleep after the receive windows itself
+        #[cfg(not(feature = "lorawan"))]
+        if *cx.resources.RADIO_LISTEN > 0 {
+            *cx.resources.RADIO_LISTEN -= 1;
+            if *cx.resources.RADIO_LISTEN == 0 {
+                longfi_bindings::radio_sleep();
+                debug!(Module::Radio, msg::RADIO_SLEEP);
+            }
+        }
+
+        *cx.resources.BATTERY_CHECK += 1;
+        if *cx.resources.BATTERY_CHECK >= 60 {
+            *cx.resources.BATTERY_CHECK = 0;
+
+            let level = cx.resources.BATTERY.update(&mut cx.resources.BREATHALYZER.adc);
+            debug!(Module::Battery, msg::BATTERY, cx.resources.BATTERY.mv, cx.resources.BATTERY.percent);
+            match level {
+                Level::Ok => {}
+                Level::Low => warn!(Module::Battery, msg::BATTERY_LOW, cx.resources.BATTERY.mv),
+                Level::Critical => {
+                    cx.spawn.shutdown().ok();
+                }
+            }
+            cx.resources.OLED.set_battery(cx.resources.BATTERY.percent);
+        }
+
+        // Status uplinks at the interval set remotely, off at 0
+        let report_s = cx.resources.SETTINGS.report_interval_min as u32 * 60;
+        *cx.resources.REPORT_COUNTER += 1;
+        if report_s == 0 {
+            *cx.resources.REPORT_COUNTER = 0;
+        } else if *cx.resources.REPORT_COUNTER >= report_s {
+            *cx.resources.REPORT_COUNTER = 0;
+            cx.spawn.status_report().ok();
+        }
+    }
+
+    // Sends telemetry without a measurement so the device is known to be alive
+    #[task(priority = 2, spawn = [send_radio_message], resources = [BREATHALYZER, CALIBRATION, BATTERY, TEMP_SENSOR, UPTIME])]
+    fn status_report(cx: status_report::Context) {
+        let breathalyzer = cx.resources.BREATHALYZER;
+        let battery = cx.resources.BATTERY;
+        let raw = breathalyzer.last;
+        let baseline = breathalyzer.curr_val;
+
+        let telemetry = Telemetry {
+            version: TELEMETRY_VERSION,
+            seq: 0,
+            uptime: *cx.resources.UPTIME,
+            bac: BAC_UNKNOWN,
+            category: 0,
+            raw: raw,
+            baseline: baseline,
+            temperature: cx.resources.TEMP_SENSOR.read(&mut breathalyzer.adc, battery.vdd_mv),
+            battery_mv: battery.mv,
+            battery_percent: battery.percent,
+            faults: faults(raw, baseline, cx.resources.CALIBRATION, battery),
+            link: None,
+            profile: GUEST,
+            reset: None,
+            crash: None,
+        };
+        cx.spawn.send_radio_message(Uplink::Telemetry(telemetry)).ok();
+    }
+
+    // Runs an authenticated command from a downlink and sends the reply,
+    // see the command module of the protocol crate
+    #[task(capacity = 2, priority = 1, spawn = [send_radio_message, measure], resources = [REMOTE, NVM, SETTINGS, CALIBRATION, RTC, HISTORY, BREATHALYZER, BATTERY, TEMP_SENSOR, WARM_UP, MEASURING, KEYS, OTA, REBOOT_IN, UPTIME, REQUESTER, EVENT_LOG, COOLDOWN, ALERTS, OLED])]
+    fn remote_command(mut cx: remote_command::Context, frame: remote::Frame) {
+        let remote = cx.resources.REMOTE;
+        let mut nvm = cx.resources.NVM;
+        let mut settings = cx.resources.SETTINGS;
+        let mut calibration = cx.resources.CALIBRATION;
+        let mut history = cx.resources.HISTORY;
+        let mut breathalyzer = cx.resources.BREATHALYZER;
+        let mut battery = cx.resources.BATTERY;
+        let mut ota = cx.resources.OTA;
+
+        let accepted = nvm.lock(|nvm| remote.accept(nvm, &frame));
+        let (counter, reply) = match accepted {
+            // Not from whoever holds the key, answering would only tell
+            // anyone listening that the device is there
+            Err((counter, Rejection::Unauthorized)) => {
+                warn!(Module::Radio, msg::COMMAND_REJECTED, Rejection::Unauthorized, counter);
+                return;
+            }
+            Err((counter, rejection)) => {
+                warn!(Module::Radio, msg::COMMAND_REJECTED, rejection, counter);
+                (counter, Reply::Rejected(rejection))
+            }
+            Ok((counter, command)) => {
+                info!(Module::Radio, msg::COMMAND, command.kind(), counter);
+
+                let reply = match command {
+                    RemoteCommand::SetLegalLimit { bac } => {
+                        save_setting(&mut settings, &mut nvm, "limit", bac)
+                    }
+                    RemoteCommand::SetReportInterval { minutes } => {
+                        save_setting(&mut settings, &mut nvm, "report", minutes)
+                    }
+                    RemoteCommand::SetEnabled { enabled } => {
+                        save_setting(&mut settings, &mut nvm, "enabled", enabled as u16)
+                    }
+                    RemoteCommand::SetCalibration { .. }
+                        if cx.resources.MEASURING.lock(|measuring| *measuring) =>
+                    {
+                        Reply::Rejected(Rejection::Busy)
+                    }
+                    RemoteCommand::SetCalibration { points } => calibration.lock(|calibration| {
+                        if calibration.set_points(&points).is_err() {
+                            Reply::Rejected(Rejection::OutOfRange)
+                        } else {
+                            match nvm.lock(|nvm| calibration.save(nvm)) {
+                                Ok(()) => Reply::Done,
+                                Err(()) => Reply::Rejected(Rejection::Storage),
+                            }
+                        }
+                    }),
+                    RemoteCommand::SetTime { seconds } => {
+                        let time = DateTime::from_seconds(seconds);
+                        cx.resources.RTC.lock(|rtc| rtc.set(time.to_instant()));
+                        Reply::Done
+                    }
+                    RemoteCommand::GetHistory { start, count } => {
+                        let total = history.lock(|history| history.len());
+                        let mut records = heapless::Vec::new();
+
+                        for i in start..start.saturating_add(count.min(REPLY_RECORDS) as u32) {
+                            match nvm.lock(|nvm| history.lock(|history| history.get(nvm, i))) {
+                                Some(record) => records.push(history_record(record)).ok(),
+                                None => break,
+                            };
+                        }
+                        Reply::History {
+                            total: total,
+                            records: records,
+                        }
+                    }
+                    RemoteCommand::SelfTest => {
+                        let warm_up = cx.resources.WARM_UP.lock(|warm_up| *warm_up);
+                        let (vdd_mv, battery_mv) = battery.lock(|battery| (battery.vdd_mv, battery.mv));
+                        let mut temp_sensor = cx.resources.TEMP_SENSOR;
+
+                        let (raw, baseline, heater, temperature) = breathalyzer.lock(|breathalyzer| {
+                            (
+                                breathalyzer.read_curr(),
+                                breathalyzer.curr_val,
+                                breathalyzer.state,
+                                temp_sensor.lock(|sensor| sensor.read(&mut breathalyzer.adc, vdd_mv)),
+                            )
+                        });
+                        let faults = calibration.lock(|calibration| {
+                            battery.lock(|battery| faults(raw, baseline, calibration, battery))
+                        });
+
+                        Reply::SelfTest(SelfTestResult {
+                            faults: faults,
+                            raw: raw,
+                            baseline: baseline,
+                            warm: heater && !warm_up,
+                            temperature: temperature,
+                            battery_mv: battery_mv,
+                            keys_provisioned: cx.resources.KEYS.is_some(),
+                        })
+                    }
+                    RemoteCommand::UpdateStart { version, size } => {
+                        info!(Module::Radio, msg::UPDATE_STATUS, version, UpdateStatus::Downloading as u8);
+                        reply(ota.lock(|ota| nvm.lock(|nvm| ota.start(nvm, version, size))))
+                    }
+                    RemoteCommand::UpdateSignature { half, bytes } => {
+                        reply(ota.lock(|ota| nvm.lock(|nvm| ota.set_signature(nvm, half, &bytes))))
+                    }
+                    RemoteCommand::UpdateStatus => {
+                        Reply::Update(ota.lock(|ota| nvm.lock(|nvm| ota.status(nvm))))
+                    }
+                    RemoteCommand::UpdateApply => {
+                        let result = ota.lock(|ota| nvm.lock(|nvm| ota.apply(nvm)));
+                        if result.is_ok() {
+                            cx.resources.REBOOT_IN.lock(|reboot_in| *reboot_in = REBOOT_DELAY_S);
+                        }
+                        reply(result)
+                    }
+                    RemoteCommand::Measure { requester } => {
+                        let now = cx.resources.UPTIME.lock(|uptime| *uptime);
+                        let time = cx.resources.RTC.lock(|rtc| DateTime::from_instant(&rtc.now()).to_seconds());
+                        let (enabled, interval_s) =
+                            settings.lock(|s| (s.enabled, s.trigger_interval_min as u32 * 60));
+                        let busy = cx.resources.WARM_UP.lock(|warm_up| *warm_up)
+                            || cx.resources.MEASURING.lock(|measuring| *measuring);
+                        let cooling_down = cx.resources.COOLDOWN.lock(|cooldown| cooldown.is_active());
+                        let last = remote.last_trigger();
+
+                        // The checks `measure` makes before starting, it would
+                        // refuse just the same after the reply
+                        let mut rejection = if !enabled || interval_s == 0 {
+                            Some(Rejection::Disabled)
+                        } else if cooling_down
+                            || last.map_or(false, |last| time.wrapping_sub(last) < interval_s)
+                        {
+                            Some(Rejection::TooSoon)
+                        } else if busy {
+                            Some(Rejection::Busy)
+                        } else {
+                            let level = battery.lock(|battery| {
+                                breathalyzer.lock(|breathalyzer| battery.update(&mut breathalyzer.adc))
+                            });
+                            match level {
+                                Level::Ok => None,
+                                Level::Low | Level::Critical => Some(Rejection::Busy),
+                            }
+                        };
+
+                        if rejection.is_none() {
+                            // The measurement takes the requester as soon as
+                            // it starts, so it is set before the spawn
+                            cx.resources.REQUESTER.lock(|r| *r = Some(requester));
+                            if cx.spawn.measure().is_err() {
+                                cx.resources.REQUESTER.lock(|r| *r = None);
+                                rejection = Some(Rejection::Busy);
+                            }
+                        }
+
+                        if let Some(rejection) = rejection {
+                            warn!(Module::Radio, msg::REMOTE_TRIGGER_REJECTED, requester, rejection);
+                            Reply::Rejected(rejection)
+                        } else {
+                            info!(Module::Radio, msg::REMOTE_TRIGGER, requester, counter);
+                            nvm.lock(|nvm| remote.set_last_trigger(nvm, time)).ok();
+
+                            let event = Event {
+                                kind: EventKind::RemoteTrigger,
+                                arg: 0,
+                                data: requester,
+                                time: now,
+                            };
+                            let mut event_log = cx.resources.EVENT_LOG;
+                            nvm.lock(|nvm| event_log.lock(|log| log.push(nvm, event)));
+                            Reply::Done
+                        }
+                    }
+                    RemoteCommand::AcknowledgeAlert { id } => {
+                        let mut alerts = cx.resources.ALERTS;
+                        match nvm.lock(|nvm| alerts.lock(|alerts| alerts.acknowledge(nvm, id))) {
+                            Some(alert) => {
+                                info!(Module::Radio, msg::ALERT_ACKNOWLEDGED, id, alert.attempt as u16 + 1);
+                                cx.resources.OLED.lock(|oled| oled.show_lines(alerts::confirmation()));
+                                Reply::Done
+                            }
+                            // Already acknowledged
+                            None => Reply::Rejected(Rejection::OutOfRange),
+                        }
+                    }
+                };
+                (counter, reply)
+            }
+        };
+
+        // Without keys there is nothing to sign the reply with
+        if let Some(frame) = remote.reply(counter, &reply) {
+            cx.spawn.send_radio_message(Uplink::Reply(frame)).ok();
+        }
+    }
+
+    // Writes a fragment of a firmware update, see ota.rs
+    #[task(capacity = 4, priority = 1, resources = [OTA, NVM, REMOTE])]
+    fn update_fragment(cx: update_fragment::Context, frame: remote::Frame) {
+        let mut ota = cx.resources.OTA;
+        let mut nvm = cx.resources.NVM;
+
+        // Without keys no update can have been started either
+        let key = match cx.resources.REMOTE.key() {
+            Some(key) => *key,
+            None => return,
+        };
+        match ota.lock(|ota| nvm.lock(|nvm| ota.fragment(nvm, &key, &frame))) {
+            Ok(true) => {
+                let total = ota.lock(|ota| nvm.lock(|nvm| ota.status(nvm).total));
+                info!(Module::Radio, msg::UPDATE_RECEIVED, total);
+            }
+            Ok(false) => {}
+            Err(()) => debug!(Module::Radio, msg::FRAGMENT_INVALID, frame.len()),
+        }
+    }
+
+    // Turns everything off at critical battery. The watchdog can't be stopped
+    // so idle keeps waking up from STOP to feed it, but nothing else runs
+    // apart from `rtc_tick` turning the display off.
+    #[task(priority = 2, resources = [OLED, BUZZER, TIMER_PWM, BREATHALYZER, TIMER_BREATH, TIMER_SEC, TIMER_WARM_UP, POWER, EXT, BUTTON, RADIO_EXTI, NVM, EVENT_LOG, BATTERY, UPTIME, DISPLAY_OFF_IN])]
+    fn shutdown(mut cx: shutdown::Context) {
+        warn!(Module::Power, msg::SHUTDOWN, cx.resources.BATTERY.mv);
+        cx.resources.EXT.unlisten(cx.resources.BUTTON.pin_number());
+        cx.resources.EXT.unlisten(cx.resources.RADIO_EXTI.pin_number());
+
+        cx.resources.BUZZER.disable();
+        cx.resources.TIMER_PWM.unlisten();
+        cx.resources.BREATHALYZER.off();
+        cx.resources.TIMER_BREATH.unlisten();
+        cx.resources.TIMER_WARM_UP.unlisten();
+        cx.resources.TIMER_SEC.unlisten();
+        longfi_bindings::radio_sleep();
+
+        cx.resources.OLED.on("Battery empty");
+        *cx.resources.DISPLAY_OFF_IN = SHUTDOWN_MESSAGE_S;
+
+        let event = Event {
+            kind: EventKind::Shutdown,
+            arg: cx.resources.BATTERY.percent,
+            data: 0,
+            time: *cx.resources.UPTIME,
+        };
+        let event_log = cx.resources.EVENT_LOG;
+        cx.resources.NVM.lock(|nvm| event_log.lock(|log| log.push(nvm, event)));
+
+        cx.resources.POWER.shutdown = true;
+    }
+
+    // Collects console input, the command runs once the line is complete
+    #[task(binds = USART1, priority = 3, spawn = [console_command, console_request], resources = [SERIAL_RX, CONSOLE_INPUT])]
+    fn console_rx(cx: console_rx::Context) {
+        // Reading clears the interrupt, bytes with errors are dropped
+        while let Ok(byte) = cx.resources.SERIAL_RX.read() {
+            match cx.resources.CONSOLE_INPUT.push(byte) {
+                Some(console::Input::Line(line)) => {
+                    cx.spawn.console_command(line).ok();
+                }
+                Some(console::Input::Frame(frame)) => {
+                    cx.spawn.console_request(frame).ok();
+                }
+                None => {}
+            }
+        }
+    }
+
+    // Runs one console command, see console.rs for the list
+    #[task(priority = 1, resources = [SERIAL_TX, BREATHALYZER, WARM_UP, NVM, HISTORY, SETTINGS, CALIBRATION, OLED, RADIO_STATS, RTC, UPTIME, RADIO, DUTY_CYCLE, IDENTITY, PROFILES, POWER])]
+    fn console_command(mut cx: console_command::Context, line: console::Line) {
+        let tx = cx.resources.SERIAL_TX;
+
+        let command = match console::parse(&line) {
+            Ok(command) => command,
+            Err(ParseError::Unknown) => {
+                writeln!(tx, "unknown command, try help").ok();
+                return;
+            }
+            Err(ParseError::Usage(usage)) => {
+                writeln!(tx, "usage: {}", usage).ok();
+                return;
+            }
+        };
+
+        match command {
+            Command::Help => {
+                for (usage, help) in console::HELP.iter() {
+                    writeln!(tx, "{:<28} {}", usage, help).ok();
+                }
+            }
+            Command::Sensor => {
+                let mut breathalyzer = cx.resources.BREATHALYZER;
+                let warm_up = cx.resources.WARM_UP.lock(|warm_up| *warm_up);
+                let (raw, baseline) = breathalyzer.lock(|b| (b.read_curr(), b.curr_val));
+
+                write!(tx, "raw {} baseline {}", raw, baseline).ok();
+                writeln!(tx, "{}", if warm_up { " (warming up)" } else { "" }).ok();
+            }
+            Command::History(n, profile) => {
+                let mut nvm = cx.resources.NVM;
+                let mut history = cx.resources.HISTORY;
+                let bands = cx.resources.SETTINGS.lock(|settings| settings.bands);
+                let mut shown = 0;
+
+                // One record at a time so the EEPROM isn't locked while printing
+                for i in 0.. {
+                    if shown == n {
+                        break;
+                    }
+                    let record = match nvm.lock(|nvm| history.lock(|history| history.get(nvm, i))) {
+                        Some(record) => record,
+                        None => break,
+                    };
+                    if profile.map_or(false, |profile| profile != record.profile) {
+                        continue;
+                    }
+
+                    write!(tx, "{} ", DateTime::from_seconds(record.time)).ok();
+                    match Severity::from_category(record.category) {
+                        Some(severity) => write!(tx, "{}", severity.band(&bands)).ok(),
+                        None => write!(tx, "category {}", record.category).ok(),
+                    };
+                    writeln!(
+                        tx,
+                        " raw {} baseline {} profile {}",
+                        record.raw,
+                        record.baseline,
+                        record.profile
+                    )
+                    .ok();
+                    shown += 1;
+                }
+            }
+            Command::Get(name) => {
+                let settings = cx.resources.SETTINGS.lock(|settings| *settings);
+                for info in SETTINGS_INFO.iter() {
+                    if name.is_none() || name == Some(info.name) {
+                        let value = settings.get(info.name).unwrap_or(0);
+                        writeln!(tx, "{} = {}    {}", info.name, value, info.help).ok();
+                    }
+                }
+                if let Some(name) = name {
+                    if settings.get(name).is_none() {
+                        writeln!(tx, "unknown setting {}", name).ok();
+                    }
+                }
+            }
+            Command::Set(name, value) => {
+                let result = cx
+                    .resources
+                    .SETTINGS
+                    .lock(|settings| settings.set(name, value).map(|_| *settings));
+
+                match result {
+                    Ok(settings) => {
+                        cx.resources.OLED.lock(|oled| {
+                            oled.power.dim_after = settings.dim_after_s;
+                            oled.power.off_after = settings.off_after_s;
+                        });
+                        cx.resources.POWER.lock(|power| power.budget_ua = settings.budget_ua());
+
+                        if cx.resources.NVM.lock(|nvm| settings.save(nvm)).is_ok() {
+                            writeln!(tx, "{} = {}", name, value).ok();
+                        } else {
+                            writeln!(tx, "{} = {}, not stored", name, value).ok();
+                        }
+                    }
+                    Err(SettingError::Unknown) => {
+                        writeln!(tx, "unknown setting {}", name).ok();
+                    }
+                    Err(SettingError::Range(min, max)) => {
+                        writeln!(tx, "{} must be {} to {}", name, min, max).ok();
+                    }
+                }
+            }
+            Command::Calibrate => {
+                if cx.resources.WARM_UP.lock(|warm_up| *warm_up) {
+                    writeln!(tx, "wait for the heater to warm up").ok();
+                    return;
+                }
+
+                let mut calibration = cx.resources.CALIBRATION;
+                let mut breathalyzer = cx.resources.BREATHALYZER;
+                let mut nvm = cx.resources.NVM;
+
+                let (clean_air, saved) = calibration.lock(|calibration| {
+                    let clean_air = breathalyzer.lock(|breathalyzer| {
+                        let clean_air = calibration.calibrate(breathalyzer);
+                        breathalyzer.curr_val = clean_air;
+                        clean_air
+                    });
+                    (clean_air, nvm.lock(|nvm| calibration.save(nvm)))
+                });
+
+                if saved.is_ok() {
+                    writeln!(tx, "clean air {}", clean_air).ok();
+                } else {
+                    writeln!(tx, "clean air {}, not stored", clean_air).ok();
+                }
+            }
+            Command::Profiles => {
+                let legal_limit = cx.resources.SETTINGS.lock(|settings| settings.legal_limit);
+                let mut profiles = cx.resources.PROFILES;
+                let selected = profiles.lock(|profiles| profiles.selected);
+
+                for id in 0..=profiles::MAX_PROFILES as u8 {
+                    let marker = if id == selected { '*' } else { ' ' };
+                    match profiles.lock(|profiles| profiles.get(id)) {
+                        None if id == GUEST => writeln!(
+                            tx,
+                            "{}{} guest limit {} (setting) rate {}",
+                            marker,
+                            id,
+                            legal_limit,
+                            sober::ELIMINATION_RATE
+                        )
+                        .ok(),
+                        None => None,
+                        Some(profile) => writeln!(
+                            tx,
+                            "{}{} {} limit {} rate {}{}",
+                            marker,
+                            id,
+                            profile.initials(),
+                            profile.limit,
+                            profile.rate,
+                            if profile.novice { " novice" } else { "" }
+                        )
+                        .ok(),
+                    };
+                }
+            }
+            Command::SetProfile(id, initials, limit, rate, novice) => match Profile::new(initials, limit, rate, novice) {
+                Some(profile) => {
+                    store_profile(tx, &mut cx.resources.PROFILES, &mut cx.resources.NVM, id, Some(profile))
+                }
+                None => {
+                    writeln!(tx, "initials are 1 to 3 letters or digits, limit 1 to {}", profiles::MAX_LIMIT).ok();
+                    writeln!(tx, "and rate {} to {}", sober::MIN_RATE, sober::MAX_RATE).ok();
+                }
+            },
+            Command::DeleteProfile(id) => {
+                store_profile(tx, &mut cx.resources.PROFILES, &mut cx.resources.NVM, id, None);
+            }
+            Command::Sober(id) => {
+                let legal_limit = cx.resources.SETTINGS.lock(|settings| settings.legal_limit);
+                let now = cx.resources.RTC.lock(|rtc| DateTime::from_instant(&rtc.now()).to_seconds());
+                let (id, profile) = cx.resources.PROFILES.lock(|profiles| {
+                    let id = id.unwrap_or(profiles.selected);
+                    (id, profiles.get(id))
+                });
+                let limit = profile.map_or(legal_limit, |profile| profile.limit());
+                let rate = profile.map_or(sober::ELIMINATION_RATE, |profile| profile.rate);
+
+                let mut nvm = cx.resources.NVM;
+                let mut history = cx.resources.HISTORY;
+                let mut calibration = cx.resources.CALIBRATION;
+                let minutes = nvm.lock(|nvm| {
+                    history.lock(|history| {
+                        calibration.lock(|calibration| {
+                            sober::from_history(history, nvm, calibration, id, limit, rate, now)
+                        })
+                    })
+                });
+
+                match minutes {
+                    None => writeln!(tx, "no calibrated reading of profile {}", id).ok(),
+                    Some(0) => writeln!(tx, "below the limit, {}", sober::DISCLAIMER).ok(),
+                    Some(minutes) => writeln!(
+                        tx,
+                        "below the limit in {}, {}",
+                        sober::Duration(minutes),
+                        sober::DISCLAIMER
+                    )
+                    .ok(),
+                };
+            }
+            Command::Radio => {
+                let stats = cx.resources.RADIO_STATS.lock(|stats| *stats);
+                let uptime = cx.resources.UPTIME.lock(|uptime| *uptime);
+                stats.report(tx, uptime).ok();
+
+                let now_ms = uptime as u64 * 1000;
+                #[cfg(not(feature = "lorawan"))]
+                let budget_ms = cx.resources.DUTY_CYCLE.lock(|duty| duty.remaining_ms(UPLINK_FREQUENCY, now_ms));
+                #[cfg(feature = "lorawan")]
+                let budget_ms = cx.resources.RADIO.lock(|radio| radio.region.duty.remaining_ms(UPLINK_FREQUENCY, now_ms));
+                writeln!(tx, "airtime left {} ms", budget_ms).ok();
+            }
+            Command::Id => {
+                let identity = *cx.resources.IDENTITY;
+                write!(tx, "uid ").ok();
+                for byte in identity::uid().iter().rev() {
+                    write!(tx, "{:02x}", byte).ok();
+                }
+                writeln!(tx).ok();
+                writeln!(
+                    tx,
+                    "oui {:08x} device {:04x} ({})",
+                    identity.oui,
+                    identity.device_id,
+                    if identity.provisioned { "provisioned" } else { "from uid" }
+                )
+                .ok();
+                writeln!(tx, "readout protection level {}", identity::readout_protection()).ok();
+            }
+            Command::Clock(None) => {
+                let now = cx.resources.RTC.lock(|rtc| DateTime::from_instant(&rtc.now()));
+                writeln!(tx, "{}", now).ok();
+            }
+            Command::Clock(Some(time)) => {
+                cx.resources.RTC.lock(|rtc| rtc.set(time.to_instant()));
+                writeln!(tx, "{}", time).ok();
+            }
+            Command::Reboot => {
+                writeln!(tx, "rebooting").ok();
+                nb::block!(tx.flush()).ok();
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+    }
+
+    // Answers a request frame from the host tools, see the protocol crate
+    #[task(priority = 1, resources = [SERIAL_TX, UPTIME, RTC, BATTERY, NVM, HISTORY, SETTINGS, CALIBRATION, KEYS, REMOTE, OLED, RADIO, IDENTITY, POWER])]
+    fn console_request(mut cx: console_request::Context, mut frame: console::Frame) {
+        let tx = cx.resources.SERIAL_TX;
+        let mut nvm = cx.resources.NVM;
+        let mut history = cx.resources.HISTORY;
+        let mut calibration = cx.resources.CALIBRATION;
+
+        let response = match protocol::decode(&mut frame[..]) {
+            Err(_) => Response::Error(ErrorCode::BadRequest),
+            Ok(Request::Info) => {
+                let mut version = heapless::String::new();
+                version.push_str(env!("CARGO_PKG_VERSION")).ok();
+
+                Response::Info(Info {
+                    version: version,
+                    uptime: cx.resources.UPTIME.lock(|uptime| *uptime),
+                    clock: cx.resources.RTC.lock(|rtc| DateTime::from_instant(&rtc.now()).to_seconds()),
+                    battery_mv: cx.resources.BATTERY.lock(|battery| battery.mv),
+                    history_len: history.lock(|history| history.len()),
+                    clean_air: calibration.lock(|calibration| calibration.clean_air),
+                    calibration_points: calibration.lock(|calibration| calibration.points.len() as u8),
+                    keys_provisioned: cx.resources.KEYS.is_some(),
+                    uid: identity::uid(),
+                    oui: cx.resources.IDENTITY.oui,
+                    device_id: cx.resources.IDENTITY.device_id,
+                    identity_provisioned: cx.resources.IDENTITY.provisioned,
+                    readout_protection: identity::readout_protection(),
+                })
+            }
+            Ok(Request::GetSettings { start }) => {
+                let settings = cx.resources.SETTINGS.lock(|settings| *settings);
+                let mut list = heapless::Vec::new();
+                for info in SETTINGS_INFO
+                    .iter()
+                    .skip(start as usize)
+                    .take(protocol::SETTINGS_CHUNK as usize)
+                {
+                    let mut setting_name = protocol::Name::new();
+                    setting_name.push_str(info.name).ok();
+                    list.push(Setting {
+                        name: setting_name,
+                        value: settings.get(info.name).unwrap_or(0),
+                        min: info.min,
+                        max: info.max,
+                    })
+                    .ok();
+                }
+                Response::Settings {
+                    total: SETTINGS_INFO.len() as u8,
+                    settings: list,
+                }
+            }
+            Ok(Request::SetSetting { name, value }) => {
+                let result = cx
+                    .resources
+                    .SETTINGS
+                    .lock(|settings| settings.set(&name, value).map(|_| *settings));
+
+                match result {
+                    Ok(settings) => {
+                        cx.resources.OLED.lock(|oled| {
+                            oled.power.dim_after = settings.dim_after_s;
+                            oled.power.off_after = settings.off_after_s;
+                        });
+                        cx.resources.POWER.lock(|power| power.budget_ua = settings.budget_ua());
+                        match nvm.lock(|nvm| settings.save(nvm)) {
+                            Ok(()) => Response::Done,
+                            Err(()) => Response::Error(ErrorCode::Storage),
+                        }
+                    }
+                    Err(SettingError::Unknown) => Response::Error(ErrorCode::UnknownSetting),
+                    Err(SettingError::Range(_, _)) => Response::Error(ErrorCode::OutOfRange),
+                }
+            }
+            Ok(Request::History { start, count }) => {
+                let total = history.lock(|history| history.len());
+                let mut records = heapless::Vec::new();
+
+                for i in start..start.saturating_add(count.min(HISTORY_CHUNK) as u32) {
+                    match nvm.lock(|nvm| history.lock(|history| history.get(nvm, i))) {
+                        Some(record) => records.push(history_record(record)).ok(),
+                        None => break,
+                    };
+                }
+                Response::History {
+                    total: total,
+                    records: records,
+                }
+            }
+            Ok(Request::SetCalibration { points }) => {
+                calibration.lock(|calibration| {
+                    if calibration.set_points(&points).is_err() {
+                        Response::Error(ErrorCode::BadRequest)
+                    } else {
+                        match nvm.lock(|nvm| calibration.save(nvm)) {
+                            Ok(()) => Response::Done,
+                            Err(()) => Response::Error(ErrorCode::Storage),
+                        }
+                    }
+                })
+            }
+            Ok(Request::ProvisionKeys { current_key, .. })
+            | Ok(Request::ProvisionIdentity { current_key, .. })
+                if !keys::may_provision(cx.resources.KEYS.as_ref(), current_key.as_ref()) =>
+            {
+                warn!(Module::Main, msg::PROVISION_REFUSED);
+                Response::Error(ErrorCode::Unauthorized)
+            }
+            // Keys stored at level 0 could be read right back with a debugger
+            #[cfg(not(debug_assertions))]
+            Ok(Request::ProvisionKeys { .. }) if identity::readout_protection() == 0 => {
+                warn!(Module::Main, msg::PROVISION_UNPROTECTED);
+                Response::Error(ErrorCode::Unprotected)
+            }
+            Ok(Request::ProvisionKeys { dev_eui, app_eui, app_key, .. }) => {
+                let keys = Keys {
+                    dev_eui: dev_eui,
+                    app_eui: app_eui,
+                    app_key: app_key,
+                };
+                let remote = cx.resources.REMOTE;
+                match nvm.lock(|nvm| keys.save(nvm).and_then(|_| remote.set_keys(nvm, &keys))) {
+                    Ok(()) => {
+                        *cx.resources.KEYS = Some(keys);
+                        // Rejoins with the new keys on the next uplink
+                        #[cfg(feature = "lorawan")]
+                        cx.resources.RADIO.lock(|radio| radio.set_keys(keys));
+                        Response::Done
+                    }
+                    Err(()) => Response::Error(ErrorCode::Storage),
+                }
+            }
+            Ok(Request::SetClock { seconds }) => {
+                let time = DateTime::from_seconds(seconds);
+                cx.resources.RTC.lock(|rtc| rtc.set(time.to_instant()));
+                Response::Done
+            }
+            Ok(Request::ProvisionIdentity { oui, device_id, .. }) => {
+                let identity = Identity {
+                    oui: oui,
+                    device_id: device_id,
+                    provisioned: true,
+                };
+                match nvm.lock(|nvm| identity.save(nvm)) {
+                    Ok(()) => {
+                        *cx.resources.IDENTITY = identity;
+                        Response::Done
+                    }
+                    Err(()) => Response::Error(ErrorCode::Storage),
+                }
+            }
+            Ok(Request::Bands) => {
+                let bands = cx.resources.SETTINGS.lock(|settings| settings.bands);
+                Response::Bands(bands.iter().cloned().collect())
+            }
+            Ok(Request::SetBand { index, band }) => {
+                // Padded the way `Band::new` does it, so it shows the same
+                let result = if Band::new(band.name(), band.icon(), band.beeps, band.alert) == Some(band) {
+                    cx.resources
+                        .SETTINGS
+                        .lock(|settings| settings.set_band(index, band).map(|_| *settings))
+                } else {
+                    Err(())
+                };
+
+                match result {
+                    Ok(settings) => match nvm.lock(|nvm| settings.save(nvm)) {
+                        Ok(()) => Response::Done,
+                        Err(()) => Response::Error(ErrorCode::Storage),
+                    },
+                    Err(()) => Response::Error(ErrorCode::BadRequest),
+                }
+            }
+        };
+
+        // Framed by zeros like the requests, the encoded frame ends with one
+        let mut buf = [0; protocol::MAX_FRAME];
+        if let Ok(encoded) = protocol::encode(&response, &mut buf) {
+            nb::block!(tx.write(0)).ok();
+            for byte in encoded.iter() {
+                nb::block!(tx.write(*byte)).ok();
+            }
         }
     }
 
     // Device warm up
-    #[task(binds = TIM22, priority = 2, resources = [OLED, BREATHALYZER, COUNT, TIMER_WARM_UP, WARM_UP])]
+    #[task(binds = TIM22, priority = 2, resources = [OLED, BREATHALYZER, COUNT, TIMER_WARM_UP, WARM_UP, POWER])]
     fn warm_up(cx: warm_up::Context) {
         cx.resources.TIMER_WARM_UP.clear_irq();
 
@@ -364,7 +2339,9 @@ const APP: () = {
             *cx.resources.COUNT += 1;
         } else {
             cx.resources.OLED.on("Ready");
+            info!(Module::Sensor, msg::WARM_UP_DONE);
             cx.resources.TIMER_WARM_UP.unlisten();
+            cx.resources.POWER.set_busy(Busy::WarmUp, false);
             *cx.resources.COUNT = 0;
             *cx.resources.WARM_UP = false;
         }
@@ -372,7 +2349,7 @@ const APP: () = {
 
     // Interrupt handlers used to dispatch software tasks
     extern "C" {
-        fn USART1();
+        fn I2C1();
         fn USART2();
         fn USART4_USART5();
     }
@@ -0,0 +1,115 @@
+use stm32l0xx_hal::pac;
+
+/// Data EEPROM of the STM32L072
+pub const EEPROM_START: u32 = 0x0808_0000;
+pub const EEPROM_SIZE: u32 = 6 * 1024;
+
+// EEPROM layout, offsets from EEPROM_START
+pub const SETTINGS: u32 = 0x000;
+pub const KEYS: u32 = 0x080;
+pub const CALIBRATION: u32 = 0x0C0;
+pub const CRASH_RECORD: u32 = 0x100;
+pub const EVENT_LOG_HEAD: u32 = 0x1FC;
+pub const EVENT_LOG: u32 = 0x200;
+pub const EVENT_LOG_SIZE: u32 = 0x200;
+pub const HISTORY_HEAD: u32 = 0x3FC;
+pub const HISTORY: u32 = 0x400;
+pub const HISTORY_SIZE: u32 = 0x800;
+pub const COMMAND_COUNTER: u32 = 0xC00;
+pub const LAST_TRIGGER: u32 = 0xC04;
+// 0xC40 to 0xF00 is used by updates, see boot::layout
+pub const IDENTITY: u32 = 0xF00;
+pub const PROFILES: u32 = 0xF10;
+pub const ALERTS: u32 = 0xF40;
+pub const BANDS: u32 = 0xF80;
+
+const PEKEY1: u32 = 0x89AB_CDEF;
+const PEKEY2: u32 = 0x0203_0405;
+
+/// Access to the non-volatile memories through the flash interface
+pub struct Nvm {
+    flash: pac::FLASH,
+}
+
+impl Nvm {
+    pub fn new(flash: pac::FLASH) -> Nvm {
+        Nvm { flash: flash }
+    }
+
+    /// Reads bytes from the EEPROM at `offset`
+    pub fn read(&self, offset: u32, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let addr = EEPROM_START + offset + i as u32;
+            *byte = unsafe { core::ptr::read_volatile(addr as *const u8) };
+        }
+    }
+
+    pub fn read_word(&self, offset: u32) -> u32 {
+        let addr = EEPROM_START + offset;
+        unsafe { core::ptr::read_volatile(addr as *const u32) }
+    }
+
+    /// Writes bytes to the EEPROM at `offset`, unchanged bytes are skipped to save wear
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+        if offset + data.len() as u32 > EEPROM_SIZE {
+            return Err(());
+        }
+
+        self.unlock();
+        let mut result = Ok(());
+
+        for (i, byte) in data.iter().enumerate() {
+            let addr = (EEPROM_START + offset + i as u32) as *mut u8;
+            unsafe {
+                if core::ptr::read_volatile(addr) != *byte {
+                    core::ptr::write_volatile(addr, *byte);
+                    if self.wait().is_err() {
+                        result = Err(());
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.lock();
+        result
+    }
+
+    pub fn write_word(&mut self, offset: u32, value: u32) -> Result<(), ()> {
+        self.write(offset, &value.to_le_bytes())
+    }
+
+    /// Erases a page of program flash, see `boot::flash`
+    pub fn erase_page(&mut self, addr: u32) -> Result<(), ()> {
+        boot::flash::erase_page(&self.flash, addr)
+    }
+
+    /// Writes words to flash or EEPROM by absolute address
+    pub fn write_words(&mut self, addr: u32, words: &[u32]) -> Result<(), ()> {
+        boot::flash::write(&self.flash, addr, words)
+    }
+
+    fn unlock(&mut self) {
+        if self.flash.pecr.read().pelock().bit_is_set() {
+            self.flash.pekeyr.write(|w| unsafe { w.bits(PEKEY1) });
+            self.flash.pekeyr.write(|w| unsafe { w.bits(PEKEY2) });
+        }
+    }
+
+    fn lock(&mut self) {
+        self.flash.pecr.modify(|_, w| w.pelock().set_bit());
+    }
+
+    // Waits for the current operation and checks the error flags
+    fn wait(&mut self) -> Result<(), ()> {
+        while self.flash.sr.read().bsy().bit_is_set() {}
+
+        let sr = self.flash.sr.read();
+        if sr.wrperr().bit_is_set() || sr.sizerr().bit_is_set() || sr.pgaerr().bit_is_set() {
+            // Error flags are cleared by writing 1
+            self.flash.sr.write(|w| unsafe { w.bits(sr.bits()) });
+            return Err(());
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,13 @@
-use ssd1306::{interface::SpiInterface, prelude::*, Builder};
-extern crate panic_semihosting;
+use core::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+use heapless::{consts::*, String};
+use ssd1306::{
+    interface::{DisplayInterface, SpiInterface},
+    prelude::*,
+    Builder,
+};
+
+use crate::diagnostics::Lines;
+use crate::display_power::{DisplayAction, DisplayPower, DisplayState};
 
 use stm32l0xx_hal::{
     delay::Delay,
@@ -16,36 +24,123 @@ use embedded_graphics::{
     pixelcolor::BinaryColor,
     prelude::*,
     primitives::{Circle, Rectangle},
-    style::{PrimitiveStyleBuilder, TextStyle},
+    style::{PrimitiveStyle, PrimitiveStyleBuilder, TextStyle},
 };
 
+type OledSpi = Spi<SPI2, (PB13<Input<Floating>>, NoMiso, PB15<Input<Floating>>)>;
+
+const CMD_CONTRAST: u8 = 0x81;
+const CMD_DISPLAY_OFF: u8 = 0xAE;
+const CMD_DISPLAY_ON: u8 = 0xAF;
+
+// Marks an empty slot in `PanelCommands`
+const NO_CONTRAST: u16 = 0x100;
+const POWER_UNCHANGED: u8 = 0;
+const POWER_OFF: u8 = 1;
+const POWER_ON: u8 = 2;
+
+/// Commands the ssd1306 driver has no API for. `Oled` queues them from its
+/// `DisplayPower` state and the interface sends them before the next frame,
+/// the driver owns the interface so the two share this.
+pub struct PanelCommands {
+    contrast: AtomicU16,
+    power: AtomicU8,
+}
+
+impl PanelCommands {
+    pub const fn new() -> PanelCommands {
+        PanelCommands {
+            contrast: AtomicU16::new(NO_CONTRAST),
+            power: AtomicU8::new(POWER_UNCHANGED),
+        }
+    }
+
+    fn queue(&self, contrast: Option<u8>, on: bool) {
+        if let Some(contrast) = contrast {
+            self.contrast.store(contrast as u16, Ordering::Relaxed);
+        }
+        let power = if on { POWER_ON } else { POWER_OFF };
+        self.power.store(power, Ordering::Relaxed);
+    }
+
+    // Only the interface takes, so load and store need not be one operation
+    fn take_contrast(&self) -> Option<u8> {
+        let contrast = self.contrast.load(Ordering::Relaxed);
+        self.contrast.store(NO_CONTRAST, Ordering::Relaxed);
+        if contrast == NO_CONTRAST {
+            None
+        } else {
+            Some(contrast as u8)
+        }
+    }
+
+    fn take_power(&self) -> Option<bool> {
+        let power = self.power.load(Ordering::Relaxed);
+        self.power.store(POWER_UNCHANGED, Ordering::Relaxed);
+        match power {
+            POWER_OFF => Some(false),
+            POWER_ON => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// SPI interface that also sends the queued contrast and display on/off commands
+pub struct OledInterface {
+    inner: SpiInterface<OledSpi, PB8<Output<PushPull>>>,
+    commands: &'static PanelCommands,
+}
+
+impl DisplayInterface for OledInterface {
+    type Error = <SpiInterface<OledSpi, PB8<Output<PushPull>>> as DisplayInterface>::Error;
+
+    fn send_commands(&mut self, cmd: &[u8]) -> Result<(), Self::Error> {
+        self.inner.send_commands(cmd)
+    }
+
+    fn send_data(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        if let Some(contrast) = self.commands.take_contrast() {
+            self.inner.send_commands(&[CMD_CONTRAST, contrast])?;
+        }
+        if let Some(on) = self.commands.take_power() {
+            let cmd = if on { CMD_DISPLAY_ON } else { CMD_DISPLAY_OFF };
+            self.inner.send_commands(&[cmd])?;
+        }
+        self.inner.send_data(buf)
+    }
+}
+
 pub struct Oled {
     pub pb9: PB9<Output<PushPull>>,
     pub delay: Delay,
-    pub disp: GraphicsMode<
-        SpiInterface<
-            Spi<SPI2, (PB13<Input<Floating>>, NoMiso, PB15<Input<Floating>>)>,
-            PB8<Output<PushPull>>,
-        >,
-    >,
+    pub disp: GraphicsMode<OledInterface>,
     pub style1:
         embedded_graphics::style::PrimitiveStyle<embedded_graphics::pixelcolor::BinaryColor>,
     pub style2:
         embedded_graphics::style::PrimitiveStyle<embedded_graphics::pixelcolor::BinaryColor>,
     pub state: bool,
+    pub power: DisplayPower,
+    pub message: String<U16>,
+    pub battery: Option<u8>,
+    pub lines: Lines,
+    commands: &'static PanelCommands,
 }
 
 impl Oled {
     pub fn new(
-        spi: Spi<SPI2, (PB13<Input<Floating>>, NoMiso, PB15<Input<Floating>>)>,
+        spi: OledSpi,
         pb8: PB8<Input<Floating>>,
         pb9: PB9<Input<Floating>>,
         delay: Delay,
+        commands: &'static PanelCommands,
     ) -> Oled {
         Oled {
             pb9: pb9.into_push_pull_output(),
             disp: Builder::new()
-                .connect_spi(spi, pb8.into_push_pull_output())
+                .connect(OledInterface {
+                    inner: SpiInterface::new(spi, pb8.into_push_pull_output()),
+                    commands: commands,
+                })
                 .into(),
             delay: delay,
             style1: PrimitiveStyleBuilder::new()
@@ -59,55 +154,162 @@ impl Oled {
                 .fill_color(BinaryColor::Off)
                 .build(),
             state: false,
+            power: DisplayPower::new(),
+            message: String::new(),
+            battery: None,
+            lines: Lines::new(),
+            commands: commands,
         }
     }
 
+    /// Shows a message, waking the display if needed
     pub fn on(&mut self, message: &str) {
-        let res = &mut self.pb9;
+        self.message.clear();
+        self.message.push_str(message).ok();
+        self.lines.clear();
 
-        self.disp.reset(res, &mut self.delay).unwrap();
-        self.disp.init().unwrap();
+        self.show();
+    }
+
+    /// Shows a screen of text lines instead of a message
+    pub fn show_lines(&mut self, lines: Lines) {
+        self.lines = lines;
+
+        self.show();
+    }
+
+    fn show(&mut self) {
+        if !self.state {
+            let res = &mut self.pb9;
+
+            self.disp.reset(res, &mut self.delay).unwrap();
+            self.disp.init().unwrap();
+        }
+
+        self.power.activity();
+        self.set_power(true);
+        self.draw();
+
+        self.state = true;
+    }
+
+    /// Wakes the display on user activity, returns true if it was dimmed or off
+    pub fn wake(&mut self) -> bool {
+        if !self.state {
+            return false;
+        }
+
+        let woken = self.power.activity();
+        if woken {
+            self.set_power(true);
+            self.draw();
+        }
+        woken
+    }
+
+    /// Turns the panel off with the display-off command, keeping its contents
+    pub fn off(&mut self) {
+        self.commands.queue(None, false);
+        self.disp.flush().unwrap();
+
+        self.power.state = DisplayState::Off;
+    }
+
+    /// Sets the contrast used while the display is fully on
+    pub fn set_contrast(&mut self, contrast: u8) {
+        self.power.contrast = contrast;
+        if self.state && self.power.state != DisplayState::Off {
+            self.set_power(true);
+            self.disp.flush().unwrap();
+        }
+    }
+
+    /// Handles dimming, turning off and pixel shifting, called once a second
+    pub fn tick(&mut self) {
+        if !self.state {
+            return;
+        }
+
+        match self.power.tick() {
+            Some(DisplayAction::Dim) => {
+                self.set_power(true);
+                self.disp.flush().unwrap();
+            }
+            Some(DisplayAction::Off) => self.off(),
+            Some(DisplayAction::Shift) => self.draw(),
+            None => {}
+        }
+    }
+
+    /// Updates the battery icon, redrawn with the next message
+    pub fn set_battery(&mut self, percent: u8) {
+        self.battery = Some(percent);
+    }
+
+    fn set_power(&mut self, on: bool) {
+        self.commands.queue(Some(self.power.current_contrast()), on);
+    }
+
+    // Redraws the current message at the burn-in offset
+    fn draw(&mut self) {
+        let (x, y) = self.power.offset();
 
         self.disp.clear();
 
-        Circle::new(Point::new(27, 23), 5)
+        if !self.lines.is_empty() {
+            for (i, line) in self.lines.iter().enumerate() {
+                Text::new(line.as_str(), Point::new(x, i as i32 * 12 + y))
+                    .into_styled(TextStyle::new(Font6x12, BinaryColor::On))
+                    .draw(&mut self.disp);
+            }
+
+            self.disp.flush().unwrap();
+            return;
+        }
+
+        Circle::new(Point::new(27 + x, 23 + y), 5)
             .into_styled(self.style2)
             .draw(&mut self.disp);
 
-        Rectangle::new(Point::new(10, 20), Point::new(25, 35))
+        Rectangle::new(Point::new(10 + x, 20 + y), Point::new(25 + x, 35 + y))
             .into_styled(self.style1)
             .draw(&mut self.disp);
 
-        Rectangle::new(Point::new(10, 15), Point::new(25, 20))
+        Rectangle::new(Point::new(10 + x, 15 + y), Point::new(25 + x, 20 + y))
             .into_styled(self.style2)
             .draw(&mut self.disp);
 
-        let t1 = Text::new("Breathalyzer", Point::new(40, 16))
+        let t1 = Text::new("Breathalyzer", Point::new(40 + x, 16 + y))
             .into_styled(TextStyle::new(Font6x12, BinaryColor::On));
 
-        let t2 = Text::new(message, Point::new(40, 35))
+        let t2 = Text::new(self.message.as_str(), Point::new(40 + x, 35 + y))
             .into_styled(TextStyle::new(Font8x16, BinaryColor::On));
 
         t1.draw(&mut self.disp);
         t2.draw(&mut self.disp);
 
-        self.disp.flush().unwrap();
+        if let Some(percent) = self.battery {
+            self.draw_battery(x, y, percent);
+        }
 
-        self.state = true;
+        self.disp.flush().unwrap();
     }
 
-    pub fn off(&mut self) {
-        let res = &mut self.pb9;
-
-        self.disp.reset(res, &mut self.delay).unwrap();
-        self.disp.init().unwrap();
-
-        self.disp.clear();
-
-        // clear display
+    // Battery outline in the top right corner, filled according to the charge
+    fn draw_battery(&mut self, x: i32, y: i32, percent: u8) {
+        Rectangle::new(Point::new(108 + x, 1 + y), Point::new(124 + x, 8 + y))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(&mut self.disp);
 
-        self.disp.flush().unwrap();
+        Rectangle::new(Point::new(125 + x, 3 + y), Point::new(126 + x, 6 + y))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(&mut self.disp);
 
-        self.state = false;
+        let width = percent.min(100) as i32 * 14 / 100;
+        if width > 0 {
+            Rectangle::new(Point::new(110 + x, 3 + y), Point::new(109 + width + x, 6 + y))
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                .draw(&mut self.disp);
+        }
     }
 }
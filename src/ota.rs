@@ -0,0 +1,174 @@
+use boot::image::{self, Manifest};
+use boot::layout::{EEPROM_START, OTA_BITMAP, OTA_BITMAP_SIZE, PAGE_SIZE, SLOT_B, SLOT_SIZE};
+use boot::state::{self, State};
+use protocol::command::{Key, Rejection};
+use protocol::update::{self, Status, UpdateStatus, FRAGMENT_SIZE};
+
+use crate::nvm::Nvm;
+
+/// Seconds new firmware has to run before it confirms itself, resets
+/// before that count towards a rollback
+pub const CONFIRM_AFTER_S: u32 = 60;
+
+const FRAGMENTS_PER_PAGE: u16 = (PAGE_SIZE / FRAGMENT_SIZE as u32) as u16;
+
+/// Receives firmware updates into slot B, see the bootloader in boot/
+pub struct Ota {
+    pub state: State,
+    received: u16,
+}
+
+impl Ota {
+    pub fn load(nvm: &Nvm) -> Ota {
+        let mut ota = Ota {
+            state: State::load(),
+            received: 0,
+        };
+        if ota.state.status == Status::Downloading {
+            ota.received = (0..ota.total()).filter(|i| ota.has(nvm, *i)).count() as u16;
+        }
+        ota
+    }
+
+    fn total(&self) -> u16 {
+        update::fragment_count(self.state.manifest.size)
+    }
+
+    fn has(&self, nvm: &Nvm, index: u16) -> bool {
+        let mut byte = [0];
+        nvm.read(OTA_BITMAP + index as u32 / 8, &mut byte);
+        byte[0] & 1 << (index % 8) != 0
+    }
+
+    fn save(&self, nvm: &mut Nvm) -> Result<(), Rejection> {
+        nvm.write_words(state::ADDRESS, &self.state.to_words())
+            .map_err(|_| Rejection::Storage)
+    }
+
+    /// Starts over with a new image, fragments of an earlier one are dropped.
+    /// Only versions above the installed one are taken, so old firmware with
+    /// known flaws can't be brought back.
+    pub fn start(&mut self, nvm: &mut Nvm, version: u32, size: u32) -> Result<(), Rejection> {
+        if size == 0 || size > SLOT_SIZE {
+            return Err(Rejection::OutOfRange);
+        }
+        if !self.state.is_newer(version) {
+            return Err(Rejection::Outdated);
+        }
+
+        let clear = [0; (OTA_BITMAP_SIZE / 4) as usize];
+        nvm.write_words(EEPROM_START + OTA_BITMAP, &clear)
+            .map_err(|_| Rejection::Storage)?;
+
+        self.state.status = Status::Downloading;
+        self.state.attempts = 0;
+        self.state.manifest = Manifest {
+            version: version,
+            size: size,
+            signature: [0; 64],
+        };
+        self.received = 0;
+        self.save(nvm)
+    }
+
+    pub fn set_signature(&mut self, nvm: &mut Nvm, half: u8, bytes: &[u8; 32]) -> Result<(), Rejection> {
+        if self.state.status != Status::Downloading {
+            return Err(Rejection::NoUpdate);
+        }
+        if half > 1 {
+            return Err(Rejection::OutOfRange);
+        }
+
+        let start = half as usize * 32;
+        self.state.manifest.signature[start..start + 32].copy_from_slice(bytes);
+        self.save(nvm)
+    }
+
+    /// Writes a fragment to slot B. Ok(true) once all of them are received,
+    /// fragments that are corrupt, not signed with the command `key` or not
+    /// of the current update are an error.
+    pub fn fragment(&mut self, nvm: &mut Nvm, key: &Key, frame: &[u8]) -> Result<bool, ()> {
+        if self.state.status != Status::Downloading {
+            return Err(());
+        }
+        let (index, data) = update::decode_radio_fragment(key, frame)?;
+        if index >= self.total() {
+            return Err(());
+        }
+
+        if !self.has(nvm, index) {
+            // A page is erased before the first of its fragments is written
+            let first = index - index % FRAGMENTS_PER_PAGE;
+            let addr = SLOT_B + index as u32 * FRAGMENT_SIZE as u32;
+            if (first..first + FRAGMENTS_PER_PAGE).all(|i| !self.has(nvm, i)) {
+                nvm.erase_page(addr)?;
+            }
+
+            let mut words = [0; FRAGMENT_SIZE / 4];
+            for (word, bytes) in words.iter_mut().zip(data.chunks(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            nvm.write_words(addr, &words)?;
+
+            let mut byte = [0];
+            nvm.read(OTA_BITMAP + index as u32 / 8, &mut byte);
+            nvm.write(OTA_BITMAP + index as u32 / 8, &[byte[0] | 1 << (index % 8)])?;
+            self.received += 1;
+        }
+
+        Ok(self.received == self.total())
+    }
+
+    pub fn status(&self, nvm: &Nvm) -> UpdateStatus {
+        let total = self.total();
+        let next_missing = if self.state.status == Status::Downloading {
+            (0..total).find(|i| !self.has(nvm, *i)).unwrap_or(total)
+        } else {
+            total
+        };
+
+        UpdateStatus {
+            status: self.state.status,
+            version: self.state.manifest.version,
+            received: self.received,
+            total: total,
+            next_missing: next_missing,
+        }
+    }
+
+    /// Checks the signature, the bootloader installs the image at the next reset
+    pub fn apply(&mut self, nvm: &mut Nvm) -> Result<(), Rejection> {
+        if self.state.status != Status::Downloading {
+            return Err(Rejection::NoUpdate);
+        }
+        if self.received != self.total() {
+            return Err(Rejection::Incomplete);
+        }
+        if !self.state.is_newer(self.state.manifest.version) {
+            return Err(Rejection::Outdated);
+        }
+
+        let manifest = self.state.manifest;
+        if !image::verify(&manifest, image::slot(SLOT_B, manifest.size)) {
+            return Err(Rejection::BadSignature);
+        }
+
+        self.state.status = Status::Pending;
+        self.save(nvm)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.state.status == Status::Pending
+    }
+
+    /// Keeps the new firmware, true if it was being tested
+    pub fn confirm(&mut self, nvm: &mut Nvm) -> bool {
+        if self.state.status != Status::Testing {
+            return false;
+        }
+        self.state.status = Status::Idle;
+        self.state.attempts = 0;
+        self.state.installed = self.state.manifest.version;
+        self.save(nvm).is_ok()
+    }
+}
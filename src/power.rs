@@ -0,0 +1,150 @@
+use cortex_m::peripheral::SCB;
+use stm32l0xx_hal::{
+    pwr::{PowerMode, StopModeConfig, PWR},
+    rcc::Rcc,
+};
+
+/// Seconds the radio keeps listening for downlinks after booting or sending
+pub const RX_WINDOW_S: u16 = 30;
+
+/// Reasons for the MCU to stay out of STOP mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Busy {
+    Button = 1 << 0,
+    Display = 1 << 1,
+    WarmUp = 1 << 2,
+    Measuring = 1 << 3,
+    Buzzer = 1 << 4,
+    Radio = 1 << 5,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerState {
+    Stop = 0,
+    Awake = 1,
+    WarmUp = 2,
+    Measuring = 3,
+    Radio = 4,
+}
+
+pub const STATE_COUNT: usize = 5;
+
+/// Keeps track of what is keeping the device awake and how long it has been
+/// in each power state.
+pub struct Power {
+    busy: u8,
+    pub shutdown: bool,
+    pub budget_ua: [u32; STATE_COUNT],
+    pub seconds: [u32; STATE_COUNT],
+    awake: u32,
+}
+
+impl Power {
+    /// `budget_ua` is the current draw of each state, from the settings
+    pub fn new(budget_ua: [u32; STATE_COUNT]) -> Power {
+        Power {
+            busy: 0,
+            shutdown: false,
+            budget_ua: budget_ua,
+            seconds: [0; STATE_COUNT],
+            awake: 0,
+        }
+    }
+
+    pub fn set_busy(&mut self, reason: Busy, busy: bool) {
+        if busy {
+            self.busy |= reason as u8;
+        } else {
+            self.busy &= !(reason as u8);
+        }
+    }
+
+    /// True if anything needs the timers running
+    pub fn is_busy(&self) -> bool {
+        self.busy != 0
+    }
+
+    pub fn state(&self) -> PowerState {
+        let busy = |reason: Busy| self.busy & reason as u8 != 0;
+
+        if busy(Busy::Measuring) || busy(Busy::Buzzer) {
+            PowerState::Measuring
+        } else if busy(Busy::WarmUp) {
+            PowerState::WarmUp
+        } else if busy(Busy::Radio) {
+            PowerState::Radio
+        } else if self.is_busy() {
+            PowerState::Awake
+        } else {
+            PowerState::Stop
+        }
+    }
+
+    /// Accounts time spent in `state`
+    pub fn add_seconds(&mut self, state: PowerState, seconds: u32) {
+        let slot = &mut self.seconds[state as usize];
+        *slot = slot.saturating_add(seconds);
+    }
+
+    /// Accounts one second awake in the current state, called from the 1 s tick
+    pub fn awake_second(&mut self) {
+        self.add_seconds(self.state(), 1);
+        self.awake += 1;
+    }
+
+    /// Called from the RTC every `period` seconds, the time not spent awake
+    /// was spent in STOP mode
+    pub fn period_elapsed(&mut self, period: u32) {
+        let stopped = period.saturating_sub(self.awake);
+        self.add_seconds(PowerState::Stop, stopped);
+        self.awake = 0;
+    }
+
+    pub fn set_budget(&mut self, state: PowerState, ua: u32) {
+        self.budget_ua[state as usize] = ua;
+    }
+
+    /// Average current in µA over the accounted time
+    pub fn average_ua(&self) -> u32 {
+        let mut total_s: u64 = 0;
+        let mut charge: u64 = 0;
+
+        for (seconds, ua) in self.seconds.iter().zip(self.budget_ua.iter()) {
+            total_s += *seconds as u64;
+            charge += *seconds as u64 * *ua as u64;
+        }
+
+        if total_s == 0 {
+            return 0;
+        }
+        (charge / total_s) as u32
+    }
+
+    /// Used charge in mAh over the accounted time
+    pub fn used_mah(&self) -> u32 {
+        let mut charge: u64 = 0;
+        for (seconds, ua) in self.seconds.iter().zip(self.budget_ua.iter()) {
+            charge += *seconds as u64 * *ua as u64;
+        }
+        (charge / 3_600_000) as u32
+    }
+}
+
+/// Sleeps until the next interrupt. Enters STOP mode if `stop` is set, in
+/// which case only EXTI lines (button, radio DIO0) and the RTC can wake the
+/// MCU. Must be called with interrupts disabled so no event is missed
+/// between deciding to sleep and sleeping.
+pub fn sleep(pwr: &mut PWR, scb: &mut SCB, rcc: &mut Rcc, stop: bool) {
+    if stop {
+        pwr.stop_mode(
+            scb,
+            rcc,
+            StopModeConfig {
+                ultra_low_power: true,
+            },
+        )
+        .enter();
+    } else {
+        pwr.sleep_mode(scb).enter();
+    }
+}
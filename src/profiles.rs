@@ -0,0 +1,199 @@
+//! Who is blowing. Up to four profiles with their own limit are stored,
+//! the guest profile 0 uses the `limit` setting. A double press steps
+//! through them before measuring, and the profile id goes with the history
+//! record and the uplink.
+
+use crate::nvm::{Nvm, PROFILES};
+use crate::sober::{ELIMINATION_RATE, MAX_RATE, MIN_RATE};
+
+pub use protocol::MAX_PROFILES;
+
+/// The profile of anyone without one of their own
+pub const GUEST: u8 = 0;
+
+/// Anything measurable is over the limit in novice mode
+pub const NOVICE_LIMIT: u16 = 1;
+
+/// Highest limit of a profile, the same as the `limit` setting
+pub const MAX_LIMIT: u16 = 500;
+
+const MAGIC: u16 = 0x5047;
+// Initials, limit, elimination rate and flags
+const PROFILE_SIZE: usize = 3 + 2 + 1 + 1;
+// Magic, selected profile, profiles and checksum
+const STORED_SIZE: usize = stored_size(PROFILE_SIZE);
+
+/// Profiles stored before they had an elimination rate, initials, limit and
+/// flags. They are loaded with `ELIMINATION_RATE`.
+const OLD_MAGIC: u16 = 0x5046;
+const OLD_PROFILE_SIZE: usize = 3 + 2 + 1;
+
+const fn stored_size(profile_size: usize) -> usize {
+    2 + 1 + MAX_PROFILES * profile_size + 2
+}
+
+const FLAG_USED: u8 = 0x01;
+const FLAG_NOVICE: u8 = 0x02;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile {
+    /// Upper case ASCII, padded with spaces
+    pub initials: [u8; 3],
+    /// In 0.01 per mille, like the `limit` setting
+    pub limit: u16,
+    /// In 0.01 per mille an hour, see `sober`
+    pub rate: u16,
+    /// Zero tolerance for new drivers, `limit` is ignored
+    pub novice: bool,
+}
+
+impl Profile {
+    /// `None` unless the initials are one to three letters or digits, the
+    /// limit is 1 to `MAX_LIMIT` and the rate `MIN_RATE` to `MAX_RATE`
+    pub fn new(initials: &str, limit: u16, rate: u16, novice: bool) -> Option<Profile> {
+        if initials.is_empty()
+            || initials.len() > 3
+            || !initials.bytes().all(|c| c.is_ascii_alphanumeric())
+            || limit == 0
+            || limit > MAX_LIMIT
+            || rate < MIN_RATE
+            || rate > MAX_RATE
+        {
+            return None;
+        }
+
+        let mut bytes = [b' '; 3];
+        for (byte, c) in bytes.iter_mut().zip(initials.bytes()) {
+            *byte = c.to_ascii_uppercase();
+        }
+        Some(Profile {
+            initials: bytes,
+            limit: limit,
+            rate: rate,
+            novice: novice,
+        })
+    }
+
+    pub fn initials(&self) -> &str {
+        core::str::from_utf8(&self.initials).unwrap_or("?").trim_end()
+    }
+
+    /// The BAC at which a result is over the limit
+    pub fn limit(&self) -> u16 {
+        if self.novice {
+            NOVICE_LIMIT
+        } else {
+            self.limit
+        }
+    }
+}
+
+/// The stored profiles and which one is selected, ids 1 to `MAX_PROFILES`
+pub struct Profiles {
+    profiles: [Option<Profile>; MAX_PROFILES],
+    pub selected: u8,
+}
+
+impl Profiles {
+    /// Loads the stored profiles, only the guest if there are none
+    pub fn load(nvm: &Nvm) -> Profiles {
+        let mut profiles = Profiles {
+            profiles: [None; MAX_PROFILES],
+            selected: GUEST,
+        };
+
+        let mut bytes = [0; STORED_SIZE];
+        nvm.read(PROFILES, &mut bytes);
+
+        let profile_size = match u16::from_le_bytes([bytes[0], bytes[1]]) {
+            MAGIC => PROFILE_SIZE,
+            OLD_MAGIC => OLD_PROFILE_SIZE,
+            _ => return profiles,
+        };
+        let size = stored_size(profile_size);
+        let checksum = u16::from_le_bytes([bytes[size - 2], bytes[size - 1]]);
+        if checksum != crc16::State::<crc16::XMODEM>::calculate(&bytes[..size - 2]) {
+            return profiles;
+        }
+
+        for (i, profile) in profiles.profiles.iter_mut().enumerate() {
+            let entry = &bytes[3 + i * profile_size..3 + (i + 1) * profile_size];
+            let (rate, flags) = if profile_size == PROFILE_SIZE {
+                (entry[5] as u16, entry[6])
+            } else {
+                (ELIMINATION_RATE, entry[5])
+            };
+            if flags & FLAG_USED != 0 {
+                *profile = Some(Profile {
+                    initials: [entry[0], entry[1], entry[2]],
+                    limit: u16::from_le_bytes([entry[3], entry[4]]),
+                    rate: rate,
+                    novice: flags & FLAG_NOVICE != 0,
+                });
+            }
+        }
+        if profiles.get(bytes[2]).is_some() {
+            profiles.selected = bytes[2];
+        }
+        profiles
+    }
+
+    pub fn save(&self, nvm: &mut Nvm) -> Result<(), ()> {
+        let mut bytes = [0; STORED_SIZE];
+        bytes[..2].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[2] = self.selected;
+
+        for (i, profile) in self.profiles.iter().enumerate() {
+            if let Some(profile) = profile {
+                let entry = &mut bytes[3 + i * PROFILE_SIZE..3 + (i + 1) * PROFILE_SIZE];
+                entry[..3].copy_from_slice(&profile.initials);
+                entry[3..5].copy_from_slice(&profile.limit.to_le_bytes());
+                entry[5] = profile.rate as u8;
+                entry[6] = FLAG_USED | if profile.novice { FLAG_NOVICE } else { 0 };
+            }
+        }
+
+        let checksum = crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2]);
+        bytes[STORED_SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+        nvm.write(PROFILES, &bytes)
+    }
+
+    /// The stored profile with `id`, `None` for the guest
+    pub fn get(&self, id: u8) -> Option<Profile> {
+        match id as usize {
+            1..=MAX_PROFILES => self.profiles[id as usize - 1],
+            _ => None,
+        }
+    }
+
+    /// Stores or with `None` removes a profile, the guest can't be changed
+    pub fn set(&mut self, id: u8, profile: Option<Profile>) -> Result<(), ()> {
+        match id as usize {
+            1..=MAX_PROFILES => {
+                self.profiles[id as usize - 1] = profile;
+                if profile.is_none() && self.selected == id {
+                    self.selected = GUEST;
+                }
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Selects the next stored profile, after the last one the guest
+    pub fn select_next(&mut self) -> u8 {
+        let next = (self.selected + 1..=MAX_PROFILES as u8).find(|id| self.get(*id).is_some());
+        self.selected = next.unwrap_or(GUEST);
+        self.selected
+    }
+
+    /// Limit of the selected profile, `legal_limit` for the guest
+    pub fn limit(&self, legal_limit: u16) -> u16 {
+        self.get(self.selected).map_or(legal_limit, |profile| profile.limit())
+    }
+
+    /// Elimination rate of the selected profile
+    pub fn rate(&self) -> u16 {
+        self.get(self.selected).map_or(ELIMINATION_RATE, |profile| profile.rate)
+    }
+}
@@ -0,0 +1,103 @@
+use core::fmt::{self, Write};
+
+use protocol::telemetry::LinkStats;
+
+use crate::clock;
+
+/// Counters for the radio link since boot
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RadioStats {
+    /// Transmissions started, join requests included
+    pub tx: u32,
+    pub tx_done: u32,
+    pub rx: u32,
+    /// Packets that could not be parsed as a `Message`
+    pub rx_invalid: u32,
+    /// Only seen by LoRaWAN, LongFi drops these itself
+    pub crc_errors: u32,
+    /// Failed joins that were tried again
+    pub retries: u32,
+    /// Of the last received packet
+    pub rssi: i16,
+    pub snr: i8,
+    /// Time from starting to TX done, summed over `tx_done`
+    pub airtime_ms: u32,
+    pub last_latency_ms: u32,
+    // `clock::millis_in_hour` when the transmission started
+    tx_start: Option<u32>,
+}
+
+impl RadioStats {
+    pub fn new() -> RadioStats {
+        RadioStats::default()
+    }
+
+    /// Called when the radio starts sending
+    pub fn tx_started(&mut self) {
+        self.tx += 1;
+        self.tx_start = Some(clock::millis_in_hour());
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_start.is_some()
+    }
+
+    pub fn tx_finished(&mut self) {
+        self.tx_done += 1;
+        if let Some(start) = self.tx_start.take() {
+            self.last_latency_ms = clock::elapsed_ms(start);
+            self.airtime_ms += self.last_latency_ms;
+        }
+    }
+
+    pub fn received(&mut self, rssi: i16, snr: i8) {
+        self.rx += 1;
+        self.rssi = rssi;
+        self.snr = snr;
+    }
+
+    pub fn average_latency_ms(&self) -> u32 {
+        if self.tx_done == 0 {
+            0
+        } else {
+            self.airtime_ms / self.tx_done
+        }
+    }
+
+    /// Time on air in 0.1 % of the time since boot
+    pub fn duty_permille(&self, uptime_s: u32) -> u32 {
+        if uptime_s == 0 {
+            0
+        } else {
+            self.airtime_ms / uptime_s
+        }
+    }
+
+    pub fn link(&self, uptime_s: u32) -> LinkStats {
+        let saturate = |value: u32| value.min(u16::MAX as u32) as u16;
+        LinkStats {
+            rssi: self.rssi,
+            snr: self.snr,
+            tx: saturate(self.tx),
+            tx_done: saturate(self.tx_done),
+            latency_ms: saturate(self.average_latency_ms()),
+            crc_errors: saturate(self.crc_errors),
+            retries: saturate(self.retries),
+            duty_permille: saturate(self.duty_permille(uptime_s)),
+        }
+    }
+
+    pub fn report<W: Write>(&self, w: &mut W, uptime_s: u32) -> fmt::Result {
+        writeln!(w, "tx {} done {} retries {}", self.tx, self.tx_done, self.retries)?;
+        writeln!(w, "rx {} invalid {} crc {}", self.rx, self.rx_invalid, self.crc_errors)?;
+        writeln!(w, "rssi {} dBm snr {} dB", self.rssi, self.snr)?;
+        writeln!(
+            w,
+            "latency {} ms avg {} ms",
+            self.last_latency_ms,
+            self.average_latency_ms()
+        )?;
+        let duty = self.duty_permille(uptime_s);
+        writeln!(w, "airtime {} ms duty {}.{}%", self.airtime_ms, duty / 10, duty % 10)
+    }
+}
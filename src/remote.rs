@@ -0,0 +1,84 @@
+use heapless::{consts::*, Vec};
+use protocol::command::{self, Command, Key, Rejection, Reply, MAX_COMMAND};
+
+use crate::keys::Keys;
+use crate::nvm::{Nvm, COMMAND_COUNTER, LAST_TRIGGER};
+
+/// A command or reply frame as it goes over the radio
+pub type Frame = Vec<u8, U64>;
+
+/// Authenticates remote commands and signs the replies
+pub struct Remote {
+    key: Option<Key>,
+    /// Highest counter accepted so far, kept across resets
+    counter: u32,
+    /// Clock time of the last remote measurement, 0 if there was none, kept
+    /// across resets so rebooting doesn't allow another one early
+    last_trigger: u32,
+}
+
+impl Remote {
+    pub fn load(nvm: &Nvm, keys: Option<&Keys>) -> Remote {
+        Remote {
+            key: keys.map(|keys| command::command_key(&keys.app_key)),
+            counter: nvm.read_word(COMMAND_COUNTER),
+            last_trigger: nvm.read_word(LAST_TRIGGER),
+        }
+    }
+
+    /// A new app key starts a new counter as well. With the same one the
+    /// counter is kept, or frames sent before could be replayed.
+    pub fn set_keys(&mut self, nvm: &mut Nvm, keys: &Keys) -> Result<(), ()> {
+        let key = command::command_key(&keys.app_key);
+        if self.key == Some(key) {
+            return Ok(());
+        }
+        self.key = Some(key);
+        self.counter = 0;
+        nvm.write(COMMAND_COUNTER, &0u32.to_le_bytes())
+    }
+
+    /// Checks the MAC and the counter. The counter comes with the rejection
+    /// so it can be replied to, except for `Unauthorized` which must not be
+    /// answered.
+    pub fn accept(&mut self, nvm: &mut Nvm, frame: &[u8]) -> Result<(u32, Command), (u32, Rejection)> {
+        let key = self.key.ok_or((0, Rejection::Unauthorized))?;
+        // The EEPROM is erased to zero, so the first is above it
+        let (counter, command) = command::accept_command(&key, self.counter, frame)?;
+        self.counter = counter;
+        nvm.write(COMMAND_COUNTER, &counter.to_le_bytes())
+            .map_err(|_| (counter, Rejection::Storage))?;
+
+        Ok((counter, command))
+    }
+
+    /// Clock time of the last remote measurement
+    pub fn last_trigger(&self) -> Option<u32> {
+        if self.last_trigger == 0 {
+            None
+        } else {
+            Some(self.last_trigger)
+        }
+    }
+
+    pub fn set_last_trigger(&mut self, nvm: &mut Nvm, time: u32) -> Result<(), ()> {
+        self.last_trigger = time;
+        nvm.write(LAST_TRIGGER, &time.to_le_bytes())
+    }
+
+    /// The command key, update fragments are authenticated with it too
+    pub fn key(&self) -> Option<&Key> {
+        self.key.as_ref()
+    }
+
+    /// Signs a reply, `None` without keys
+    pub fn reply(&self, counter: u32, reply: &Reply) -> Option<Frame> {
+        let key = self.key?;
+        let mut buf = [0; MAX_COMMAND];
+        let encoded = command::encode_reply(&key, counter, reply, &mut buf).ok()?;
+
+        let mut frame = Frame::new();
+        frame.extend_from_slice(encoded).ok()?;
+        Some(frame)
+    }
+}
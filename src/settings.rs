@@ -0,0 +1,246 @@
+use crate::nvm::{Nvm, BANDS, SETTINGS};
+use crate::power::STATE_COUNT;
+use protocol::settings::{band_start_range, SETTINGS_INFO};
+use protocol::severity::{Band, BAND_COUNT, DEFAULT_BANDS, DEFAULT_STARTS, MAX_BEEPS, MAX_ICON, MAX_NAME};
+
+/// Unit of the current budget settings in µA, so the radio and heater fit a u16
+pub const BUDGET_UNIT_UA: u32 = 10;
+
+const VERSION: u8 = 2;
+const COUNT: usize = SETTINGS_INFO.len();
+// Version, count, values and checksum
+const STORED_SIZE: usize = 2 + 2 * COUNT + 2;
+
+/// Had an `alert` setting at `OLD_ALERT`, the lowest band that alerted,
+/// which is now the `alert` flag of each band
+const OLD_VERSION: u8 = 1;
+const OLD_ALERT: usize = 15;
+const OLD_STORED_SIZE: usize = STORED_SIZE + 2;
+
+const BANDS_MAGIC: u16 = 0x424E;
+// Name, icon, beeps and alert
+const BAND_SIZE: usize = MAX_NAME + MAX_ICON + 1 + 1;
+// Magic, bands and checksum
+const BANDS_STORED_SIZE: usize = 2 + BAND_COUNT * BAND_SIZE + 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SettingError {
+    Unknown,
+    /// Outside the allowed minimum and maximum
+    Range(u16, u16),
+}
+
+/// User configurable settings, stored in EEPROM. See
+/// `protocol::settings` for their names, defaults and limits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Settings {
+    pub dim_after_s: u16,
+    pub off_after_s: u16,
+    pub heater_off_after_s: u16,
+    pub rx_window_s: u16,
+    pub blow_s: u16,
+    /// Blood alcohol content in 0.01 per mille
+    pub legal_limit: u16,
+    pub report_interval_min: u16,
+    pub enabled: bool,
+    pub trigger_interval_min: u16,
+    pub cooldown_s: u16,
+    /// Where the severity bands start, see `protocol::severity`
+    pub band_starts: [u16; BAND_COUNT - 1],
+    /// What the severity bands are called and do, stored apart from the
+    /// values at `nvm::BANDS`
+    pub bands: [Band; BAND_COUNT],
+    /// Current draw of each `PowerState` in `BUDGET_UNIT_UA`
+    pub budget: [u16; STATE_COUNT],
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        let mut settings = Settings::default();
+        for info in SETTINGS_INFO.iter() {
+            settings.store(info.name, info.default).ok();
+        }
+        settings.bands = DEFAULT_BANDS;
+        settings
+    }
+
+    /// The current budget in µA for `Power`
+    pub fn budget_ua(&self) -> [u32; STATE_COUNT] {
+        let mut ua = [0; STATE_COUNT];
+        for (ua, budget) in ua.iter_mut().zip(self.budget.iter()) {
+            *ua = *budget as u32 * BUDGET_UNIT_UA;
+        }
+        ua
+    }
+
+    /// Loads the stored settings, or the defaults if there are none or they
+    /// are corrupt. Settings added since they were stored get the default.
+    pub fn load(nvm: &Nvm) -> Settings {
+        let mut bytes = [0; OLD_STORED_SIZE];
+        nvm.read(SETTINGS, &mut bytes);
+
+        let version = bytes[0];
+        let max = if version == OLD_VERSION { COUNT + 1 } else { COUNT };
+        let count = (bytes[1] as usize).min(max);
+        let size = 2 + 2 * count;
+        let checksum = u16::from_le_bytes([bytes[size], bytes[size + 1]]);
+        let valid = (version == VERSION || version == OLD_VERSION)
+            && bytes[1] as usize <= max
+            && checksum == crc16::State::<crc16::XMODEM>::calculate(&bytes[..size]);
+
+        let mut settings = Settings::new();
+        settings.bands = load_bands(nvm);
+        if valid {
+            for i in 0..count {
+                let value = u16::from_le_bytes([bytes[2 + 2 * i], bytes[3 + 2 * i]]);
+                let index = match version {
+                    OLD_VERSION if i == OLD_ALERT => {
+                        for (category, band) in settings.bands.iter_mut().enumerate() {
+                            band.alert = category as u16 >= value;
+                        }
+                        continue;
+                    }
+                    OLD_VERSION if i > OLD_ALERT => i - 1,
+                    _ => i,
+                };
+                settings.store(SETTINGS_INFO[index].name, value).ok();
+            }
+        }
+        // Stored one at a time they could have crossed
+        if !settings.band_starts.windows(2).all(|pair| pair[0] < pair[1]) {
+            settings.band_starts = DEFAULT_STARTS;
+        }
+        settings
+    }
+
+    /// Saves the values and the bands
+    pub fn save(&self, nvm: &mut Nvm) -> Result<(), ()> {
+        let mut bytes = [0; STORED_SIZE];
+        bytes[0] = VERSION;
+        bytes[1] = COUNT as u8;
+        for (i, info) in SETTINGS_INFO.iter().enumerate() {
+            let value = self.get(info.name).unwrap_or(0).to_le_bytes();
+            bytes[2 + 2 * i] = value[0];
+            bytes[3 + 2 * i] = value[1];
+        }
+
+        let checksum = crc16::State::<crc16::XMODEM>::calculate(&bytes[..STORED_SIZE - 2]);
+        bytes[STORED_SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+        nvm.write(SETTINGS, &bytes)?;
+        save_bands(nvm, &self.bands)
+    }
+
+    pub fn set_band(&mut self, index: u8, band: Band) -> Result<(), ()> {
+        *self.bands.get_mut(index as usize).ok_or(())? = band;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        match name {
+            "dim" => Some(self.dim_after_s),
+            "off" => Some(self.off_after_s),
+            "heater" => Some(self.heater_off_after_s),
+            "rx" => Some(self.rx_window_s),
+            "blow" => Some(self.blow_s),
+            "limit" => Some(self.legal_limit),
+            "report" => Some(self.report_interval_min),
+            "enabled" => Some(self.enabled as u16),
+            "trigger" => Some(self.trigger_interval_min),
+            "cooldown" => Some(self.cooldown_s),
+            "band1" => Some(self.band_starts[0]),
+            "band2" => Some(self.band_starts[1]),
+            "band3" => Some(self.band_starts[2]),
+            "band4" => Some(self.band_starts[3]),
+            "band5" => Some(self.band_starts[4]),
+            "istop" => Some(self.budget[0]),
+            "iawake" => Some(self.budget[1]),
+            "iwarmup" => Some(self.budget[2]),
+            "imeasure" => Some(self.budget[3]),
+            "iradio" => Some(self.budget[4]),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: u16) -> Result<(), SettingError> {
+        // A band has to start above the one below and below the one above
+        if let Some((min, max)) = band_start_range(name, &self.band_starts) {
+            if value < min || value > max {
+                return Err(SettingError::Range(min, max));
+            }
+        }
+        self.store(name, value)
+    }
+
+    /// Sets a value within its limits, but not checked against the others
+    fn store(&mut self, name: &str, value: u16) -> Result<(), SettingError> {
+        let info = protocol::settings::find(name).ok_or(SettingError::Unknown)?;
+        if value < info.min || value > info.max {
+            return Err(SettingError::Range(info.min, info.max));
+        }
+
+        match name {
+            "dim" => self.dim_after_s = value,
+            "off" => self.off_after_s = value,
+            "heater" => self.heater_off_after_s = value,
+            "rx" => self.rx_window_s = value,
+            "blow" => self.blow_s = value,
+            "limit" => self.legal_limit = value,
+            "report" => self.report_interval_min = value,
+            "enabled" => self.enabled = value != 0,
+            "trigger" => self.trigger_interval_min = value,
+            "cooldown" => self.cooldown_s = value,
+            "band1" => self.band_starts[0] = value,
+            "band2" => self.band_starts[1] = value,
+            "band3" => self.band_starts[2] = value,
+            "band4" => self.band_starts[3] = value,
+            "band5" => self.band_starts[4] = value,
+            "istop" => self.budget[0] = value,
+            "iawake" => self.budget[1] = value,
+            "iwarmup" => self.budget[2] = value,
+            "imeasure" => self.budget[3] = value,
+            "iradio" => self.budget[4] = value,
+            _ => return Err(SettingError::Unknown),
+        }
+        Ok(())
+    }
+}
+
+/// The stored bands, or the defaults if there are none or they are corrupt
+fn load_bands(nvm: &Nvm) -> [Band; BAND_COUNT] {
+    let mut bytes = [0; BANDS_STORED_SIZE];
+    nvm.read(BANDS, &mut bytes);
+
+    let magic = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let checksum = u16::from_le_bytes([bytes[BANDS_STORED_SIZE - 2], bytes[BANDS_STORED_SIZE - 1]]);
+    if magic != BANDS_MAGIC
+        || checksum != crc16::State::<crc16::XMODEM>::calculate(&bytes[..BANDS_STORED_SIZE - 2])
+    {
+        return DEFAULT_BANDS;
+    }
+
+    let mut bands = DEFAULT_BANDS;
+    for (i, band) in bands.iter_mut().enumerate() {
+        let entry = &bytes[2 + i * BAND_SIZE..2 + (i + 1) * BAND_SIZE];
+        band.name.copy_from_slice(&entry[..MAX_NAME]);
+        band.icon.copy_from_slice(&entry[MAX_NAME..MAX_NAME + MAX_ICON]);
+        band.beeps = entry[MAX_NAME + MAX_ICON].min(MAX_BEEPS);
+        band.alert = entry[MAX_NAME + MAX_ICON + 1] != 0;
+    }
+    bands
+}
+
+fn save_bands(nvm: &mut Nvm, bands: &[Band; BAND_COUNT]) -> Result<(), ()> {
+    let mut bytes = [0; BANDS_STORED_SIZE];
+    bytes[..2].copy_from_slice(&BANDS_MAGIC.to_le_bytes());
+    for (i, band) in bands.iter().enumerate() {
+        let entry = &mut bytes[2 + i * BAND_SIZE..2 + (i + 1) * BAND_SIZE];
+        entry[..MAX_NAME].copy_from_slice(&band.name);
+        entry[MAX_NAME..MAX_NAME + MAX_ICON].copy_from_slice(&band.icon);
+        entry[MAX_NAME + MAX_ICON] = band.beeps;
+        entry[MAX_NAME + MAX_ICON + 1] = band.alert as u8;
+    }
+
+    let checksum = crc16::State::<crc16::XMODEM>::calculate(&bytes[..BANDS_STORED_SIZE - 2]);
+    bytes[BANDS_STORED_SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+    nvm.write(BANDS, &bytes)
+}
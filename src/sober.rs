@@ -0,0 +1,71 @@
+//! Widmark estimate of when the BAC falls below a limit. Once absorbed,
+//! alcohol is eliminated at a roughly constant rate, 0.10 to 0.20 per mille
+//! an hour for most adults. Food, drinking still going on and the sensor's
+//! own error all move the real time, so it is only ever shown as an
+//! estimate.
+
+use core::fmt;
+
+use crate::calibration::Calibration;
+use crate::diagnostics::{push_line, Lines};
+use crate::history::History;
+use crate::nvm::Nvm;
+
+/// Elimination rate in 0.01 per mille an hour, for the guest and new profiles
+pub const ELIMINATION_RATE: u16 = 15;
+pub const MIN_RATE: u16 = 5;
+pub const MAX_RATE: u16 = 40;
+
+/// Shown with every estimate
+pub const DISCLAIMER: &str = "Estimate only";
+
+/// Minutes from a reading of `bac` until it is below `limit`, 0 if it
+/// already is. `rate` is the elimination rate in 0.01 per mille an hour.
+pub fn minutes_to_limit(bac: u16, limit: u16, rate: u16) -> u32 {
+    if bac < limit || rate == 0 {
+        return 0;
+    }
+    ((bac - limit + 1) as u32 * 60 + rate as u32 - 1) / rate as u32
+}
+
+/// Minutes left of the estimate from the newest reading of `profile`, `None`
+/// without a reading of it or without calibration
+pub fn from_history(
+    history: &History,
+    nvm: &Nvm,
+    calibration: &Calibration,
+    profile: u8,
+    limit: u16,
+    rate: u16,
+    now: u32,
+) -> Option<u32> {
+    let record = (0..history.len())
+        .filter_map(|i| history.get(nvm, i))
+        .find(|record| record.profile == profile)?;
+    let bac = calibration.bac(record.raw, record.baseline)?;
+
+    let elapsed_min = now.saturating_sub(record.time) / 60;
+    Some(minutes_to_limit(bac, limit, rate).saturating_sub(elapsed_min))
+}
+
+/// The screen with an estimate, under `title` and the BAC if there is one
+pub fn lines(title: &str, bac: Option<u16>, minutes: u32) -> Lines {
+    let mut lines = Lines::new();
+    push_line(&mut lines, format_args!("{}", title));
+    if let Some(bac) = bac {
+        push_line(&mut lines, format_args!("BAC {}.{:02}", bac / 100, bac % 100));
+    }
+    push_line(&mut lines, format_args!("Below limit in"));
+    push_line(&mut lines, format_args!("{}", Duration(minutes)));
+    push_line(&mut lines, format_args!("{}", DISCLAIMER));
+    lines
+}
+
+/// Formats minutes as "1h 05m"
+pub struct Duration(pub u32);
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}h {:02}m", self.0 / 60, self.0 % 60)
+    }
+}
@@ -0,0 +1,42 @@
+use embedded_hal::adc::{Channel, OneShot};
+use stm32l0xx_hal::{adc::Adc, pac};
+
+/// Factory calibration of the sensor at 30 and 130 °C, measured with VDDA = 3.0 V
+const TS_CAL1: *const u16 = 0x1FF8_007A as *const u16;
+const TS_CAL2: *const u16 = 0x1FF8_007E as *const u16;
+const TS_CAL_MV: i32 = 3000;
+
+/// Internal temperature sensor on ADC channel 18
+pub struct TempSensor;
+
+impl Channel<Adc> for TempSensor {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        18
+    }
+}
+
+impl TempSensor {
+    pub fn new() -> TempSensor {
+        unsafe {
+            (*pac::ADC::ptr()).ccr.modify(|_, w| w.tsen().set_bit());
+        }
+        TempSensor
+    }
+
+    /// Die temperature in °C, `vdd_mv` is the supply measured by `Battery`
+    pub fn read(&mut self, adc: &mut Adc, vdd_mv: u16) -> i8 {
+        let raw: u16 = adc.read(self).unwrap();
+
+        // Scaled to what it would read at the calibration voltage
+        let raw = raw as i32 * vdd_mv as i32 / TS_CAL_MV;
+        let cal1 = unsafe { core::ptr::read_volatile(TS_CAL1) } as i32;
+        let cal2 = unsafe { core::ptr::read_volatile(TS_CAL2) } as i32;
+        if cal2 == cal1 {
+            return 0;
+        }
+
+        ((raw - cal1) * (130 - 30) / (cal2 - cal1) + 30) as i8
+    }
+}
@@ -0,0 +1,175 @@
+use stm32l0xx_hal::pac;
+
+/// Seconds between supervisor checks, the RTC wakes the MCU this often
+pub const CHECK_PERIOD_S: u32 = 4;
+
+// LSI at ~37 kHz divided by 128 with the maximum reload gives ~14 s
+const IWDG_PRESCALER_128: u32 = 0b101;
+const IWDG_RELOAD: u32 = 0xFFF;
+
+const KEY_START: u32 = 0xCCCC;
+const KEY_FEED: u32 = 0xAAAA;
+const KEY_UNLOCK: u32 = 0x5555;
+
+/// Checks in a row a task may miss before the watchdog is starved
+const MAX_STRIKES: u8 = 2;
+
+/// Independent watchdog, it can not be stopped once started
+pub struct Watchdog {
+    iwdg: pac::IWDG,
+}
+
+impl Watchdog {
+    pub fn start(iwdg: pac::IWDG) -> Watchdog {
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_START) });
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_UNLOCK) });
+        iwdg.pr.write(|w| unsafe { w.bits(IWDG_PRESCALER_128) });
+        iwdg.rlr.write(|w| unsafe { w.bits(IWDG_RELOAD) });
+        while iwdg.sr.read().bits() != 0 {}
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_FEED) });
+
+        Watchdog { iwdg: iwdg }
+    }
+
+    pub fn feed(&mut self) {
+        self.iwdg.kr.write(|w| unsafe { w.bits(KEY_FEED) });
+    }
+}
+
+/// Tasks that have to show progress for the watchdog to be fed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Task {
+    /// The idle loop, runs whenever no task is stuck
+    Idle = 1 << 0,
+    /// The one second tick, only expected while awake
+    Housekeeping = 1 << 1,
+    /// Radio TX done, only expected while sending
+    Radio = 1 << 2,
+}
+
+pub struct Supervisor {
+    alive: u8,
+    radio: bool,
+    slept: bool,
+    strikes: [u8; 3],
+    /// Tasks that missed the last check
+    pub missed: u8,
+    /// Set once the starvation is logged, the watchdog resets us soon after
+    pub reported: bool,
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            alive: 0,
+            radio: false,
+            slept: false,
+            strikes: [0; 3],
+            missed: 0,
+            reported: false,
+        }
+    }
+
+    pub fn check_in(&mut self, task: Task) {
+        self.alive |= task as u8;
+    }
+
+    /// Sending started or finished, TX done is expected meanwhile
+    pub fn expect_radio(&mut self, expected: bool) {
+        self.radio = expected;
+    }
+
+    /// The MCU is entering STOP, the one second tick will not run
+    pub fn sleeping(&mut self) {
+        self.slept = true;
+    }
+
+    /// Called every `CHECK_PERIOD_S`, returns false if the watchdog should
+    /// no longer be fed.
+    pub fn check(&mut self) -> bool {
+        let mut expected = Task::Idle as u8;
+        if !self.slept {
+            expected |= Task::Housekeeping as u8;
+        }
+        if self.radio {
+            expected |= Task::Radio as u8;
+        }
+
+        let mut healthy = true;
+        self.missed = expected & !self.alive;
+
+        for (i, strikes) in self.strikes.iter_mut().enumerate() {
+            if self.missed & (1 << i) != 0 {
+                *strikes += 1;
+                if *strikes >= MAX_STRIKES {
+                    healthy = false;
+                }
+            } else {
+                *strikes = 0;
+            }
+        }
+
+        self.alive = 0;
+        self.slept = false;
+        healthy
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetCause {
+    Unknown = 0,
+    /// Power-on or brown-out, the hardware does not tell them apart
+    BrownOut = 1,
+    Pin = 2,
+    Software = 3,
+    Watchdog = 4,
+    WindowWatchdog = 5,
+    LowPower = 6,
+    OptionBytes = 7,
+    Firewall = 8,
+}
+
+impl ResetCause {
+    /// Reads and clears the reset flags, must be called before `RCC.freeze`
+    pub fn read(rcc: &pac::RCC) -> ResetCause {
+        let csr = rcc.csr.read();
+
+        // The pin flag is set by every reset so it is checked last
+        let cause = if csr.lpwrrstf().bit_is_set() {
+            ResetCause::LowPower
+        } else if csr.iwdgrstf().bit_is_set() {
+            ResetCause::Watchdog
+        } else if csr.wwdgrstf().bit_is_set() {
+            ResetCause::WindowWatchdog
+        } else if csr.sftrstf().bit_is_set() {
+            ResetCause::Software
+        } else if csr.porrstf().bit_is_set() {
+            ResetCause::BrownOut
+        } else if csr.oblrstf().bit_is_set() {
+            ResetCause::OptionBytes
+        } else if csr.fwrstf().bit_is_set() {
+            ResetCause::Firewall
+        } else if csr.pinrstf().bit_is_set() {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        };
+
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+        cause
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResetCause::Unknown => "Unknown",
+            ResetCause::BrownOut => "Power/BOR",
+            ResetCause::Pin => "Pin",
+            ResetCause::Software => "Software",
+            ResetCause::Watchdog => "Watchdog",
+            ResetCause::WindowWatchdog => "WWDG",
+            ResetCause::LowPower => "Low power",
+            ResetCause::OptionBytes => "Option bytes",
+            ResetCause::Firewall => "Firewall",
+        }
+    }
+}